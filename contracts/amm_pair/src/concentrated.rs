@@ -0,0 +1,587 @@
+use shadeswap_shared::{
+    fadroma::{
+        scrt::{StdError, StdResult, Storage, Uint128},
+        scrt_uint256::Uint256,
+    },
+};
+use crate::state::{ConcentratedPool, Position, TickInfo};
+use crate::state::amm_pair_storage::{load_tick, store_tick, load_tick_word, store_tick_word, load_tick_summary,
+    store_tick_summary, store_concentrated_pool};
+
+/// Scale of the square root prices and of the fee growth per unit of liquidity.
+pub const PRICE_PRECISION: u128 = 1_000_000_000_000_000_000;
+/// Ticks are spaced 1 basis point apart, `price = 1.0001^tick`, so the price stays within 2^-64 and 2^64.
+pub const MIN_TICK: i32 = -443_600;
+pub const MAX_TICK: i32 = 443_600;
+/// `sqrt(1.0001)` scaled by `PRICE_PRECISION`.
+const SQRT_TICK_BASE: u128 = 1_000_049_998_750_062_496;
+/// Ticks per word of the tick bitmap, and words per word of its summary.
+const WORD_BITS: u32 = 128;
+
+fn precision() -> Uint256 {
+    Uint256::from(PRICE_PRECISION)
+}
+
+fn ceil_div(nominator: Uint256, denominator: Uint256) -> StdResult<Uint256> {
+    if nominator == Uint256::zero() {
+        return Ok(Uint256::zero());
+    }
+    Ok((((nominator - Uint256::from(1u8))? / denominator)? + Uint256::from(1u8))?)
+}
+
+pub fn assert_ticks(lower_tick: i32, upper_tick: i32, tick_spacing: u16) -> StdResult<()> {
+    if lower_tick >= upper_tick {
+        return Err(StdError::generic_err("lower_tick has to be below upper_tick."));
+    }
+    if lower_tick < MIN_TICK || upper_tick > MAX_TICK {
+        return Err(StdError::generic_err(format!(
+            "Ticks have to be between {} and {}.",
+            MIN_TICK, MAX_TICK
+        )));
+    }
+    let spacing = tick_spacing as i32;
+    if lower_tick % spacing != 0 || upper_tick % spacing != 0 {
+        return Err(StdError::generic_err(format!(
+            "Ticks have to be multiples of the tick spacing {}.",
+            tick_spacing
+        )));
+    }
+    Ok(())
+}
+
+/// `sqrt(1.0001^tick)` scaled by `PRICE_PRECISION`.
+pub fn sqrt_price_at_tick(tick: i32) -> StdResult<Uint256> {
+    if tick < MIN_TICK || tick > MAX_TICK {
+        return Err(StdError::generic_err(format!(
+            "Ticks have to be between {} and {}.",
+            MIN_TICK, MAX_TICK
+        )));
+    }
+
+    let mut exponent = tick.abs() as u32;
+    let mut base = Uint256::from(SQRT_TICK_BASE);
+    let mut result = precision();
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = ((result * base)? / precision())?;
+        }
+        base = ((base * base)? / precision())?;
+        exponent >>= 1;
+    }
+
+    if tick < 0 {
+        return (precision() * precision())? / result;
+    }
+    Ok(result)
+}
+
+/// Largest tick whose square root price is not above `sqrt_price`.
+pub fn tick_at_sqrt_price(sqrt_price: Uint256) -> StdResult<i32> {
+    let mut low = MIN_TICK;
+    let mut high = MAX_TICK;
+    while low < high {
+        // round up so the range always shrinks
+        let middle = low + (high - low + 1) / 2;
+        if sqrt_price_at_tick(middle)? <= sqrt_price {
+            low = middle;
+        } else {
+            high = middle - 1;
+        }
+    }
+    Ok(low)
+}
+
+/// Amount of token 0 in `liquidity` between the two prices, `L * (b - a) / (a * b)`.
+pub fn amount_0_delta(
+    sqrt_price_a: Uint256,
+    sqrt_price_b: Uint256,
+    liquidity: Uint256,
+    round_up: bool,
+) -> StdResult<Uint256> {
+    let (lower, upper) = if sqrt_price_a < sqrt_price_b {
+        (sqrt_price_a, sqrt_price_b)
+    } else {
+        (sqrt_price_b, sqrt_price_a)
+    };
+    let nominator = ((liquidity * precision())? * (upper - lower)?)?;
+    if round_up {
+        ceil_div(ceil_div(nominator, upper)?, lower)
+    } else {
+        (nominator / upper)? / lower
+    }
+}
+
+/// Amount of token 1 in `liquidity` between the two prices, `L * (b - a)`.
+pub fn amount_1_delta(
+    sqrt_price_a: Uint256,
+    sqrt_price_b: Uint256,
+    liquidity: Uint256,
+    round_up: bool,
+) -> StdResult<Uint256> {
+    let (lower, upper) = if sqrt_price_a < sqrt_price_b {
+        (sqrt_price_a, sqrt_price_b)
+    } else {
+        (sqrt_price_b, sqrt_price_a)
+    };
+    let nominator = (liquidity * (upper - lower)?)?;
+    if round_up {
+        ceil_div(nominator, precision())
+    } else {
+        nominator / precision()
+    }
+}
+
+/// Largest liquidity the amounts can provide in the range at the current price.
+pub fn liquidity_for_amounts(
+    sqrt_price: Uint256,
+    sqrt_price_lower: Uint256,
+    sqrt_price_upper: Uint256,
+    amount_0: Uint256,
+    amount_1: Uint256,
+) -> StdResult<Uint256> {
+    let liquidity_0 = |lower: Uint256| -> StdResult<Uint256> {
+        ((((amount_0 * lower)? / precision())? * sqrt_price_upper)?) / (sqrt_price_upper - lower)?
+    };
+    let liquidity_1 = |upper: Uint256| -> StdResult<Uint256> {
+        (amount_1 * precision())? / (upper - sqrt_price_lower)?
+    };
+
+    if sqrt_price <= sqrt_price_lower {
+        liquidity_0(sqrt_price_lower)
+    } else if sqrt_price >= sqrt_price_upper {
+        liquidity_1(sqrt_price_upper)
+    } else {
+        Ok(std::cmp::min(liquidity_0(sqrt_price)?, liquidity_1(sqrt_price)?))
+    }
+}
+
+/// Token amounts held by `liquidity` in the range at the current price.
+pub fn amounts_for_liquidity(
+    sqrt_price: Uint256,
+    sqrt_price_lower: Uint256,
+    sqrt_price_upper: Uint256,
+    liquidity: Uint256,
+    round_up: bool,
+) -> StdResult<[Uint256; 2]> {
+    if sqrt_price <= sqrt_price_lower {
+        Ok([
+            amount_0_delta(sqrt_price_lower, sqrt_price_upper, liquidity, round_up)?,
+            Uint256::zero(),
+        ])
+    } else if sqrt_price >= sqrt_price_upper {
+        Ok([
+            Uint256::zero(),
+            amount_1_delta(sqrt_price_lower, sqrt_price_upper, liquidity, round_up)?,
+        ])
+    } else {
+        Ok([
+            amount_0_delta(sqrt_price, sqrt_price_upper, liquidity, round_up)?,
+            amount_1_delta(sqrt_price_lower, sqrt_price, liquidity, round_up)?,
+        ])
+    }
+}
+
+/// Token amounts `liquidity` takes from `deposit`, rounded up in favour of the pool.
+/// Fails instead of giving the position liquidity the deposit doesn't fully pay for.
+pub fn deposit_amounts_for_liquidity(
+    sqrt_price: Uint256,
+    sqrt_price_lower: Uint256,
+    sqrt_price_upper: Uint256,
+    liquidity: Uint128,
+    deposit: [Uint128; 2],
+) -> StdResult<[Uint128; 2]> {
+    let required = amounts_for_liquidity(
+        sqrt_price,
+        sqrt_price_lower,
+        sqrt_price_upper,
+        Uint256::from(liquidity),
+        true,
+    )?;
+    let mut amounts = [Uint128::zero(); 2];
+    for i in 0..2 {
+        amounts[i] = required[i].clamp_u128()?.into();
+        if amounts[i] > deposit[i] {
+            return Err(StdError::generic_err(format!(
+                "The deposit doesn't cover the liquidity. Actual: {}, Expected: {}",
+                deposit[i], amounts[i]
+            )));
+        }
+    }
+    Ok(amounts)
+}
+
+/// Price after `amount` goes into the pool, rounded in favour of the pool.
+fn next_sqrt_price_from_input(
+    sqrt_price: Uint256,
+    liquidity: Uint256,
+    amount: Uint256,
+    zero_for_one: bool,
+) -> StdResult<Uint256> {
+    if zero_for_one {
+        // L * P / (L + amount * P)
+        let scaled_liquidity = (liquidity * precision())?;
+        ceil_div(
+            (scaled_liquidity * sqrt_price)?,
+            (scaled_liquidity + (amount * sqrt_price)?)?,
+        )
+    } else {
+        // P + amount / L
+        sqrt_price + ((amount * precision())? / liquidity)?
+    }
+}
+
+/// Fee growth inside a range, from the fee growth outside of its two ticks.
+pub fn fee_growth_inside(
+    pool: &ConcentratedPool,
+    lower_tick: i32,
+    lower: &TickInfo,
+    upper_tick: i32,
+    upper: &TickInfo,
+) -> [u128; 2] {
+    let global = [pool.fee_growth_global_0.u128(), pool.fee_growth_global_1.u128()];
+    let lower_outside = [lower.fee_growth_outside_0.u128(), lower.fee_growth_outside_1.u128()];
+    let upper_outside = [upper.fee_growth_outside_0.u128(), upper.fee_growth_outside_1.u128()];
+
+    let mut inside = [0u128; 2];
+    for i in 0..2 {
+        let below = if pool.tick >= lower_tick {
+            lower_outside[i]
+        } else {
+            global[i].wrapping_sub(lower_outside[i])
+        };
+        let above = if pool.tick < upper_tick {
+            upper_outside[i]
+        } else {
+            global[i].wrapping_sub(upper_outside[i])
+        };
+        inside[i] = global[i].wrapping_sub(below).wrapping_sub(above);
+    }
+    inside
+}
+
+/// Moves the fees earned by the position since it was last touched into `tokens_owed_*`.
+pub fn settle_position_fees(
+    pool: &ConcentratedPool,
+    position: &mut Position,
+    lower_tick: i32,
+    lower: &TickInfo,
+    upper_tick: i32,
+    upper: &TickInfo,
+) -> StdResult<()> {
+    let inside = fee_growth_inside(pool, lower_tick, lower, upper_tick, upper);
+    let last = [
+        position.fee_growth_inside_0_last.u128(),
+        position.fee_growth_inside_1_last.u128(),
+    ];
+    let mut owed = [Uint128::zero(); 2];
+    for i in 0..2 {
+        let growth = Uint256::from(inside[i].wrapping_sub(last[i]));
+        owed[i] = ((Uint256::from(position.liquidity) * growth)? / precision())?
+            .clamp_u128()?
+            .into();
+    }
+    position.tokens_owed_0 = position.tokens_owed_0 + owed[0];
+    position.tokens_owed_1 = position.tokens_owed_1 + owed[1];
+    position.fee_growth_inside_0_last = Uint128(inside[0]);
+    position.fee_growth_inside_1_last = Uint128(inside[1]);
+    Ok(())
+}
+
+/// Changes the liquidity of a position by `liquidity_delta` and settles the fees it
+/// earned since it was last touched into `tokens_owed_*`.
+pub fn update_position(
+    storage: &mut impl Storage,
+    pool: &mut ConcentratedPool,
+    position: &mut Position,
+    lower_tick: i32,
+    upper_tick: i32,
+    liquidity_delta: Uint128,
+    add: bool,
+) -> StdResult<()> {
+    let mut lower = load_tick(storage, lower_tick)?;
+    let mut upper = load_tick(storage, upper_tick)?;
+    if add && !liquidity_delta.is_zero() {
+        initialize_tick(pool, lower_tick, &mut lower);
+        initialize_tick(pool, upper_tick, &mut upper);
+    }
+
+    settle_position_fees(pool, position, lower_tick, &lower, upper_tick, &upper)?;
+
+    if liquidity_delta.is_zero() {
+        return Ok(());
+    }
+
+    let in_range = pool.tick >= lower_tick && pool.tick < upper_tick;
+    if add {
+        position.liquidity = position.liquidity + liquidity_delta;
+        lower.liquidity_gross = lower.liquidity_gross + liquidity_delta;
+        lower.liquidity_lower = lower.liquidity_lower + liquidity_delta;
+        upper.liquidity_gross = upper.liquidity_gross + liquidity_delta;
+        upper.liquidity_upper = upper.liquidity_upper + liquidity_delta;
+        if in_range {
+            pool.liquidity = pool.liquidity + liquidity_delta;
+        }
+    } else {
+        position.liquidity = (position.liquidity - liquidity_delta)?;
+        lower.liquidity_gross = (lower.liquidity_gross - liquidity_delta)?;
+        lower.liquidity_lower = (lower.liquidity_lower - liquidity_delta)?;
+        upper.liquidity_gross = (upper.liquidity_gross - liquidity_delta)?;
+        upper.liquidity_upper = (upper.liquidity_upper - liquidity_delta)?;
+        if in_range {
+            pool.liquidity = (pool.liquidity - liquidity_delta)?;
+        }
+    }
+
+    store_or_clear_tick(storage, lower_tick, &lower)?;
+    store_or_clear_tick(storage, upper_tick, &upper)
+}
+
+fn initialize_tick(pool: &ConcentratedPool, tick: i32, info: &mut TickInfo) {
+    if !info.liquidity_gross.is_zero() {
+        return;
+    }
+    // By convention all growth so far happened below the current tick.
+    if pool.tick >= tick {
+        info.fee_growth_outside_0 = pool.fee_growth_global_0;
+        info.fee_growth_outside_1 = pool.fee_growth_global_1;
+    }
+}
+
+fn store_or_clear_tick(storage: &mut impl Storage, tick: i32, info: &TickInfo) -> StdResult<()> {
+    let initialized = !info.liquidity_gross.is_zero();
+    set_tick_initialized(storage, tick, initialized)?;
+    if initialized {
+        store_tick(storage, tick, info)
+    } else {
+        store_tick(storage, tick, &TickInfo::default())
+    }
+}
+
+/// Word of the tick bitmap and bit within it of `tick`.
+fn tick_position(tick: i32) -> (u32, u32) {
+    let position = (tick - MIN_TICK) as u32;
+    (position / WORD_BITS, position % WORD_BITS)
+}
+
+fn tick_at_position(word: u32, bit: u32) -> i32 {
+    (word * WORD_BITS + bit) as i32 + MIN_TICK
+}
+
+/// Highest set bit of `bits` which is not above `bit`.
+fn highest_bit_up_to(bits: u128, bit: u32) -> Option<u32> {
+    let masked = if bit + 1 >= WORD_BITS { bits } else { bits & ((1u128 << (bit + 1)) - 1) };
+    if masked == 0 {
+        None
+    } else {
+        Some(WORD_BITS - 1 - masked.leading_zeros())
+    }
+}
+
+/// Lowest set bit of `bits` which is not below `bit`.
+fn lowest_bit_from(bits: u128, bit: u32) -> Option<u32> {
+    let masked = bits & (u128::MAX << bit);
+    if masked == 0 {
+        None
+    } else {
+        Some(masked.trailing_zeros())
+    }
+}
+
+fn set_tick_initialized(storage: &mut impl Storage, tick: i32, initialized: bool) -> StdResult<()> {
+    let (word, bit) = tick_position(tick);
+    let bits = load_tick_word(storage, word)?;
+    let new_bits = if initialized { bits | (1u128 << bit) } else { bits & !(1u128 << bit) };
+    if new_bits == bits {
+        return Ok(());
+    }
+    store_tick_word(storage, word, new_bits)?;
+
+    // the summary only changes when the word becomes empty or stops being empty
+    if (bits == 0) != (new_bits == 0) {
+        let mut summary = load_tick_summary(storage)?;
+        let (index, summary_bit) = ((word / WORD_BITS) as usize, word % WORD_BITS);
+        if summary.len() <= index {
+            summary.resize(index + 1, Uint128::zero());
+        }
+        let summary_bits = summary[index].u128();
+        summary[index] = Uint128(if new_bits == 0 {
+            summary_bits & !(1u128 << summary_bit)
+        } else {
+            summary_bits | (1u128 << summary_bit)
+        });
+        store_tick_summary(storage, &summary)?;
+    }
+    Ok(())
+}
+
+/// Closest initialized tick at or below `tick`.
+pub fn initialized_tick_at_or_below(storage: &impl Storage, tick: i32) -> StdResult<Option<i32>> {
+    if tick < MIN_TICK {
+        return Ok(None);
+    }
+    let (word, bit) = tick_position(tick.min(MAX_TICK));
+    if let Some(bit) = highest_bit_up_to(load_tick_word(storage, word)?, bit) {
+        return Ok(Some(tick_at_position(word, bit)));
+    }
+    if word == 0 {
+        return Ok(None);
+    }
+
+    let summary = load_tick_summary(storage)?;
+    let (mut index, mut from) = ((word - 1) / WORD_BITS, (word - 1) % WORD_BITS);
+    loop {
+        let summary_bits = summary.get(index as usize).map_or(0, |bits| bits.u128());
+        if let Some(summary_bit) = highest_bit_up_to(summary_bits, from) {
+            let word = index * WORD_BITS + summary_bit;
+            let bits = load_tick_word(storage, word)?;
+            return Ok(Some(tick_at_position(word, WORD_BITS - 1 - bits.leading_zeros())));
+        }
+        if index == 0 {
+            return Ok(None);
+        }
+        index -= 1;
+        from = WORD_BITS - 1;
+    }
+}
+
+/// Closest initialized tick above `tick`.
+pub fn initialized_tick_above(storage: &impl Storage, tick: i32) -> StdResult<Option<i32>> {
+    if tick >= MAX_TICK {
+        return Ok(None);
+    }
+    let (word, bit) = tick_position((tick + 1).max(MIN_TICK));
+    if let Some(bit) = lowest_bit_from(load_tick_word(storage, word)?, bit) {
+        return Ok(Some(tick_at_position(word, bit)));
+    }
+
+    let summary = load_tick_summary(storage)?;
+    let (mut index, mut from) = ((word + 1) / WORD_BITS, (word + 1) % WORD_BITS);
+    while (index as usize) < summary.len() {
+        if let Some(summary_bit) = lowest_bit_from(summary[index as usize].u128(), from) {
+            let word = index * WORD_BITS + summary_bit;
+            let bits = load_tick_word(storage, word)?;
+            return Ok(Some(tick_at_position(word, bits.trailing_zeros())));
+        }
+        index += 1;
+        from = 0;
+    }
+    Ok(None)
+}
+
+pub fn store_concentrated_swap(storage: &mut impl Storage, swap: &ConcentratedSwap) -> StdResult<()> {
+    for (tick, info) in swap.crossed_ticks.iter() {
+        store_tick(storage, *tick, info)?;
+    }
+    store_concentrated_pool(storage, &swap.pool)
+}
+
+/// Result of a swap through the initialized ticks, nothing is stored until
+/// `store_concentrated_swap` is called with it.
+pub struct ConcentratedSwap {
+    pub amount_out: Uint256,
+    pub lp_fee_amount: Uint256,
+    pub pool: ConcentratedPool,
+    pub crossed_ticks: Vec<(i32, TickInfo)>,
+}
+
+/// Swaps `amount` through the pool, moving from one initialized tick to the next. The
+/// LP fee, `fee_nom / fee_denom`, is taken from the input of every step and credited to
+/// the liquidity in range.
+pub fn compute_swap(
+    storage: &impl Storage,
+    pool: &ConcentratedPool,
+    amount: Uint256,
+    zero_for_one: bool,
+    fee_nom: Uint256,
+    fee_denom: Uint256,
+) -> StdResult<ConcentratedSwap> {
+    let mut pool = pool.clone();
+    let mut sqrt_price = Uint256::from(pool.sqrt_price);
+    let mut liquidity = Uint256::from(pool.liquidity);
+    let mut fee_growth_global = [pool.fee_growth_global_0.u128(), pool.fee_growth_global_1.u128()];
+    let mut remaining = amount;
+    let mut amount_out = Uint256::zero();
+    let mut lp_fee_amount = Uint256::zero();
+    let mut crossed_ticks = vec![];
+
+    while remaining > Uint256::zero() {
+        let next_tick = if zero_for_one {
+            initialized_tick_at_or_below(storage, pool.tick)?
+        } else {
+            initialized_tick_above(storage, pool.tick)?
+        }
+        .ok_or_else(|| StdError::generic_err("Not enough liquidity in the pool for this swap."))?;
+        let target_price = sqrt_price_at_tick(next_tick)?;
+
+        if liquidity > Uint256::zero() {
+            let remaining_less_fee = ((remaining * (fee_denom - fee_nom)?)? / fee_denom)?;
+            let max_in = if zero_for_one {
+                amount_0_delta(target_price, sqrt_price, liquidity, true)?
+            } else {
+                amount_1_delta(sqrt_price, target_price, liquidity, true)?
+            };
+
+            let (next_price, amount_in, fee_amount) = if remaining_less_fee >= max_in {
+                let fee_amount = ceil_div((max_in * fee_nom)?, (fee_denom - fee_nom)?)?;
+                (target_price, max_in, fee_amount)
+            } else {
+                let next_price = next_sqrt_price_from_input(sqrt_price, liquidity, remaining_less_fee, zero_for_one)?;
+                let amount_in = std::cmp::min(
+                    remaining,
+                    if zero_for_one {
+                        amount_0_delta(next_price, sqrt_price, liquidity, true)?
+                    } else {
+                        amount_1_delta(sqrt_price, next_price, liquidity, true)?
+                    },
+                );
+                // the whole rest goes in, what isn't needed for the price move is fee
+                (next_price, amount_in, (remaining - amount_in)?)
+            };
+
+            let step_out = if zero_for_one {
+                amount_1_delta(next_price, sqrt_price, liquidity, false)?
+            } else {
+                amount_0_delta(sqrt_price, next_price, liquidity, false)?
+            };
+
+            remaining = ((remaining - amount_in)? - fee_amount).unwrap_or(Uint256::zero());
+            amount_out = (amount_out + step_out)?;
+            lp_fee_amount = (lp_fee_amount + fee_amount)?;
+            let growth = ((fee_amount * precision())? / liquidity)?.clamp_u128()?;
+            let index = if zero_for_one { 0 } else { 1 };
+            fee_growth_global[index] = fee_growth_global[index].wrapping_add(growth);
+            sqrt_price = next_price;
+        } else {
+            // nothing to trade against until the next tick
+            sqrt_price = target_price;
+        }
+
+        if sqrt_price == target_price {
+            let mut info = load_tick(storage, next_tick)?;
+            info.fee_growth_outside_0 = Uint128(fee_growth_global[0].wrapping_sub(info.fee_growth_outside_0.u128()));
+            info.fee_growth_outside_1 = Uint128(fee_growth_global[1].wrapping_sub(info.fee_growth_outside_1.u128()));
+            if zero_for_one {
+                liquidity = ((liquidity + Uint256::from(info.liquidity_upper))? - Uint256::from(info.liquidity_lower))?;
+                pool.tick = next_tick - 1;
+            } else {
+                liquidity = ((liquidity + Uint256::from(info.liquidity_lower))? - Uint256::from(info.liquidity_upper))?;
+                pool.tick = next_tick;
+            }
+            crossed_ticks.push((next_tick, info));
+        } else {
+            pool.tick = tick_at_sqrt_price(sqrt_price)?;
+        }
+    }
+
+    pool.sqrt_price = sqrt_price.clamp_u128()?.into();
+    pool.liquidity = liquidity.clamp_u128()?.into();
+    pool.fee_growth_global_0 = Uint128(fee_growth_global[0]);
+    pool.fee_growth_global_1 = Uint128(fee_growth_global[1]);
+
+    Ok(ConcentratedSwap {
+        amount_out,
+        lp_fee_amount,
+        pool,
+        crossed_ticks,
+    })
+}
@@ -1,20 +1,30 @@
-use shadeswap_shared::msg::amm_pair::{{InitMsg,QueryMsg, SwapInfo, SwapResult, HandleMsg,TradeHistory, InvokeMsg,QueryMsgResponse}};
+use shadeswap_shared::msg::amm_pair::{{InitMsg,QueryMsg, SwapInfo, SwapResult, HandleMsg,TradeHistory, InvokeMsg,QueryMsgResponse, TWAP_PRICE_PRECISION}};
 use shadeswap_shared::msg::factory::{QueryResponse as FactoryQueryResponse,QueryMsg as FactoryQueryMsg };
 
-use shadeswap_shared::amm_pair::{{AMMSettings, AMMPair, Fee}};
+use shadeswap_shared::amm_pair::{{AMMSettings, AMMPair, Fee, CustomFee, FeeDiscounts, FeeDiscountSource, MAX_FEE_DISCOUNT,
+    OracleSettings, PairMigration, PoolType, ShadeDaoFeeMode}};
 use shadeswap_shared::token_amount::{{TokenAmount}};
 use shadeswap_shared::token_pair_amount::{{TokenPairAmount}};
 use shadeswap_shared::token_type::{{TokenType}};
 use shadeswap_shared::token_pair::{{TokenPair}};
-use shadeswap_shared::admin::{{apply_admin_guard, store_admin, load_admin, set_admin_guard}};
+use shadeswap_shared::admin::{{apply_admin_guard, store_admin, load_admin, set_admin_guard,
+    apply_swap_guard, apply_deposit_guard, set_status, set_status_guard, load_status, store_status}};
 use shadeswap_shared::Pagination;
-use crate::state::{{Config}};
-use crate::state::amm_pair_storage::{store_config, is_address_in_whitelist, store_trade_counter,
-     load_whitelist_address,add_whitelist_address,load_staking_contract, store_staking_contract, load_config, store_trade_history,remove_whitelist_address,
-load_trade_counter, load_trade_history};
+use shadeswap_shared::deadline::assert_deadline;
+use crate::state::{{Config, Migration, PriceObservation, ConcentratedPool}};
+use crate::state::amm_pair_storage::{store_config, store_trade_counter,
+     load_fee_discounts, store_fee_discounts, load_discount_viewing_key, store_discount_viewing_key,
+     load_staking_contract, store_staking_contract, load_config, store_trade_history,
+load_trade_counter, load_trade_history, load_price_accumulator, store_price_accumulator,
+load_price_observation_count, load_price_observation, load_price_observations, store_price_observation, load_locked_liquidity,
+store_locked_liquidity, load_root_k_last, store_root_k_last, load_concentrated_pool, store_concentrated_pool, load_position, store_position,
+remove_position, load_tick, load_migration, store_migration};
 use crate::help_math::{{substraction, multiply}};
+use crate::stable_swap;
+use crate::weighted;
+use crate::concentrated::{self, ConcentratedSwap};
 use crate::state::tradehistory::DirectionType;
-use crate::state::PAGINATION_LIMIT;
+use crate::state::{PAGINATION_LIMIT, PRICE_OBSERVATION_LIMIT, PRICE_OBSERVATION_PERIOD};
 use shadeswap_shared::fadroma::{
     scrt::{
         from_binary, log, secret_toolkit::snip20, to_binary, Api, BankMsg, Binary, Coin, CosmosMsg,
@@ -22,7 +32,7 @@ use shadeswap_shared::fadroma::{
         QueryResult, StdError, StdResult, Storage, Uint128, WasmMsg, WasmQuery,
     },
     scrt_callback::Callback,
-    scrt_link::ContractLink,
+    scrt_link::{ContractInstantiationInfo, ContractLink},
     scrt_uint256::Uint256,
     scrt_vk::ViewingKey,
 };
@@ -31,12 +41,15 @@ use shadeswap_shared::msg::staking::QueryResponse as StakingQueryResponse;
 use shadeswap_shared::msg::staking::HandleMsg as StakingHandleMsg;
 use shadeswap_shared::msg::router::HandleMsg as RouterHandleMsg;
 use shadeswap_shared::msg::staking::InitMsg as StakingInitMsg;
+use shadeswap_shared::msg::oracle::{QueryMsg as OracleQueryMsg, QueryResponse as OracleQueryResponse, ORACLE_PRICE_PRECISION};
 use composable_snip20::msg::{
-    InitConfig as Snip20ComposableConfig, InitMsg as Snip20ComposableMsg,
+    HandleMsg as Snip20ComposableHandleMsg, InitConfig as Snip20ComposableConfig, InitMsg as Snip20ComposableMsg,
 };
 
 const AMM_PAIR_CONTRACT_VERSION: u32 = 1;
 pub const BLOCK_SIZE: usize = 256;
+/// LP tokens minted to the pair itself on the first deposit so the share price can't be inflated
+pub const MINIMUM_LIQUIDITY: u128 = 1000;
 
 pub fn init<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
@@ -49,46 +62,58 @@ pub fn init<S: Storage, A: Api, Q: Querier>(
         ));
     }
 
+    let pool_type = msg.pool_type.unwrap_or_default();
+    match pool_type {
+        PoolType::StableSwap { amp } => stable_swap::assert_amp(amp)?,
+        PoolType::Weighted { weight_0, weight_1 } => weighted::assert_weights(weight_0, weight_1)?,
+        _ => {}
+    }
+    if let Some(oracle) = &msg.oracle {
+        assert_oracle_settings(pool_type, oracle)?;
+    }
+
     let mut messages = vec![];
     let viewing_key = create_viewing_key(&env, msg.prng_seed.clone(), msg.entropy.clone());
     register_pair_token(&env, &mut messages, &msg.pair.0, &viewing_key)?;
     register_pair_token(&env, &mut messages, &msg.pair.1, &viewing_key)?;   
-    // Create LP token and store it
-    messages.push(CosmosMsg::Wasm(WasmMsg::Instantiate {
-        code_id: msg.lp_token_contract.id,
-        msg: to_binary(&Snip20ComposableMsg {
-            name: format!(
-                "SHADESWAP Liquidity Provider (LP) token for {}-{}",
-                &msg.pair.0, &msg.pair.1
-            ),
-            admin: Some(env.contract.address.clone()),
-            symbol: "SWAP-LP".to_string(),
-            decimals: 18,
-            callback: Some(Callback {
-                msg: to_binary(&HandleMsg::OnLpTokenInitAddr)?,
-                contract: ContractLink {
-                    address: env.contract.address.clone(),
-                    code_hash: env.contract_code_hash.clone(),
-                },
-            }),
-            initial_balances: None,
-            initial_allowances: None,
-            prng_seed: msg.prng_seed,
-            config: Some(
-                Snip20ComposableConfig::builder()
-                    .public_total_supply()
-                    .enable_mint()
-                    .enable_burn()
-                    .build(),
+    // Create LP token and store it, a replacement pair takes over the one of the old pair
+    if msg.migration.is_none() {
+        messages.push(CosmosMsg::Wasm(WasmMsg::Instantiate {
+            code_id: msg.lp_token_contract.id,
+            msg: to_binary(&Snip20ComposableMsg {
+                name: format!(
+                    "SHADESWAP Liquidity Provider (LP) token for {}-{}",
+                    &msg.pair.0, &msg.pair.1
+                ),
+                admin: Some(env.contract.address.clone()),
+                symbol: "SWAP-LP".to_string(),
+                decimals: 18,
+                callback: Some(Callback {
+                    msg: to_binary(&HandleMsg::OnLpTokenInitAddr)?,
+                    contract: ContractLink {
+                        address: env.contract.address.clone(),
+                        code_hash: env.contract_code_hash.clone(),
+                    },
+                }),
+                initial_balances: None,
+                initial_allowances: None,
+                prng_seed: msg.prng_seed.clone(),
+                config: Some(
+                    Snip20ComposableConfig::builder()
+                        .public_total_supply()
+                        .enable_mint()
+                        .enable_burn()
+                        .build(),
+                ),
+            })?,
+            send: vec![],
+            label: format!(
+                "{}-{}-ShadeSwap-Pair-Token-{}",
+                &msg.pair.0, &msg.pair.1, &env.contract.address
             ),
-        })?,
-        send: vec![],
-        label: format!(
-            "{}-{}-ShadeSwap-Pair-Token-{}",
-            &msg.pair.0, &msg.pair.1, &env.contract.address
-        ),
-        callback_code_hash: msg.lp_token_contract.code_hash.clone(),
-    }));
+            callback_code_hash: msg.lp_token_contract.code_hash.clone(),
+        }));
+    }
 
     match msg.staking_contract {
         Some(c) => messages.push(CosmosMsg::Wasm(WasmMsg::Instantiate {
@@ -118,20 +143,76 @@ pub fn init<S: Storage, A: Api, Q: Querier>(
         None => println!("No callback given"),
     }
 
+    let (lp_token_info, custom_fee) = match &msg.migration {
+        Some(migration) => (migration.lp_token.clone(), migration.custom_fee),
+        None => (
+            ContractLink {
+                code_hash: msg.lp_token_contract.code_hash,
+                // We get the address when the instantiated LP token calls OnLpTokenInit
+                address: HumanAddr::default(),
+            },
+            None,
+        ),
+    };
     let config = Config {
         factory_info: msg.factory_info.clone(),
-        lp_token_info: ContractLink {
-            code_hash: msg.lp_token_contract.code_hash,
-            // We get the address when the instantiated LP token calls OnLpTokenInit
-            address: HumanAddr::default(),
-        },
+        lp_token_info,
         pair: msg.pair,
         contract_addr: env.contract.address.clone(),
         viewing_key: viewing_key,
+        custom_fee,
+        pool_type,
+        oracle: msg.oracle,
     };
 
     store_config(deps, &config)?;       
 
+    if let Some(migration) = msg.migration {
+        if let PoolType::ConcentratedLiquidity { .. } = pool_type {
+            return Err(concentrated_pool_error());
+        }
+        if let Some(staking_contract) = &migration.staking_contract {
+            store_staking_contract(deps, staking_contract)?;
+        }
+        store_status(&mut deps.storage, &migration.status)?;
+        store_fee_discounts(&mut deps.storage, &migration.fee_discounts)?;
+        store_root_k_last(&mut deps.storage, migration.root_k_last)?;
+        store_locked_liquidity(&mut deps.storage, migration.locked_liquidity)?;
+        if let Some(accumulator) = &migration.price_accumulator {
+            store_price_accumulator(&mut deps.storage, accumulator)?;
+        }
+        for observation in migration.price_observations.iter() {
+            store_price_observation(&mut deps.storage, observation)?;
+        }
+        messages.push(snip20::register_receive_msg(
+            env.contract_code_hash.clone(),
+            None,
+            BLOCK_SIZE,
+            config.lp_token_info.code_hash.clone(),
+            config.lp_token_info.address.clone(),
+        )?);
+        // the old pair sends its reserves once it knows the address of the replacement
+        messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: migration.old_pair.address,
+            callback_code_hash: migration.old_pair.code_hash,
+            msg: to_binary(&HandleMsg::CompleteMigration { key: migration.key })?,
+            send: vec![],
+        }));
+    }
+
+    if let PoolType::ConcentratedLiquidity { tick_spacing, initial_tick } = pool_type {
+        if tick_spacing == 0 {
+            return Err(StdError::generic_err("tick_spacing has to be greater than zero."));
+        }
+        store_concentrated_pool(&mut deps.storage, &ConcentratedPool {
+            sqrt_price: concentrated::sqrt_price_at_tick(initial_tick)?.clamp_u128()?.into(),
+            tick: initial_tick,
+            liquidity: Uint128::zero(),
+            fee_growth_global_0: Uint128::zero(),
+            fee_growth_global_1: Uint128::zero(),
+        })?;
+    }
+
     match msg.admin {
         Some(admin) =>  store_admin(deps, &admin)?,
         None => println!("No admin given"),
@@ -161,14 +242,20 @@ fn register_lp_token<S: Storage, A: Api, Q: Querier>(
     // store config against Smart contract address
     store_config(deps, &config)?;
 
+    let mut messages = vec![snip20::register_receive_msg(
+        env.contract_code_hash,
+        None,
+        BLOCK_SIZE,
+        config.lp_token_info.code_hash.clone(),
+        env.message.sender.clone(),
+    )?];
+    let staking_contract = load_staking_contract(&deps.storage)?;
+    if staking_contract.address != HumanAddr::default() {
+        messages.push(set_lp_token_msg(&staking_contract, &config.lp_token_info)?);
+    }
+
     Ok(HandleResponse {
-        messages: vec![snip20::register_receive_msg(
-            env.contract_code_hash,
-            None,
-            BLOCK_SIZE,
-            config.lp_token_info.code_hash,
-            env.message.sender.clone(),
-        )?],
+        messages,
         log: vec![log("liquidity_token_addr", env.message.sender)],
         data: None,
     })
@@ -210,6 +297,24 @@ pub fn handle<S: Storage, A: Api, Q: Querier>(
     env: Env,
     msg: HandleMsg,
 ) -> StdResult<HandleResponse> {
+    if let Some(migration) = load_migration(&deps.storage)? {
+        return match msg {
+            HandleMsg::CompleteMigration { key } => complete_migration(deps, env, migration, key),
+            _ => Err(StdError::generic_err(
+                "This pair has been upgraded, use its replacement instead.",
+            )),
+        };
+    }
+
+    match &msg {
+        HandleMsg::SwapTokens { .. }
+        | HandleMsg::SwapTokensForExactOutput { .. }
+        | HandleMsg::ZapIn { .. } => apply_swap_guard(&deps.storage)?,
+        HandleMsg::AddLiquidityToAMMContract { .. }
+        | HandleMsg::AddRangeLiquidity { .. } => apply_deposit_guard(&deps.storage)?,
+        _ => {}
+    }
+
     match msg {
         HandleMsg::Receive {
             from, amount, msg, ..
@@ -217,23 +322,67 @@ pub fn handle<S: Storage, A: Api, Q: Querier>(
         HandleMsg::AddLiquidityToAMMContract { deposit, slippage } => {
             add_liquidity(deps, env, deposit, slippage)
         }
+        HandleMsg::ZapIn { offer, min_lp_tokens } => {
+            // SNIP20 tokens go through the Receive interface
+            if !offer.token.is_native_token() {
+                return Err(StdError::unauthorized());
+            }
+
+            offer.assert_sent_native_token_balance(&env)?;
+            let sender = env.message.sender.clone();
+            zap_in(deps, env, sender, offer, min_lp_tokens)
+        }
+        HandleMsg::AddRangeLiquidity { deposit, lower_tick, upper_tick, min_liquidity } => {
+            add_range_liquidity(deps, env, deposit, lower_tick, upper_tick, min_liquidity)
+        }
+        HandleMsg::RemoveRangeLiquidity { lower_tick, upper_tick, liquidity } => {
+            remove_range_liquidity(deps, env, lower_tick, upper_tick, liquidity)
+        }
+        HandleMsg::CollectRangeFees { lower_tick, upper_tick } => {
+            remove_range_liquidity(deps, env, lower_tick, upper_tick, Uint128::zero())
+        }
         HandleMsg::SetStakingContract{contract} => set_staking_contract(deps, env, contract),
+        HandleMsg::SetCustomFee { fee } => set_custom_fee(deps, env, fee),
+        HandleMsg::SetOracle { oracle } => set_oracle(deps, env, oracle),
+        HandleMsg::MigrateTo { new_code, prng_seed, entropy, callback } => {
+            migrate_to(deps, env, new_code, prng_seed, entropy, callback)
+        }
+        // only accepted while the pair is being upgraded
+        HandleMsg::CompleteMigration { .. } => Err(StdError::unauthorized()),
         HandleMsg::SetAMMPairAdmin {admin} => set_admin_guard(deps,env,admin),
+        HandleMsg::SetAMMPairStatus { status } => {
+            // the factory pauses all of its pairs at once
+            if load_config(deps)?.factory_info.address == env.message.sender {
+                set_status(deps, env, status)
+            } else {
+                set_status_guard(deps, env, status)
+            }
+        }
         HandleMsg::OnLpTokenInitAddr => register_lp_token(deps, env),
-        HandleMsg::AddWhiteListAddress{address} => add_address_to_whitelist(&mut deps.storage, address, env),
-        HandleMsg::RemoveWhitelistAddresses{addresses} => remove_address_from_whitelist(&mut deps.storage, addresses, env),
+        HandleMsg::SetFeeDiscounts { discounts } => set_fee_discounts(&mut deps.storage, env, discounts),
+        HandleMsg::SetDiscountViewingKey { key } => {
+            store_discount_viewing_key(&mut deps.storage, &env.message.sender, &key)?;
+            Ok(HandleResponse {
+                messages: vec![],
+                log: vec![log("action", "set_discount_viewing_key")],
+                data: None,
+            })
+        }
         HandleMsg::SwapTokens {
             offer,
             expected_return,
             to,
             router_link,
             callback_signature,
+            deadline,
+            trader,
         } => {
             // this is assert if token is SCRT if not then swapp will be called via SNIP20 Interface
             if !offer.token.is_native_token() {
                 return Err(StdError::unauthorized());
             }
 
+            assert_deadline(&env, &deadline)?;
             offer.assert_sent_native_token_balance(&env)?;
             let config_settings = load_config(deps)?;
             let sender = env.message.sender.clone();
@@ -247,6 +396,34 @@ pub fn handle<S: Storage, A: Api, Q: Querier>(
                 expected_return,
                 router_link,
                 callback_signature,
+                trader,
+            )
+        }
+        HandleMsg::SwapTokensForExactOutput {
+            offer,
+            desired_output,
+            max_input,
+            to,
+            deadline,
+        } => {
+            // same as SwapTokens, SNIP20 tokens go through the Receive interface
+            if !offer.token.is_native_token() {
+                return Err(StdError::unauthorized());
+            }
+
+            assert_deadline(&env, &deadline)?;
+            offer.assert_sent_native_token_balance(&env)?;
+            let config_settings = load_config(deps)?;
+            let sender = env.message.sender.clone();
+            swap_for_exact_output(
+                deps,
+                env,
+                config_settings,
+                sender,
+                to,
+                offer,
+                desired_output,
+                max_input,
             )
         }
     }
@@ -257,8 +434,9 @@ pub fn query_calculate_price_and_spread<S: Storage, A: Api, Q: Querier>(
     offer: TokenAmount<HumanAddr>
 ) -> StdResult<SwapInfo>{
     let config_settings = load_config(deps)?;
-    let amm_settings = query_factory_amm_settings(&deps.querier, config_settings.factory_info.clone())?;
-    let swap_result = calculate_swap_result(&deps.querier, &amm_settings, &config_settings,&offer,  &deps.storage, HumanAddr::default())?;
+    let amm_settings = query_pair_amm_settings(&deps.querier, &config_settings)?;
+    let swap_result = calculate_swap_result(&deps.querier, &amm_settings, &config_settings,&offer,  &deps.storage, HumanAddr::default(),
+        false, None)?;
     Ok(swap_result)
 }
 
@@ -272,10 +450,22 @@ pub fn swap<S: Storage, A: Api, Q: Querier>(
     expected_return: Option<Uint128>,
     router_link: Option<ContractLink<HumanAddr>>,
     callback_signature: Option<Binary>,
+    trader: Option<HumanAddr>,
 ) -> StdResult<HandleResponse> {
+    let trader = discount_trader(&deps.storage, &sender, trader)?;
     let swaper_receiver = recipient.unwrap_or(sender);
-    let amm_settings = query_factory_amm_settings(&deps.querier,config.factory_info.clone())?;
-    let swap_result = calculate_swap_result(&deps.querier, &amm_settings, &config, &offer,&mut deps.storage, swaper_receiver.clone())?;
+    let amm_settings = query_pair_amm_settings(&deps.querier, &config)?;
+    // concentrated pools read their price from storage, record it before the swap moves it
+    record_price_before_swap(deps, &env, &config, &offer)?;
+    let swap_result = if let PoolType::ConcentratedLiquidity { .. } = config.pool_type {
+        let (swap_result, concentrated_swap) = calculate_concentrated_swap_result(
+            &deps.querier, &amm_settings, &config, &offer, &deps.storage, trader)?;
+        concentrated::store_concentrated_swap(&mut deps.storage, &concentrated_swap)?;
+        swap_result
+    } else {
+        calculate_swap_result(&deps.querier, &amm_settings, &config, &offer,&mut deps.storage, trader,
+            true, Some(env.block.time))?
+    };
 
     // check for the slippage expected value compare to actual value
     if let Some(expected_return) = expected_return {
@@ -288,32 +478,13 @@ pub fn swap<S: Storage, A: Api, Q: Querier>(
 
     // // Send Shade_Dao_Fee back to shade_dao_address which is 0.1%
     let mut messages = Vec::with_capacity(3);
-    if swap_result.shade_dao_fee_amount > Uint128::zero() {
-        match &offer.token {
-            TokenType::CustomToken {
-                contract_addr,
-                token_code_hash,
-            } => {
-                messages.push(snip20::transfer_msg(
-                    amm_settings.shade_dao_address.address,
-                    swap_result.shade_dao_fee_amount,
-                    None,
-                    BLOCK_SIZE,
-                    token_code_hash.clone(),
-                    contract_addr.clone(),
-                )?);
-            }
-            TokenType::NativeToken { denom } => {
-                messages.push(CosmosMsg::Bank(BankMsg::Send {
-                    from_address: env.contract.address.clone(),
-                    to_address: amm_settings.shade_dao_address.address.clone(),
-                    amount: vec![Coin {
-                        denom: denom.clone(),
-                        amount: swap_result.shade_dao_fee_amount,
-                    }],
-                }));
-            }
-        }
+    if swap_result.shade_dao_fee_amount > Uint128::zero() && transfers_shade_dao_fee(&amm_settings, config.pool_type) {
+        messages.push(create_shade_dao_fee_msg(
+            &env,
+            &amm_settings,
+            &offer.token,
+            swap_result.shade_dao_fee_amount,
+        )?);
     }
 
     // Send Token to Buyer or Swapper
@@ -376,123 +547,606 @@ pub fn swap<S: Storage, A: Api, Q: Querier>(
     })
 }
 
-pub fn set_staking_contract<S: Storage, A: Api, Q: Querier>(
-    deps: &mut Extern<S, A, Q>, 
+pub fn swap_for_exact_output<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
     env: Env,
-    contract: ContractLink<HumanAddr>
-)-> StdResult<HandleResponse>{      
-    // only callback can call this method
-    let contract_info = load_staking_contract(&deps)?;    
-    if contract_info.address != HumanAddr::default(){
-        return Err(StdError::unauthorized())
+    config: Config<HumanAddr>,
+    sender: HumanAddr,
+    recipient: Option<HumanAddr>,
+    offer: TokenAmount<HumanAddr>,
+    desired_output: Uint128,
+    max_input: Uint128,
+) -> StdResult<HandleResponse> {
+    let swaper_receiver = recipient.unwrap_or(sender.clone());
+    let amm_settings = query_pair_amm_settings(&deps.querier, &config)?;
+    let (required_input, swap_result) = calculate_exact_output_swap_result(
+        &deps.querier,
+        &amm_settings,
+        &config,
+        &offer,
+        desired_output,
+        &deps.storage,
+        sender.clone(),
+        Some(env.block.time),
+    )?;
+
+    if required_input > max_input {
+        return Err(StdError::generic_err(format!(
+            "Operation requires {} of the offered token which exceeds max_input {}",
+            required_input, max_input
+        )));
     }
-    store_staking_contract(deps, &contract.clone())?;
+
+    if required_input > offer.amount {
+        return Err(StdError::generic_err(format!(
+            "Operation requires {} of the offered token but only {} was sent",
+            required_input, offer.amount
+        )));
+    }
+    record_price_before_swap(deps, &env, &config, &offer)?;
+
+    let mut messages = Vec::with_capacity(3);
+    if swap_result.shade_dao_fee_amount > Uint128::zero() && transfers_shade_dao_fee(&amm_settings, config.pool_type) {
+        messages.push(create_shade_dao_fee_msg(
+            &env,
+            &amm_settings,
+            &offer.token,
+            swap_result.shade_dao_fee_amount,
+        )?);
+    }
+
+    // Send exactly the desired output to Buyer or Swapper
+    let index = config.pair.get_token_index(&offer.token).unwrap(); // Safe, checked in calculate_exact_output_swap_result
+    let token = config.pair.get_token(index ^ 1).unwrap();
+    messages.push(token.create_send_msg(
+        env.contract.address.clone(),
+        swaper_receiver,
+        desired_output,
+    )?);
+
+    // Refund the part of the offer which wasn't needed
+    let refund_amount = (offer.amount - required_input)?;
+    if refund_amount > Uint128::zero() {
+        messages.push(offer.token.create_send_msg(
+            env.contract.address.clone(),
+            sender,
+            refund_amount,
+        )?);
+    }
+
+    let sell_or_swap = if index == 0 { "Buy" } else { "Sell" };
+    let trade_history = TradeHistory {
+        price: swap_result.price,
+        amount: desired_output,
+        timestamp: env.block.time,
+        direction: sell_or_swap.to_string(),
+        lp_fee_amount: swap_result.lp_fee_amount,
+        total_fee_amount: swap_result.total_fee_amount,
+        shade_dao_fee_amount: swap_result.shade_dao_fee_amount,
+    };
+    store_trade_history(deps, &trade_history)?;
+
     Ok(HandleResponse {
-        messages: vec![],
+        messages,
         log: vec![
-            log("action", "set_staking_contract"),
-            log("contract_address", contract.address.clone()),
-            log("contract_hash", contract.code_hash.to_string().clone()),
+            log("action", "swap_for_exact_output"),
+            log("offer_token", offer.token),
+            log("offer_amount", required_input),
+            log("return_amount", desired_output),
+            log("refund_amount", refund_amount),
+            log("spread_amount", swap_result.result.spread_amount),
+            log("lp_fee", swap_result.lp_fee_amount),
+            log("shade_dao_fee", swap_result.shade_dao_fee_amount),
+            log("shade_total_fee", swap_result.total_fee_amount),
         ],
         data: None,
     })
 }
 
-pub fn query<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>, msg: QueryMsg) -> QueryResult {
-    match msg {
-        QueryMsg::GetPairInfo => {
-            let config = load_config(deps)?;
-            let balances = config.pair.query_balances(
-                &deps.querier,
-                config.contract_addr,
-                config.viewing_key.0,
-            )?;
-            let total_liquidity = query_liquidity(&deps.querier, &config.lp_token_info)?;
-            to_binary(&QueryMsgResponse::GetPairInfo {
-                liquidity_token: config.lp_token_info,
-                factory: config.factory_info,
-                pair: config.pair,
-                amount_0: balances[0],
-                amount_1: balances[1],
-                total_liquidity,
-                contract_version: AMM_PAIR_CONTRACT_VERSION,
-            })
-        }
-        QueryMsg::GetTradeHistory { pagination } => {
-            let data = load_trade_history_query(&deps, pagination)?;
-            to_binary(&QueryMsgResponse::GetTradeHistory { data })
-        },
-        QueryMsg::GetAdmin{} =>{
-            let admin_address = load_admin(&deps.storage)?;
-            to_binary(&QueryMsgResponse::GetAdminAddress{
-                address: admin_address
-            })
-        },
-        QueryMsg::GetWhiteListAddress => {
-            let stored_addr = load_whitelist_address(&deps.storage)?;
-            to_binary(&QueryMsgResponse::GetWhiteListAddress {
-                addresses: stored_addr,
-            })
-        }
-        QueryMsg::GetTradeCount => {
-            let count = load_trade_counter(&deps.storage)?;
-            to_binary(&QueryMsgResponse::GetTradeCount { count })
-        },
-        QueryMsg::GetStakingContract => {
-            let staking_contract = load_staking_contract(&deps)?;
-            to_binary(&QueryMsgResponse::StakingContractInfo{
-                staking_contract: staking_contract
-            })
-        },
-        QueryMsg::GetClaimReward {time, staker} => {
-            let amount = query_claim_rewards(&deps, staker, time)?;
-            to_binary(&QueryMsgResponse::GetClaimReward { amount: amount })
-        },       
-        QueryMsg::GetEstimatedPrice {offer} => {
-           let swap_result = query_calculate_price_and_spread(&deps,offer)?;
-           to_binary(&QueryMsgResponse::EstimatedPrice { estimated_price : swap_result.price })
-        }
+fn create_shade_dao_fee_msg(
+    env: &Env,
+    settings: &AMMSettings<HumanAddr>,
+    token: &TokenType<HumanAddr>,
+    amount: Uint128,
+) -> StdResult<CosmosMsg> {
+    match token {
+        TokenType::CustomToken {
+            contract_addr,
+            token_code_hash,
+        } => snip20::transfer_msg(
+            settings.shade_dao_address.address.clone(),
+            amount,
+            None,
+            BLOCK_SIZE,
+            token_code_hash.clone(),
+            contract_addr.clone(),
+        ),
+        TokenType::NativeToken { denom } => Ok(CosmosMsg::Bank(BankMsg::Send {
+            from_address: env.contract.address.clone(),
+            to_address: settings.shade_dao_address.address.clone(),
+            amount: vec![Coin {
+                denom: denom.clone(),
+                amount,
+            }],
+        })),
     }
 }
 
-fn load_trade_history_query<S: Storage, A: Api, Q: Querier>(
-    deps: &Extern<S, A, Q>,
-    pagination: Pagination,
-) -> StdResult<Vec<TradeHistory>> {
-    let count = load_trade_counter(&deps.storage)?;
-
-    if pagination.start >= count {
-        return Ok(vec![]);
+/// Concentrated liquidity pools have no LP tokens to mint, they always transfer the DAO fee.
+fn transfers_shade_dao_fee(settings: &AMMSettings<HumanAddr>, pool_type: PoolType) -> bool {
+    match pool_type {
+        PoolType::ConcentratedLiquidity { .. } => true,
+        _ => settings.shade_dao_fee_mode == ShadeDaoFeeMode::Transfer,
     }
+}
 
-    let limit = pagination.limit.min(PAGINATION_LIMIT);
-    let end = (pagination.start + limit as u64).min(count);
+/// Size of the pool which only grows through fees, `sqrt(x * y)` for constant product
+/// pools and the invariant for the others.
+fn calculate_root_k(pool_type: PoolType, reserves: &[Uint128; 2]) -> StdResult<Uint256> {
+    let reserve_0 = Uint256::from(reserves[0]);
+    let reserve_1 = Uint256::from(reserves[1]);
+    if reserve_0 == Uint256::zero() || reserve_1 == Uint256::zero() {
+        return Ok(Uint256::zero());
+    }
+    match pool_type {
+        PoolType::StableSwap { amp } => stable_swap::calculate_invariant(amp, reserve_0, reserve_1),
+        PoolType::Weighted { weight_0, weight_1 } => {
+            weighted::calculate_invariant(weight_0, weight_1, reserve_0, reserve_1)
+        }
+        _ => (reserve_0 * reserve_1)?.sqrt(),
+    }
+}
 
-    let mut result = Vec::with_capacity((end - pagination.start) as usize);
+/// Mints the DAO share of the fees earned since the last liquidity change as LP tokens
+/// (Uniswap v2 `kLast`). Has to run on the reserves before the change, the returned
+/// amount is added to the LP token supply.
+pub fn mint_shade_dao_liquidity(
+    storage: &impl Storage,
+    settings: &AMMSettings<HumanAddr>,
+    pool_type: PoolType,
+    reserves: &[Uint128; 2],
+    total_liquidity: Uint128,
+    lp_token_info: &ContractLink<HumanAddr>,
+    messages: &mut Vec<CosmosMsg>,
+) -> StdResult<Uint128> {
+    if transfers_shade_dao_fee(settings, pool_type) {
+        return Ok(Uint128::zero());
+    }
+    let root_k_last = Uint256::from(load_root_k_last(storage)?);
+    let root_k = calculate_root_k(pool_type, reserves)?;
+    if root_k_last == Uint256::zero() || root_k <= root_k_last || total_liquidity.is_zero() {
+        return Ok(Uint128::zero());
+    }
 
-    for i in pagination.start..end {
-        let tempIndex = i + 1;
-        let trade_history: TradeHistory = load_trade_history(deps, tempIndex)?;
-        result.push(trade_history);
+    // part of the collected fees which belongs to the DAO, dao / (lp + dao)
+    let dao_share = (Uint256::from(settings.shade_dao_fee.nom as u128) * Uint256::from(settings.lp_fee.denom as u128))?;
+    let lp_share = (Uint256::from(settings.lp_fee.nom as u128) * Uint256::from(settings.shade_dao_fee.denom as u128))?;
+    if dao_share == Uint256::zero() {
+        return Ok(Uint128::zero());
+    }
+    // s / (S + s) = dao_share / (lp_share + dao_share) * (root_k - root_k_last) / root_k
+    let nominator = (((Uint256::from(total_liquidity) * dao_share)?) * (root_k - root_k_last)?)?;
+    let denominator = ((lp_share * root_k)? + (dao_share * root_k_last)?)?;
+    let liquidity: Uint128 = (nominator / denominator)?.clamp_u128()?.into();
+    if liquidity.is_zero() {
+        return Ok(liquidity);
     }
 
-    Ok(result)
+    messages.push(snip20::mint_msg(
+        settings.shade_dao_address.address.clone(),
+        liquidity,
+        None,
+        BLOCK_SIZE,
+        lp_token_info.code_hash.clone(),
+        lp_token_info.address.clone(),
+    )?);
+    Ok(liquidity)
 }
 
-fn calculate_fee(amount: Uint256, fee: Fee) -> StdResult<Uint128> {
-    let nom = Uint256::from(fee.nom);
-    let denom = Uint256::from(fee.denom);
-    let amount = ((amount * nom)? / denom)?;
-    Ok(amount.clamp_u128()?.into())
+/// Remembers the size of the pool after a liquidity change, the DAO share of the fees
+/// is measured from here on.
+fn store_root_k_after_change(
+    storage: &mut impl Storage,
+    settings: &AMMSettings<HumanAddr>,
+    pool_type: PoolType,
+    reserves: &[Uint128; 2],
+) -> StdResult<()> {
+    let root_k = if transfers_shade_dao_fee(settings, pool_type) {
+        Uint128::zero()
+    } else {
+        calculate_root_k(pool_type, reserves)?.clamp_u128()?.into()
+    };
+    store_root_k_last(storage, root_k)
 }
 
-pub fn calculate_swap_result(
-    querier: &impl Querier,
-    settings: &AMMSettings<HumanAddr>,
+pub fn set_custom_fee<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    fee: Option<CustomFee>,
+) -> StdResult<HandleResponse> {
+    // only the factory can change the fees of the pair
+    let mut config = load_config(&deps)?;
+    if config.factory_info.address != env.message.sender {
+        return Err(StdError::unauthorized());
+    }
+    if let Some(fee) = &fee {
+        fee.validate()?;
+    }
+    config.custom_fee = fee;
+    store_config(deps, &config)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "set_custom_fee"),
+            log("custom_fee", format!("{:?}", fee)),
+        ],
+        data: None,
+    })
+}
+
+/// Replaces the oracle of an oracle pegged pair, only the factory can rotate it.
+pub fn set_oracle<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    oracle: OracleSettings<HumanAddr>,
+) -> StdResult<HandleResponse> {
+    let mut config = load_config(&deps)?;
+    if config.factory_info.address != env.message.sender {
+        return Err(StdError::unauthorized());
+    }
+    if config.oracle.is_none() {
+        return Err(StdError::generic_err("Only oracle pegged pairs have an oracle to replace."));
+    }
+    assert_oracle_settings(config.pool_type, &oracle)?;
+    let address = oracle.contract.address.clone();
+    config.oracle = Some(oracle);
+    store_config(deps, &config)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "set_oracle"),
+            log("oracle", address),
+        ],
+        data: None,
+    })
+}
+
+/// Stops the pair and instantiates its replacement from `new_code`. The replacement calls
+/// back with the migration key before the reserves are moved.
+pub fn migrate_to<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    new_code: ContractInstantiationInfo,
+    prng_seed: Binary,
+    entropy: Binary,
+    callback: Callback<HumanAddr>,
+) -> StdResult<HandleResponse> {
+    let config = load_config(&deps)?;
+    if config.factory_info.address != env.message.sender {
+        return Err(StdError::unauthorized());
+    }
+    // positions of concentrated pools live in the storage of the pair
+    if let PoolType::ConcentratedLiquidity { .. } = config.pool_type {
+        return Err(concentrated_pool_error());
+    }
+
+    let key = create_viewing_key(&env, prng_seed.clone(), entropy.clone()).0;
+    store_migration(&mut deps.storage, &Migration {
+        key: key.clone(),
+        new_pair: None,
+        new_code_hash: new_code.code_hash.clone(),
+    })?;
+
+    let staking_contract = load_staking_contract(&deps.storage)?;
+    let admin = load_admin(&deps.storage)?;
+    Ok(HandleResponse {
+        messages: vec![CosmosMsg::Wasm(WasmMsg::Instantiate {
+            code_id: new_code.id,
+            callback_code_hash: new_code.code_hash.clone(),
+            send: vec![],
+            label: format!(
+                "{}-{}-pair-{}-{}",
+                config.pair.0, config.pair.1, env.contract.address, new_code.id
+            ),
+            msg: to_binary(&InitMsg {
+                pair: config.pair.clone(),
+                lp_token_contract: ContractInstantiationInfo {
+                    code_hash: config.lp_token_info.code_hash.clone(),
+                    id: 0,
+                },
+                factory_info: config.factory_info.clone(),
+                prng_seed,
+                callback: Some(callback),
+                entropy,
+                admin: if admin == HumanAddr::default() { None } else { Some(admin) },
+                staking_contract: None,
+                pool_type: Some(config.pool_type),
+                oracle: config.oracle.clone(),
+                migration: Some(PairMigration {
+                    old_pair: ContractLink {
+                        address: env.contract.address.clone(),
+                        code_hash: env.contract_code_hash.clone(),
+                    },
+                    key,
+                    lp_token: config.lp_token_info.clone(),
+                    staking_contract: if staking_contract.address == HumanAddr::default() {
+                        None
+                    } else {
+                        Some(staking_contract)
+                    },
+                    custom_fee: config.custom_fee,
+                    status: load_status(&deps.storage)?,
+                    fee_discounts: load_fee_discounts(&deps.storage)?,
+                    root_k_last: load_root_k_last(&deps.storage)?,
+                    locked_liquidity: load_locked_liquidity(&deps.storage)?,
+                    price_accumulator: load_price_accumulator(&deps.storage)?,
+                    price_observations: load_price_observations(&deps.storage)?,
+                }),
+            })?,
+        })],
+        log: vec![
+            log("action", "migrate_to"),
+            log("code_id", new_code.id),
+        ],
+        data: None,
+    })
+}
+
+/// Hands the reserves, the LP token and the staking contract to the replacement. The DAO
+/// share of the fees collected so far is left to the replacement, it carries on from the
+/// `root_k_last` of this pair.
+fn complete_migration<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    mut migration: Migration,
+    key: String,
+) -> StdResult<HandleResponse> {
+    if migration.new_pair.is_some() || migration.key != key {
+        return Err(StdError::unauthorized());
+    }
+    let new_pair = env.message.sender.clone();
+    migration.new_pair = Some(new_pair.clone());
+    store_migration(&mut deps.storage, &migration)?;
+
+    let config = load_config(&deps)?;
+    let reserves = config.pair.query_balances(
+        &deps.querier,
+        config.contract_addr.clone(),
+        config.viewing_key.0.clone(),
+    )?;
+    let mut messages = vec![];
+    for (i, amount) in reserves.iter().enumerate() {
+        if amount.is_zero() {
+            continue;
+        }
+        messages.push(config.pair.get_token(i).unwrap().create_send_msg(
+            env.contract.address.clone(),
+            new_pair.clone(),
+            *amount,
+        )?);
+    }
+    messages.push(snip20::set_minters_msg(
+        vec![new_pair.clone()],
+        None,
+        BLOCK_SIZE,
+        config.lp_token_info.code_hash.clone(),
+        config.lp_token_info.address.clone(),
+    )?);
+    // only the admin of the LP token can set its minters, so the replacement has to
+    // become the admin for it to be upgraded in turn
+    messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: config.lp_token_info.address.clone(),
+        callback_code_hash: config.lp_token_info.code_hash.clone(),
+        msg: to_binary(&Snip20ComposableHandleMsg::ChangeAdmin {
+            address: new_pair.clone(),
+            padding: None,
+        })?,
+        send: vec![],
+    }));
+
+    let staking_contract = load_staking_contract(&deps.storage)?;
+    if staking_contract.address != HumanAddr::default() {
+        messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: staking_contract.address,
+            callback_code_hash: staking_contract.code_hash,
+            msg: to_binary(&StakingHandleMsg::SetStakingAdmin {
+                admin: ContractLink {
+                    address: new_pair.clone(),
+                    code_hash: migration.new_code_hash.clone(),
+                },
+            })?,
+            send: vec![],
+        }));
+    }
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![
+            log("action", "complete_migration"),
+            log("new_pair", new_pair),
+            log("reserve_0", reserves[0]),
+            log("reserve_1", reserves[1]),
+        ],
+        data: None,
+    })
+}
+
+/// Lets the staking contract accept LP tokens.
+fn set_lp_token_msg(
+    staking_contract: &ContractLink<HumanAddr>,
+    lp_token_info: &ContractLink<HumanAddr>,
+) -> StdResult<CosmosMsg> {
+    Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: staking_contract.address.clone(),
+        callback_code_hash: staking_contract.code_hash.clone(),
+        msg: to_binary(&StakingHandleMsg::SetLPToken { lp_token: lp_token_info.clone() })?,
+        send: vec![],
+    }))
+}
+
+pub fn set_staking_contract<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>, 
+    env: Env,
+    contract: ContractLink<HumanAddr>
+)-> StdResult<HandleResponse>{      
+    // only callback can call this method
+    let contract_info = load_staking_contract(&deps.storage)?;    
+    if contract_info.address != HumanAddr::default(){
+        return Err(StdError::unauthorized())
+    }
+    store_staking_contract(deps, &contract.clone())?;
+    // whichever of the staking contract and the LP token comes second sends the LP token over
+    let lp_token_info = load_config(deps)?.lp_token_info;
+    let mut messages = vec![];
+    if lp_token_info.address != HumanAddr::default() {
+        messages.push(set_lp_token_msg(&contract, &lp_token_info)?);
+    }
+    Ok(HandleResponse {
+        messages,
+        log: vec![
+            log("action", "set_staking_contract"),
+            log("contract_address", contract.address.clone()),
+            log("contract_hash", contract.code_hash.to_string().clone()),
+        ],
+        data: None,
+    })
+}
+
+pub fn query<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>, msg: QueryMsg) -> QueryResult {
+    match msg {
+        QueryMsg::GetPairInfo => {
+            let config = load_config(deps)?;
+            let balances = config.pair.query_balances(
+                &deps.querier,
+                config.contract_addr,
+                config.viewing_key.0,
+            )?;
+            let total_liquidity = query_liquidity(&deps.querier, &config.lp_token_info)?;
+            to_binary(&QueryMsgResponse::GetPairInfo {
+                liquidity_token: config.lp_token_info,
+                factory: config.factory_info,
+                pair: config.pair,
+                amount_0: balances[0],
+                amount_1: balances[1],
+                total_liquidity,
+                locked_liquidity: load_locked_liquidity(&deps.storage)?,
+                pool_type: config.pool_type,
+                oracle: config.oracle,
+                contract_version: AMM_PAIR_CONTRACT_VERSION,
+            })
+        }
+        QueryMsg::GetTradeHistory { pagination } => {
+            let data = load_trade_history_query(&deps, pagination)?;
+            to_binary(&QueryMsgResponse::GetTradeHistory { data })
+        },
+        QueryMsg::GetAdmin{} =>{
+            let admin_address = load_admin(&deps.storage)?;
+            to_binary(&QueryMsgResponse::GetAdminAddress{
+                address: admin_address
+            })
+        },
+        QueryMsg::GetFeeDiscounts => {
+            let discounts = load_fee_discounts(&deps.storage)?;
+            to_binary(&QueryMsgResponse::GetFeeDiscounts { discounts })
+        }
+        QueryMsg::GetFeeDiscount { address } => {
+            let discount = query_fee_discount(&deps.querier, &deps.storage, &address)?;
+            to_binary(&QueryMsgResponse::GetFeeDiscount { discount })
+        }
+        QueryMsg::GetTradeCount => {
+            let count = load_trade_counter(&deps.storage)?;
+            to_binary(&QueryMsgResponse::GetTradeCount { count })
+        },
+        QueryMsg::GetStakingContract => {
+            let staking_contract = load_staking_contract(&deps.storage)?;
+            to_binary(&QueryMsgResponse::StakingContractInfo{
+                staking_contract: staking_contract
+            })
+        },
+        QueryMsg::GetClaimReward {time, staker} => {
+            let rewards = query_claim_rewards(&deps, staker, time)?;
+            to_binary(&QueryMsgResponse::GetClaimReward { rewards })
+        },       
+        QueryMsg::GetEstimatedPrice {offer} => {
+           let swap_result = query_calculate_price_and_spread(&deps,offer)?;
+           to_binary(&QueryMsgResponse::EstimatedPrice { estimated_price : swap_result.price })
+        },
+        QueryMsg::GetTwap { window, time } => query_twap(deps, window, time),
+        QueryMsg::GetConcentratedPool => {
+            let pool = load_concentrated_pool(&deps.storage)?;
+            to_binary(&QueryMsgResponse::GetConcentratedPool {
+                sqrt_price: pool.sqrt_price,
+                tick: pool.tick,
+                liquidity: pool.liquidity,
+            })
+        }
+        QueryMsg::GetPosition { owner, lower_tick, upper_tick } => {
+            query_position(deps, owner, lower_tick, upper_tick)
+        }
+        QueryMsg::SwapSimulation { offer } => {
+            let swap_result = query_calculate_price_and_spread(&deps, offer)?;
+            to_binary(&QueryMsgResponse::SwapSimulation {
+                total_fee_amount: swap_result.total_fee_amount,
+                lp_fee_amount: swap_result.lp_fee_amount,
+                shade_dao_fee_amount: swap_result.shade_dao_fee_amount,
+                result: swap_result.result,
+                price: swap_result.price,
+            })
+        }
+    }
+}
+
+fn load_trade_history_query<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    pagination: Pagination,
+) -> StdResult<Vec<TradeHistory>> {
+    let count = load_trade_counter(&deps.storage)?;
+
+    if pagination.start >= count {
+        return Ok(vec![]);
+    }
+
+    let limit = pagination.limit.min(PAGINATION_LIMIT);
+    let end = (pagination.start + limit as u64).min(count);
+
+    let mut result = Vec::with_capacity((end - pagination.start) as usize);
+
+    for i in pagination.start..end {
+        let tempIndex = i + 1;
+        let trade_history: TradeHistory = load_trade_history(deps, tempIndex)?;
+        result.push(trade_history);
+    }
+
+    Ok(result)
+}
+
+/// `fee` of `amount` with the discount of the trader taken off.
+fn calculate_fee(amount: Uint256, fee: Fee, discount: u16) -> StdResult<Uint128> {
+    let (nom, denom) = discounted_fee(fee, discount)?;
+    let amount = ((amount * nom)? / denom)?;
+    Ok(amount.clamp_u128()?.into())
+}
+
+/// `fee` as a `nom / denom` fraction, reduced by `discount` basis points.
+fn discounted_fee(fee: Fee, discount: u16) -> StdResult<(Uint256, Uint256)> {
+    let nom = (Uint256::from(fee.nom as u128) * Uint256::from(MAX_FEE_DISCOUNT.saturating_sub(discount)))?;
+    let denom = (Uint256::from(fee.denom as u128) * Uint256::from(MAX_FEE_DISCOUNT))?;
+    Ok((nom, denom))
+}
+
+/// Prices `offer` against the reserves of the pool. `offer_in_pool` is set when the offer
+/// was already sent to the pair, so quotes and executed swaps see the same reserves.
+pub fn calculate_swap_result(
+    querier: &impl Querier,
+    settings: &AMMSettings<HumanAddr>,
     config: &Config<HumanAddr>,
     offer: &TokenAmount<HumanAddr>,
     storage: &impl Storage,
-    recipient: HumanAddr,
+    trader: HumanAddr,
+    offer_in_pool: bool,
+    time: Option<u64>,
 ) -> StdResult<SwapInfo> {
     if !config.pair.contains(&offer.token) {
         return Err(StdError::generic_err(format!(
@@ -501,30 +1155,32 @@ pub fn calculate_swap_result(
         )));
     }
 
+    if let PoolType::ConcentratedLiquidity { .. } = config.pool_type {
+        return calculate_concentrated_swap_result(querier, settings, config, offer, storage, trader)
+            .map(|(swap_info, _)| swap_info);
+    }
+
     let amount = Uint256::from(offer.amount);
     // conver tand get avialble balance
-    let tokens_pool = get_token_pool_balance(querier, config, offer)?;
-    let token0_pool = tokens_pool[0];
-    let token1_pool = tokens_pool[1];
-    // calculate price
-    let swap_amount = calculate_price(amount, token0_pool, token1_pool)?;
-    let spread_amount = calculate_spread(amount, token0_pool, token1_pool)?;
-
-    // calculate fee
-    let lp_fee = settings.lp_fee;
-    let shade_dao_fee = settings.shade_dao_fee;
-    let mut lp_fee_amount = Uint128(0u128);
-    let mut shade_dao_fee_amount = Uint128(0u128);
-    // calculation fee
-    let discount_fee = is_address_in_whitelist(storage, recipient)?;
-    if discount_fee == false {
-        lp_fee_amount = calculate_fee(swap_amount, lp_fee)?;
-        shade_dao_fee_amount = calculate_fee(swap_amount, shade_dao_fee)?;
-    }
+    let tokens_pool = get_token_pool_balance(querier, config, offer, offer_in_pool)?;
+    let offer_index = config.pair.get_token_index(&offer.token).unwrap();
+    let pool_type = pool_type_for_offer(config.pool_type, offer_index);
+    let oracle_price = query_oracle_price(querier, &config.oracle, offer_index, time)?;
+    let (token0_pool, token1_pool) = center_pool_on_oracle(oracle_price, tokens_pool[0], tokens_pool[1])?;
+
+    // fees are charged on the offer, only the rest is swapped
+    let discount = query_fee_discount(querier, storage, &trader)?;
+    let lp_fee_amount = calculate_fee(amount, settings.lp_fee, discount)?;
+    let shade_dao_fee_amount = calculate_fee(amount, settings.shade_dao_fee, discount)?;
     let total_fee_amount = lp_fee_amount + shade_dao_fee_amount;
-    let deducted_offer_amount = (swap_amount - Uint256::from(total_fee_amount))?;
+    let net_amount = (amount - Uint256::from(total_fee_amount))?;
+
+    // calculate price
+    let swap_amount = calculate_swap_output(pool_type, net_amount, token0_pool, token1_pool)?;
+    assert_oracle_swap(&config.oracle, oracle_price, net_amount, swap_amount, tokens_pool[1])?;
+    let spread_amount = calculate_swap_spread(pool_type, net_amount, swap_amount, token0_pool, token1_pool)?;
     let result_swap = SwapResult {
-        return_amount: deducted_offer_amount.clamp_u128()?.into(),
+        return_amount: swap_amount.clamp_u128()?.into(),
         spread_amount: spread_amount.clamp_u128()?.into(),
     };
 
@@ -533,40 +1189,207 @@ pub fn calculate_swap_result(
         shade_dao_fee_amount: shade_dao_fee_amount,
         total_fee_amount: total_fee_amount,
         result: result_swap,
-        price: ((token0_pool + amount)? / (token1_pool - swap_amount)?)?
+        price: calculate_spot_price(pool_type, (token0_pool + amount)?, (token1_pool - swap_amount)?)?
             .clamp_u128()?
             .into(),
     })
 }
 
-pub fn add_address_to_whitelist(storage: &mut impl Storage, address: HumanAddr, env :Env) -> StdResult<HandleResponse>{
+/// Works out how much of `offer.token` has to be put in so that `desired_output`
+/// is received after fees. Returns the required input together with the swap info.
+pub fn calculate_exact_output_swap_result(
+    querier: &impl Querier,
+    settings: &AMMSettings<HumanAddr>,
+    config: &Config<HumanAddr>,
+    offer: &TokenAmount<HumanAddr>,
+    desired_output: Uint128,
+    storage: &impl Storage,
+    trader: HumanAddr,
+    time: Option<u64>,
+) -> StdResult<(Uint128, SwapInfo)> {
+    if !config.pair.contains(&offer.token) {
+        return Err(StdError::generic_err(format!(
+            "The required token {}, is not presented in this contract.",
+            offer.token
+        )));
+    }
+
+    if desired_output.is_zero() {
+        return Err(StdError::generic_err("desired_output must be greater than zero."));
+    }
+
+    if let PoolType::ConcentratedLiquidity { .. } = config.pool_type {
+        return Err(StdError::generic_err(
+            "Exact output swaps are not supported by concentrated liquidity pools.",
+        ));
+    }
+
+    let tokens_pool = get_token_pool_balance(querier, config, offer, true)?;
+    let token0_pool = tokens_pool[0];
+    let token1_pool = tokens_pool[1];
+    let offer_index = config.pair.get_token_index(&offer.token).unwrap();
+    let pool_type = pool_type_for_offer(config.pool_type, offer_index);
+
+    let swap_amount = Uint256::from(desired_output);
+    if swap_amount >= token1_pool {
+        return Err(StdError::generic_err(
+            "Not enough liquidity in the pool to return desired_output.",
+        ));
+    }
+
+    let oracle_price = query_oracle_price(querier, &config.oracle, offer_index, time)?;
+    let (token0_pool, token1_pool) = center_pool_on_oracle(oracle_price, token0_pool, token1_pool)?;
+    let net_input = calculate_swap_input(pool_type, swap_amount, token0_pool, token1_pool)?;
+    assert_oracle_swap(&config.oracle, oracle_price, net_input, swap_amount, tokens_pool[1])?;
+    let spread_amount = calculate_swap_spread(pool_type, net_input, swap_amount, token0_pool, token1_pool)?;
+
+    // fees are charged on the offer, on top of what has to be swapped
+    let discount = query_fee_discount(querier, storage, &trader)?;
+    let required_input = calculate_gross_input(net_input, settings.lp_fee, settings.shade_dao_fee, discount)?;
+    let lp_fee_amount = calculate_fee(required_input, settings.lp_fee, discount)?;
+    let shade_dao_fee_amount = calculate_fee(required_input, settings.shade_dao_fee, discount)?;
+    let total_fee_amount = lp_fee_amount + shade_dao_fee_amount;
+    let result_swap = SwapResult {
+        return_amount: desired_output,
+        spread_amount: spread_amount.clamp_u128()?.into(),
+    };
+
+    Ok((
+        required_input.clamp_u128()?.into(),
+        SwapInfo {
+            lp_fee_amount: lp_fee_amount,
+            shade_dao_fee_amount: shade_dao_fee_amount,
+            total_fee_amount: total_fee_amount,
+            result: result_swap,
+            price: calculate_spot_price(pool_type, (token0_pool + net_input)?, (token1_pool - swap_amount)?)?
+                .clamp_u128()?
+                .into(),
+        },
+    ))
+}
+
+/// Smallest offer which still leaves `net_amount` to swap once both fees are taken off.
+fn calculate_gross_input(
+    net_amount: Uint256,
+    lp_fee: Fee,
+    shade_dao_fee: Fee,
+    discount: u16,
+) -> StdResult<Uint256> {
+    let (fee_nom, denom) = combine_fees(lp_fee, shade_dao_fee, discount)?;
+    let remaining = (denom - fee_nom)
+        .map_err(|_| StdError::generic_err("Configured fees exceed the swapped amount."))?;
+    if remaining == Uint256::zero() {
+        return Err(StdError::generic_err("Configured fees exceed the swapped amount."));
+    }
+
+    let mut gross_amount = ceil_div((net_amount * denom)?, remaining)?;
+    // fees are rounded down separately, make sure the net amount is still covered
+    loop {
+        let fee_amount = calculate_fee(gross_amount, lp_fee, discount)?
+            + calculate_fee(gross_amount, shade_dao_fee, discount)?;
+        if (gross_amount - Uint256::from(fee_amount))? >= net_amount {
+            return Ok(gross_amount);
+        }
+        gross_amount = (gross_amount + Uint256::from(1u128))?;
+    }
+}
+
+fn ceil_div(nominator: Uint256, denominator: Uint256) -> StdResult<Uint256> {
+    Ok((((nominator + denominator)? - Uint256::from(1u128))? / denominator)?)
+}
+
+pub fn set_fee_discounts(
+    storage: &mut impl Storage,
+    env: Env,
+    discounts: Option<FeeDiscounts<HumanAddr>>,
+) -> StdResult<HandleResponse> {
     apply_admin_guard(env.message.sender.clone(), storage)?;
-    add_whitelist_address(storage, address.clone())?;  
+    if let Some(discounts) = &discounts {
+        if discounts.tiers.iter().any(|tier| tier.discount > MAX_FEE_DISCOUNT) {
+            return Err(StdError::generic_err(format!(
+                "Fee discounts can't be more than {} basis points.",
+                MAX_FEE_DISCOUNT
+            )));
+        }
+    }
+    store_fee_discounts(storage, &discounts)?;
     Ok(HandleResponse {
         messages: vec![],
         log: vec![
-            log("action", "save_address_to_whitelist"),
-            log("whitelist_address", address.as_str().clone()),
+            log("action", "set_fee_discounts"),
+            log("tiers", discounts.map_or(0, |discounts| discounts.tiers.len())),
         ],
         data: None,
     })
 }
 
-pub fn remove_address_from_whitelist(storage: &mut impl Storage, list: Vec<HumanAddr>,
-    env :Env) -> StdResult<HandleResponse>{
-    apply_admin_guard(env.message.sender.clone(), storage)?;
-    remove_whitelist_address(storage, list.clone())?;
-    Ok(HandleResponse {
-        messages: vec![],
-        log: vec![log("action", "remove_address_from_whitelist")],
-        data: None,
+/// Fee discount of `trader` in basis points, from their balance of the discount token or
+/// their stake. Traders whose balance or stake can't be read (no or a stale viewing key,
+/// a failing staking contract) get no discount instead of a failed swap.
+pub fn query_fee_discount(
+    querier: &impl Querier,
+    storage: &impl Storage,
+    trader: &HumanAddr,
+) -> StdResult<u16> {
+    let discounts = match load_fee_discounts(storage)? {
+        Some(discounts) => discounts,
+        None => return Ok(0),
+    };
+    let amount = match &discounts.source {
+        FeeDiscountSource::TokenBalance { token } => match load_discount_viewing_key(storage, trader)? {
+            Some(key) => match snip20::balance_query(
+                querier,
+                trader.clone(),
+                key,
+                BLOCK_SIZE,
+                token.code_hash.clone(),
+                token.address.clone(),
+            ) {
+                Ok(balance) => balance.amount,
+                Err(_) => return Ok(0),
+            },
+            None => return Ok(0),
+        },
+        FeeDiscountSource::Stake => {
+            let staking_contract = load_staking_contract(storage)?;
+            if staking_contract.address == HumanAddr::default() {
+                return Ok(0);
+            }
+            let result: StdResult<StakingQueryResponse> = querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+                contract_addr: staking_contract.address,
+                callback_code_hash: staking_contract.code_hash,
+                msg: to_binary(&StakingQueryMsg::GetStakedAmount { staker: trader.clone() })?,
+            }));
+            match result {
+                Ok(StakingQueryResponse::StakedAmount { amount }) => amount,
+                _ => return Ok(0),
+            }
+        }
+    };
+    Ok(discounts.discount_for(amount))
+}
+
+/// Address whose fee discount applies to a swap of `sender`. Only the router of the fee
+/// discounts swaps on behalf of another trader.
+fn discount_trader(
+    storage: &impl Storage,
+    sender: &HumanAddr,
+    trader: Option<HumanAddr>,
+) -> StdResult<HumanAddr> {
+    let router = load_fee_discounts(storage)?.and_then(|discounts| discounts.router);
+    Ok(match trader {
+        Some(trader) if router.as_ref() == Some(sender) => trader,
+        _ => sender.clone(),
     })
 }
 
+/// Reserves of the offered token and of the returned token. The offered amount is already
+/// part of the balance once sent, `offer_in_pool` takes it out again.
 fn get_token_pool_balance(
     querier: &impl Querier,  
     config: &Config<HumanAddr>,
     swap_offer: &TokenAmount<HumanAddr>,
+    offer_in_pool: bool,
 ) -> StdResult<[Uint256; 2]> {
     let tokens_balances = config.pair.query_balances(
         querier,
@@ -574,8 +1397,11 @@ fn get_token_pool_balance(
         config.viewing_key.0.clone(),
     )?;
     let index = config.pair.get_token_index(&swap_offer.token).unwrap();
-    let token0_pool = tokens_balances[index];
+    let mut token0_pool = tokens_balances[index];
     let token1_pool = tokens_balances[index ^ 1];
+    if offer_in_pool {
+        token0_pool = (token0_pool - swap_offer.amount)?;
+    }
 
     // conver tand get avialble balance
     let token0_pool = Uint256::from(token0_pool);
@@ -583,25 +1409,253 @@ fn get_token_pool_balance(
     Ok([token0_pool, token1_pool])
 }
 
+/// The offered amount is already part of the pool balance, take it out to get
+/// the reserves the price was held at before this trade.
+fn record_price_before_swap<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    config: &Config<HumanAddr>,
+    offer: &TokenAmount<HumanAddr>,
+) -> StdResult<()> {
+    let mut reserves = config.pair.query_balances(
+        &deps.querier,
+        config.contract_addr.clone(),
+        config.viewing_key.0.clone(),
+    )?;
+    let index = config.pair.get_token_index(&offer.token).unwrap(); // Safe, checked by the swap calculation
+    reserves[index] = (reserves[index] - offer.amount)?;
+    record_pool_price(&mut deps.storage, env, config.pool_type, &reserves)
+}
+
+/// Adds the spot price of the pool, at the reserves from before the current trade or
+/// liquidity change, to the cumulative prices.
+pub fn record_pool_price(
+    storage: &mut impl Storage,
+    env: &Env,
+    pool_type: PoolType,
+    reserves: &[Uint128; 2],
+) -> StdResult<()> {
+    let prices = pool_prices(storage, pool_type, reserves)?;
+    accumulate_prices(storage, env, prices)
+}
+
+/// Spot prices of token 0 and of token 1, scaled by `TWAP_PRICE_PRECISION`. Weighted pools
+/// hold more value in the heavier token than its balance alone suggests, the price of
+/// stable pools follows their invariant and concentrated pools keep their price themselves.
+/// `None` while the pool has no price.
+fn pool_prices(
+    storage: &impl Storage,
+    pool_type: PoolType,
+    reserves: &[Uint128; 2],
+) -> StdResult<Option<[u128; 2]>> {
+    match pool_type {
+        PoolType::Weighted { weight_0, weight_1 } => reserve_prices(&[
+            Uint128(reserves[0].u128().saturating_mul(weight_1 as u128)),
+            Uint128(reserves[1].u128().saturating_mul(weight_0 as u128)),
+        ]),
+        PoolType::StableSwap { amp } => {
+            if reserves[0].is_zero() || reserves[1].is_zero() {
+                return Ok(None);
+            }
+            let precision = Uint256::from(TWAP_PRICE_PRECISION);
+            let (reserve_0, reserve_1) = (Uint256::from(reserves[0]), Uint256::from(reserves[1]));
+            let price_0 = stable_swap::calculate_spot_price(amp, reserve_0, reserve_1, precision)?;
+            let price_1 = stable_swap::calculate_spot_price(amp, reserve_1, reserve_0, precision)?;
+            Ok(Some([
+                price_0.clamp_u128().unwrap_or(u128::MAX),
+                price_1.clamp_u128().unwrap_or(u128::MAX),
+            ]))
+        }
+        PoolType::ConcentratedLiquidity { .. } => {
+            // the price of token 0 in token 1 is the square of `sqrt_price`
+            let sqrt_price = Uint256::from(load_concentrated_pool(storage)?.sqrt_price);
+            let price = (sqrt_price * sqrt_price)?;
+            let scale = Uint256::from(concentrated::PRICE_PRECISION);
+            let scale = (scale * scale)?;
+            let twap_precision = Uint256::from(TWAP_PRICE_PRECISION);
+            let price_0 = ((price * twap_precision)? / scale)?;
+            let price_1 = ((scale * twap_precision)? / price)?;
+            Ok(Some([
+                price_0.clamp_u128().unwrap_or(u128::MAX),
+                price_1.clamp_u128().unwrap_or(u128::MAX),
+            ]))
+        }
+        PoolType::ConstantProduct => reserve_prices(reserves),
+    }
+}
+
+/// Prices of token 0 and of token 1 as the ratio of the reserves.
+fn reserve_prices(reserves: &[Uint128; 2]) -> StdResult<Option<[u128; 2]>> {
+    if reserves[0].is_zero() || reserves[1].is_zero() {
+        return Ok(None);
+    }
+    Ok(Some([
+        calculate_fixed_point_price(reserves[0], reserves[1])?,
+        calculate_fixed_point_price(reserves[1], reserves[0])?,
+    ]))
+}
+
+/// Adds `prices`, of token 0 and of token 1 scaled by `TWAP_PRICE_PRECISION`, for the time
+/// since the last update to the cumulative prices. `None` while the pool has no price.
+fn accumulate_prices(
+    storage: &mut impl Storage,
+    env: &Env,
+    prices: Option<[u128; 2]>,
+) -> StdResult<()> {
+    let now = env.block.time;
+    let accumulator = match load_price_accumulator(storage)? {
+        Some(accumulator) => advance_accumulator(accumulator, now, prices),
+        None => PriceObservation {
+            block_time: now,
+            price_0_cumulative: Uint128::zero(),
+            price_1_cumulative: Uint128::zero(),
+        },
+    };
+    store_price_accumulator(storage, &accumulator)?;
+
+    let count = load_price_observation_count(storage)?;
+    let is_due = count == 0
+        || load_price_observation(storage, count - 1)?.block_time + PRICE_OBSERVATION_PERIOD
+            <= accumulator.block_time;
+    if is_due {
+        store_price_observation(storage, &accumulator)?;
+    }
+
+    Ok(())
+}
+
+/// The cumulative prices at `now` if `prices` were held since the last update.
+fn advance_accumulator(
+    mut accumulator: PriceObservation,
+    now: u64,
+    prices: Option<[u128; 2]>,
+) -> PriceObservation {
+    let elapsed = now.saturating_sub(accumulator.block_time) as u128;
+    if let Some([price_0, price_1]) = prices.filter(|_| elapsed > 0) {
+        // overflow is desired, only differences between observations are used
+        accumulator.price_0_cumulative = Uint128(
+            accumulator.price_0_cumulative.u128().wrapping_add(price_0.wrapping_mul(elapsed)),
+        );
+        accumulator.price_1_cumulative = Uint128(
+            accumulator.price_1_cumulative.u128().wrapping_add(price_1.wrapping_mul(elapsed)),
+        );
+    }
+    accumulator.block_time = accumulator.block_time.max(now);
+    accumulator
+}
+
+/// Price of the base token expressed in the quote token, scaled by `TWAP_PRICE_PRECISION`.
+/// Prices too large for 128 bits are capped.
+fn calculate_fixed_point_price(base_reserve: Uint128, quote_reserve: Uint128) -> StdResult<u128> {
+    let price = ((Uint256::from(quote_reserve) * Uint256::from(TWAP_PRICE_PRECISION))?
+        / Uint256::from(base_reserve))?;
+    Ok(price.clamp_u128().unwrap_or(u128::MAX))
+}
+
+/// Average prices over the `window` seconds up to `time`. The price held since the last
+/// update is carried forward to `time` and the cumulative prices at the start of the window
+/// are interpolated between the two observations around it.
+fn query_twap<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    window: u64,
+    time: u64,
+) -> QueryResult {
+    if window == 0 {
+        return Err(StdError::generic_err("TWAP window must be greater than zero."));
+    }
+
+    let mut latest = load_price_accumulator(&deps.storage)?
+        .ok_or_else(|| StdError::generic_err("The pair has no price history yet."))?;
+    if time < latest.block_time {
+        return Err(StdError::generic_err(format!(
+            "The TWAP time can't be before the last price update at {}.",
+            latest.block_time
+        )));
+    }
+    if time > latest.block_time {
+        let config = load_config(deps)?;
+        let reserves = config.pair.query_balances(&deps.querier, config.contract_addr, config.viewing_key.0)?;
+        let prices = pool_prices(&deps.storage, config.pool_type, &reserves)?;
+        latest = advance_accumulator(latest, time, prices);
+    }
+    let not_enough_history = || {
+        StdError::generic_err(format!(
+            "Not enough price history to cover a window of {} seconds.",
+            window
+        ))
+    };
+    let window_start = latest.block_time.checked_sub(window).ok_or_else(not_enough_history)?;
+
+    // the observations right before and after the start of the window
+    let count = load_price_observation_count(&deps.storage)?;
+    let mut before = None;
+    let mut after = latest.clone();
+    for index in (count.saturating_sub(PRICE_OBSERVATION_LIMIT)..count).rev() {
+        let observation = load_price_observation(&deps.storage, index)?;
+        if observation.block_time <= window_start {
+            before = Some(observation);
+            break;
+        }
+        after = observation;
+    }
+    let before = before.ok_or_else(not_enough_history)?;
+    let part = (window_start - before.block_time) as u128;
+    let span = (after.block_time - before.block_time) as u128;
+    let cumulative_at_start = |before: Uint128, after: Uint128| -> StdResult<u128> {
+        if part == 0 {
+            return Ok(before.u128());
+        }
+        let delta = Uint256::from(after.u128().wrapping_sub(before.u128()));
+        let passed = ((delta * Uint256::from(part))? / Uint256::from(span))?.clamp_u128()?;
+        Ok(before.u128().wrapping_add(passed))
+    };
+    let start_0 = cumulative_at_start(before.price_0_cumulative, after.price_0_cumulative)?;
+    let start_1 = cumulative_at_start(before.price_1_cumulative, after.price_1_cumulative)?;
+
+    to_binary(&QueryMsgResponse::GetTwap {
+        price_0_average: Uint128(latest.price_0_cumulative.u128().wrapping_sub(start_0) / window as u128),
+        price_1_average: Uint128(latest.price_1_cumulative.u128().wrapping_sub(start_1) / window as u128),
+        window_start,
+        window_end: latest.block_time,
+    })
+}
+
 fn remove_liquidity<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
     amount: Uint128,
+    sender: HumanAddr,
     recipient: HumanAddr,
+    min_amounts: [Option<Uint128>; 2],
+    withdraw_into: Option<TokenType<HumanAddr>>,
 ) -> StdResult<HandleResponse> {    
     let config = load_config(&deps)?;
+    let amm_settings = query_pair_amm_settings(&deps.querier, &config)?;
     let Config {
         pair,
         contract_addr,
         viewing_key,
         lp_token_info,
+        pool_type,
+        oracle,
         ..
     } = config;
 
     let liquidity_pair_contract = query_liquidity_pair_contract(&deps.querier, &lp_token_info)?;
     let pool_balances = pair.query_balances(&deps.querier, contract_addr, viewing_key.0)?;
+    record_pool_price(&mut deps.storage, &env, pool_type, &pool_balances)?;
+    let mut pair_messages: Vec<CosmosMsg> = Vec::with_capacity(6);
+    let shade_dao_liquidity = mint_shade_dao_liquidity(
+        &deps.storage,
+        &amm_settings,
+        pool_type,
+        &pool_balances,
+        liquidity_pair_contract,
+        &lp_token_info,
+        &mut pair_messages,
+    )?;
     let withdraw_amount = Uint256::from(amount);
-    let total_liquidity = Uint256::from(liquidity_pair_contract);
+    let total_liquidity = Uint256::from(liquidity_pair_contract + shade_dao_liquidity);
 
     let mut pool_withdrawn: [Uint128; 2] = [Uint128::zero(), Uint128::zero()];
 
@@ -612,9 +1666,84 @@ fn remove_liquidity<S: Storage, A: Api, Q: Querier>(
             .into();
     }
 
-    let mut pair_messages: Vec<CosmosMsg> = Vec::with_capacity(4);
+    let mut swap_logs = vec![];
+
+    if let Some(withdraw_into) = withdraw_into {
+        let index = pair.get_token_index(&withdraw_into).ok_or_else(|| {
+            StdError::generic_err(format!(
+                "The required token {}, is not presented in this contract.",
+                withdraw_into
+            ))
+        })?;
+        let offer_index = index ^ 1;
+        let offer_token = pair.get_token(offer_index).unwrap().clone();
+        let offer_amount = Uint256::from(pool_withdrawn[offer_index]);
+
+        // Swap the other side against what is left in the pool after the withdrawal.
+        let token0_pool = (Uint256::from(pool_balances[offer_index]) - Uint256::from(pool_withdrawn[offer_index]))?;
+        let token1_pool = (Uint256::from(pool_balances[index]) - Uint256::from(pool_withdrawn[index]))?;
+        let pool_type = pool_type_for_offer(pool_type, offer_index);
+        let oracle_price = query_oracle_price(&deps.querier, &oracle, offer_index, Some(env.block.time))?;
+        let ask_pool = token1_pool;
+        let (token0_pool, token1_pool) = center_pool_on_oracle(oracle_price, token0_pool, token1_pool)?;
+
+        let discount = query_fee_discount(&deps.querier, &deps.storage, &sender)?;
+        let lp_fee_amount = calculate_fee(offer_amount, amm_settings.lp_fee, discount)?;
+        let shade_dao_fee_amount = calculate_fee(offer_amount, amm_settings.shade_dao_fee, discount)?;
+        let total_fee_amount = lp_fee_amount + shade_dao_fee_amount;
+        let net_amount = (offer_amount - Uint256::from(total_fee_amount))?;
+        let swap_amount = calculate_swap_output(pool_type, net_amount, token0_pool, token1_pool)?;
+        assert_oracle_swap(&oracle, oracle_price, net_amount, swap_amount, ask_pool)?;
+        let return_amount: Uint128 = swap_amount.clamp_u128()?.into();
+
+        if shade_dao_fee_amount > Uint128::zero() && transfers_shade_dao_fee(&amm_settings, pool_type) {
+            pair_messages.push(create_shade_dao_fee_msg(
+                &env,
+                &amm_settings,
+                &offer_token,
+                shade_dao_fee_amount,
+            )?);
+        }
+
+        let sell_or_swap = if offer_index == 0 { "Buy" } else { "Sell" };
+        let trade_history = TradeHistory {
+            price: calculate_spot_price(pool_type, (token0_pool + offer_amount)?, (token1_pool - swap_amount)?)?
+                .clamp_u128()?
+                .into(),
+            amount: return_amount,
+            timestamp: env.block.time,
+            direction: sell_or_swap.to_string(),
+            lp_fee_amount,
+            total_fee_amount,
+            shade_dao_fee_amount,
+        };
+        store_trade_history(deps, &trade_history)?;
+
+        swap_logs.push(log("withdraw_into", withdraw_into));
+        swap_logs.push(log("swapped_amount", pool_withdrawn[offer_index]));
+        swap_logs.push(log("return_amount", return_amount));
+        swap_logs.push(log("lp_fee", lp_fee_amount));
+        swap_logs.push(log("shade_dao_fee", shade_dao_fee_amount));
+
+        pool_withdrawn[index] = pool_withdrawn[index] + return_amount;
+        pool_withdrawn[offer_index] = Uint128::zero();
+    }
+
+    for (i, min_amount) in min_amounts.iter().enumerate() {
+        if let Some(min_amount) = min_amount {
+            if pool_withdrawn[i] < *min_amount {
+                return Err(StdError::generic_err(format!(
+                    "Operation fell short of min_amount_{}. Actual: {}, Expected: {}",
+                    i, pool_withdrawn[i], min_amount
+                )));
+            }
+        }
+    }
 
     for (i, token) in pair.into_iter().enumerate() {
+        if pool_withdrawn[i].is_zero() {
+            continue;
+        }
         pair_messages.push(token.create_send_msg(
             env.contract.address.clone(),
             recipient.clone(),
@@ -622,6 +1751,11 @@ fn remove_liquidity<S: Storage, A: Api, Q: Querier>(
         )?);
     }
 
+    store_root_k_after_change(&mut deps.storage, &amm_settings, pool_type, &[
+        (pool_balances[0] - pool_withdrawn[0])?,
+        (pool_balances[1] - pool_withdrawn[1])?,
+    ])?;
+
     pair_messages.push(snip20::burn_msg(
         amount,
         None,
@@ -630,28 +1764,218 @@ fn remove_liquidity<S: Storage, A: Api, Q: Querier>(
         lp_token_info.address,
     )?);
 
-     // unstake
-     let staking_contract = load_staking_contract(deps)?;
-     if staking_contract.address != HumanAddr::default() {
-        pair_messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
-            contract_addr: staking_contract.address.clone(),
-            callback_code_hash: staking_contract.code_hash.to_uppercase().clone(),
-            msg: to_binary(&StakingHandleMsg::Unstake{address: recipient.clone()})?,
-            send: vec![],
-        }));
-     }    
-  
+    let mut logs = vec![
+        log("action", "remove_liquidity"),
+        log("withdrawn_share", amount),
+        log("refund_assets", format!("{}, {}", &pair.0, &pair.1)),
+        log("refund_amounts", format!("{}, {}", pool_withdrawn[0], pool_withdrawn[1])),
+    ];
+    logs.append(&mut swap_logs);
+
     Ok(HandleResponse {
         messages: pair_messages,
-        log: vec![
-            log("action", "remove_liquidity"),
-            log("withdrawn_share", amount),
-            log("refund_assets", format!("{}, {}", &pair.0, &pair.1)),
-        ],
+        log: logs,
         data: None,
     })
 }
 
+/// Output of a swap of `amount` of token 0, priced with the invariant of the pool.
+pub fn calculate_swap_output(
+    pool_type: PoolType,
+    amount: Uint256,
+    token0_pool_balance: Uint256,
+    token1_pool_balance: Uint256,
+) -> StdResult<Uint256> {
+    match pool_type {
+        PoolType::ConstantProduct => calculate_price(amount, token0_pool_balance, token1_pool_balance),
+        PoolType::StableSwap { amp } => {
+            stable_swap::calculate_swap_output(amp, amount, token0_pool_balance, token1_pool_balance)
+        }
+        PoolType::Weighted { weight_0, weight_1 } => {
+            weighted::calculate_swap_output(weight_0, weight_1, amount, token0_pool_balance, token1_pool_balance)
+        }
+        PoolType::ConcentratedLiquidity { .. } => Err(concentrated_pool_error()),
+    }
+}
+
+/// Input of token 0 needed for `amount` of token 1, priced with the invariant of the pool.
+pub fn calculate_swap_input(
+    pool_type: PoolType,
+    amount: Uint256,
+    token0_pool_balance: Uint256,
+    token1_pool_balance: Uint256,
+) -> StdResult<Uint256> {
+    match pool_type {
+        PoolType::ConstantProduct => calculate_required_input(amount, token0_pool_balance, token1_pool_balance),
+        PoolType::StableSwap { amp } => {
+            stable_swap::calculate_swap_input(amp, amount, token0_pool_balance, token1_pool_balance)
+        }
+        PoolType::Weighted { weight_0, weight_1 } => {
+            weighted::calculate_swap_input(weight_0, weight_1, amount, token0_pool_balance, token1_pool_balance)
+        }
+        PoolType::ConcentratedLiquidity { .. } => Err(concentrated_pool_error()),
+    }
+}
+
+fn calculate_swap_spread(
+    pool_type: PoolType,
+    amount: Uint256,
+    swap_amount: Uint256,
+    token0_pool_balance: Uint256,
+    token1_pool_balance: Uint256,
+) -> StdResult<Uint256> {
+    match pool_type {
+        PoolType::ConstantProduct => calculate_spread(amount, token0_pool_balance, token1_pool_balance),
+        PoolType::StableSwap { .. } => Ok(stable_swap::calculate_spread(amount, swap_amount)),
+        PoolType::Weighted { weight_0, weight_1 } => {
+            // what the amount returns at the spot price, before the price moves
+            let ideal_amount = ((((amount * token1_pool_balance)? * Uint256::from(weight_0))?
+                / token0_pool_balance)?
+                / Uint256::from(weight_1))?;
+            Ok((ideal_amount - swap_amount).unwrap_or(Uint256::zero()))
+        }
+        PoolType::ConcentratedLiquidity { .. } => Err(concentrated_pool_error()),
+    }
+}
+
+/// Price of the offered token in the returned token after a trade.
+fn calculate_spot_price(pool_type: PoolType, token0_pool_balance: Uint256, token1_pool_balance: Uint256) -> StdResult<Uint256> {
+    match pool_type {
+        PoolType::Weighted { weight_0, weight_1 } => {
+            weighted::calculate_spot_price(weight_0, weight_1, token0_pool_balance, token1_pool_balance)
+        }
+        _ => token0_pool_balance / token1_pool_balance,
+    }
+}
+
+/// The swap math works on the offered token as token 0, weighted pools have to
+/// swap their weights when token 1 is offered.
+pub fn pool_type_for_offer(pool_type: PoolType, offer_index: usize) -> PoolType {
+    match pool_type {
+        PoolType::Weighted { weight_0, weight_1 } if offer_index == 1 => PoolType::Weighted {
+            weight_0: weight_1,
+            weight_1: weight_0,
+        },
+        _ => pool_type,
+    }
+}
+
+/// Price of the offered token in the returned token reported by the oracle of the pair,
+/// scaled by `ORACLE_PRICE_PRECISION`. `None` unless the pair is oracle pegged. Prices
+/// older than `max_age` at `time` are rejected, simulations without a block time don't
+/// check the age.
+fn query_oracle_price(
+    querier: &impl Querier,
+    oracle: &Option<OracleSettings<HumanAddr>>,
+    offer_index: usize,
+    time: Option<u64>,
+) -> StdResult<Option<Uint256>> {
+    let oracle = match oracle {
+        Some(oracle) => oracle,
+        None => return Ok(None),
+    };
+    let result: OracleQueryResponse = querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        callback_code_hash: oracle.contract.code_hash.clone(),
+        contract_addr: oracle.contract.address.clone(),
+        msg: to_binary(&OracleQueryMsg::GetPrice {})?,
+    }))?;
+    let OracleQueryResponse::GetPrice { price, last_updated } = result;
+    if price.is_zero() {
+        return Err(StdError::generic_err("The oracle did not report a price."));
+    }
+    if let Some(time) = time {
+        if time.saturating_sub(last_updated) > oracle.max_age {
+            return Err(StdError::generic_err(format!(
+                "The oracle price is more than {} seconds old.",
+                oracle.max_age
+            )));
+        }
+    }
+
+    // the oracle reports token 0 in token 1, invert it when token 1 is offered
+    let price = Uint256::from(price);
+    if offer_index == 0 {
+        Ok(Some(price))
+    } else {
+        let precision = Uint256::from(ORACLE_PRICE_PRECISION);
+        Ok(Some(((precision * precision)? / price)?))
+    }
+}
+
+/// Moves the pool balances along the constant product curve until their ratio is the
+/// oracle price, so swaps are priced around it instead of around the last traded price.
+fn center_pool_on_oracle(
+    oracle_price: Option<Uint256>,
+    token0_pool_balance: Uint256,
+    token1_pool_balance: Uint256,
+) -> StdResult<(Uint256, Uint256)> {
+    let price = match oracle_price {
+        Some(price) => price,
+        None => return Ok((token0_pool_balance, token1_pool_balance)),
+    };
+    // x * y stays the same, x' = sqrt(x * y / price) and y' = sqrt(x * y * price)
+    let precision = Uint256::from(ORACLE_PRICE_PRECISION);
+    let token1_in_token0 = ((token1_pool_balance * precision)? / price)?;
+    let token0_in_token1 = ((token0_pool_balance * price)? / precision)?;
+    Ok((
+        (token0_pool_balance * token1_in_token0)?.sqrt()?,
+        (token1_pool_balance * token0_in_token1)?.sqrt()?,
+    ))
+}
+
+fn assert_oracle_settings(pool_type: PoolType, oracle: &OracleSettings<HumanAddr>) -> StdResult<()> {
+    if pool_type != PoolType::ConstantProduct {
+        return Err(StdError::generic_err(
+            "Oracle pegged pairs have to use the constant product pool type.",
+        ));
+    }
+    if oracle.max_deviation == 0 || oracle.max_deviation > 10_000 {
+        return Err(StdError::generic_err(
+            "The oracle max_deviation has to be between 1 and 10000 basis points.",
+        ));
+    }
+    if oracle.max_age == 0 {
+        return Err(StdError::generic_err("The oracle max_age has to be greater than zero."));
+    }
+    Ok(())
+}
+
+/// Oracle pegged swaps have to be covered by the real balance of the pool and execute
+/// within `max_deviation` of the oracle price.
+fn assert_oracle_swap(
+    oracle: &Option<OracleSettings<HumanAddr>>,
+    oracle_price: Option<Uint256>,
+    amount: Uint256,
+    swap_amount: Uint256,
+    ask_pool_balance: Uint256,
+) -> StdResult<()> {
+    let (oracle, price) = match (oracle, oracle_price) {
+        (Some(oracle), Some(price)) => (oracle, price),
+        _ => return Ok(()),
+    };
+    if swap_amount >= ask_pool_balance {
+        return Err(StdError::generic_err("Not enough liquidity in the pool for this swap."));
+    }
+
+    let executed_price = ((swap_amount * Uint256::from(ORACLE_PRICE_PRECISION))? / amount)?;
+    let difference = if executed_price > price {
+        (executed_price - price)?
+    } else {
+        (price - executed_price)?
+    };
+    if ((difference * Uint256::from(10_000u16))? / price)? > Uint256::from(oracle.max_deviation) {
+        return Err(StdError::generic_err(format!(
+            "The swap price deviates more than {} basis points from the oracle price.",
+            oracle.max_deviation
+        )));
+    }
+    Ok(())
+}
+
+fn concentrated_pool_error() -> StdError {
+    StdError::generic_err("Concentrated liquidity pools only take deposits through AddRangeLiquidity.")
+}
+
 pub fn calculate_price(
     amount: Uint256,
     token0_pool_balance: Uint256,
@@ -660,6 +1984,15 @@ pub fn calculate_price(
     Ok(((token1_pool_balance * amount)? / (token0_pool_balance + amount)?)?)
 }
 
+/// Inverse of `calculate_price`, rounded up so the input always covers the requested amount.
+pub fn calculate_required_input(
+    amount: Uint256,
+    token0_pool_balance: Uint256,
+    token1_pool_balance: Uint256,
+) -> StdResult<Uint256> {
+    ceil_div((token0_pool_balance * amount)?, (token1_pool_balance - amount)?)
+}
+
 pub fn calculate_spread(
     amount: Uint256,
     token0_pool_balance: Uint256,
@@ -678,48 +2011,38 @@ fn add_liquidity<S: Storage, A: Api, Q: Querier>(
     slippage: Option<Decimal>,
 ) -> StdResult<HandleResponse> {
     let config = load_config(&deps)?;
+    let amm_settings = query_pair_amm_settings(&deps.querier, &config)?;
     let Config {
         pair,
         contract_addr,
         viewing_key,
         lp_token_info,
+        pool_type,
         ..
     } = config;
 
+    if let PoolType::ConcentratedLiquidity { .. } = pool_type {
+        return Err(concentrated_pool_error());
+    }
+
     if pair != deposit.pair {
         return Err(StdError::generic_err(
             "The provided tokens dont match those managed by the contract.",
         ));
     }
-
-    // let staking_contract = load_staking_contract(&deps)?;
-    let mut pair_messages: Vec<CosmosMsg> = vec![];
-    let mut pool_balances =
-        deposit
-            .pair
-            .query_balances(&deps.querier, contract_addr, viewing_key.0)?;
-    for (i, (amount, token)) in deposit.into_iter().enumerate() {
-        match &token {
-            TokenType::CustomToken {
-                contract_addr,
-                token_code_hash,
-            } => {
-                pair_messages.push(snip20::transfer_from_msg(
-                    env.message.sender.clone(),
-                    env.contract.address.clone(),
-                    amount,
-                    None,
-                    BLOCK_SIZE,
-                    token_code_hash.clone(),
-                    contract_addr.clone(),
-                )?);              
-            }
-            TokenType::NativeToken { .. } => {
-                // If the asset is native token, balance is already increased.
-                // To calculate properly we should subtract user deposit from the pool.
-                token.assert_sent_native_token_balance(&env, amount)?;
-                pool_balances[i] = (pool_balances[i] - amount)?;
-            }
+
+    // let staking_contract = load_staking_contract(&deps)?;
+    let mut pair_messages: Vec<CosmosMsg> = vec![];
+    let mut pool_balances =
+        deposit
+            .pair
+            .query_balances(&deps.querier, contract_addr, viewing_key.0)?;
+    for (i, (amount, token)) in deposit.into_iter().enumerate() {
+        if let TokenType::NativeToken { .. } = &token {
+            // If the asset is native token, balance is already increased.
+            // To calculate properly we should subtract user deposit from the pool.
+            token.assert_sent_native_token_balance(&env, amount)?;
+            pool_balances[i] = (pool_balances[i] - amount)?;
         }
     }
 
@@ -728,24 +2051,69 @@ fn add_liquidity<S: Storage, A: Api, Q: Querier>(
         &[deposit.amount_0, deposit.amount_1],
         &pool_balances,
     )?;
+    record_pool_price(&mut deps.storage, &env, config.pool_type, &pool_balances)?;
 
     let pair_contract_pool_liquidity =
         query_liquidity_pair_contract(&deps.querier, &lp_token_info)?;
+    let pair_contract_pool_liquidity = pair_contract_pool_liquidity + mint_shade_dao_liquidity(
+        &deps.storage,
+        &amm_settings,
+        pool_type,
+        &pool_balances,
+        pair_contract_pool_liquidity,
+        &lp_token_info,
+        &mut pair_messages,
+    )?;
+    // Only the part of the deposit which matches the pool ratio is used, the rest
+    // is refunded (native) or never pulled in (SNIP20).
+    let used_amounts = if pair_contract_pool_liquidity == Uint128::zero() {
+        [deposit.amount_0, deposit.amount_1]
+    } else {
+        calculate_balanced_deposit(&[deposit.amount_0, deposit.amount_1], &pool_balances)?
+    };
+
+    pair_messages.append(&mut create_deposit_msgs(&env, &deposit, &used_amounts)?);
+
     let mut lp_tokens: u128 = u128::MIN;
     if pair_contract_pool_liquidity == Uint128::zero() {
         // If user mints new liquidity pool -> liquidity % = sqrt(x * y) where
-        // x and y is amount of token0 and token1 provided
-        let deposit_token0_amount = Uint256::from(deposit.amount_0);
-        let deposit_token1_amount = Uint256::from(deposit.amount_1);
-        lp_tokens = (deposit_token0_amount * deposit_token1_amount)?
-            .sqrt()?
-            .clamp_u128()?
+        // x and y is amount of token0 and token1 provided, stable pools use the invariant D
+        let deposit_token0_amount = Uint256::from(used_amounts[0]);
+        let deposit_token1_amount = Uint256::from(used_amounts[1]);
+        lp_tokens = match pool_type {
+            PoolType::StableSwap { amp } => {
+                stable_swap::calculate_invariant(amp, deposit_token0_amount, deposit_token1_amount)?
+            }
+            PoolType::Weighted { weight_0, weight_1 } => {
+                weighted::calculate_invariant(weight_0, weight_1, deposit_token0_amount, deposit_token1_amount)?
+            }
+            _ => (deposit_token0_amount * deposit_token1_amount)?.sqrt()?,
+        }
+        .clamp_u128()?;
+        if lp_tokens <= MINIMUM_LIQUIDITY {
+            return Err(StdError::generic_err(format!(
+                "The first deposit has to mint more than {} LP tokens.",
+                MINIMUM_LIQUIDITY
+            )));
+        }
+
+        // Locked in the pair forever, the pair never sends its own LP tokens.
+        pair_messages.push(snip20::mint_msg(
+            env.contract.address.clone(),
+            Uint128(MINIMUM_LIQUIDITY),
+            None,
+            BLOCK_SIZE,
+            lp_token_info.code_hash.clone(),
+            lp_token_info.address.clone(),
+        )?);
+        store_locked_liquidity(&mut deps.storage, Uint128(MINIMUM_LIQUIDITY))?;
+        lp_tokens -= MINIMUM_LIQUIDITY;
     } else {
         // Total % of Pool
         let total_share = Uint256::from(pair_contract_pool_liquidity);
         // Deposit amounts of the tokens
-        let deposit_token0_amount = Uint256::from(deposit.amount_0);
-        let deposit_token1_amount = Uint256::from(deposit.amount_1);
+        let deposit_token0_amount = Uint256::from(used_amounts[0]);
+        let deposit_token1_amount = Uint256::from(used_amounts[1]);
 
         // get token pair balance
         let token0_pool = Uint256::from(pool_balances[0]);
@@ -755,6 +2123,10 @@ fn add_liquidity<S: Storage, A: Api, Q: Querier>(
         let percent_token1_pool = ((deposit_token1_amount * total_share)? / token1_pool)?;
         lp_tokens = std::cmp::min(percent_token0_pool, percent_token1_pool).clamp_u128()?
     };
+    store_root_k_after_change(&mut deps.storage, &amm_settings, pool_type, &[
+        pool_balances[0] + used_amounts[0],
+        pool_balances[1] + used_amounts[1],
+    ])?;
 
     pair_messages.push(snip20::mint_msg(
         env.message.sender.clone(),
@@ -765,27 +2137,512 @@ fn add_liquidity<S: Storage, A: Api, Q: Querier>(
         lp_token_info.address,
     )?);
 
-    let staking_contract = load_staking_contract(deps)?;
-    if staking_contract.address != HumanAddr::default() {
-        pair_messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
-            contract_addr: staking_contract.address.clone(),
-            callback_code_hash: staking_contract.code_hash.to_uppercase().clone(),
-            msg: to_binary(&StakingHandleMsg::Stake{from: env.message.sender.clone(), amount: Uint128(lp_tokens)})?,
-            send: vec![],
-        }));
-    }   
-
     Ok(HandleResponse {
         messages: pair_messages,
         log: vec![
             log("action", "add_liquidity_to_pair_contract"),
             log("assets", format!("{}, {}", deposit.pair.0, deposit.pair.1)),
             log("share_pool", lp_tokens),
+            log("amount_0", used_amounts[0]),
+            log("amount_1", used_amounts[1]),
+            log("refund_amount_0", (deposit.amount_0 - used_amounts[0])?),
+            log("refund_amount_1", (deposit.amount_1 - used_amounts[1])?),
+        ],
+        data: None,
+    })
+}
+
+/// Single sided liquidity provision. The offered amount is already in the pool balance.
+fn zap_in<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    sender: HumanAddr,
+    offer: TokenAmount<HumanAddr>,
+    min_lp_tokens: Option<Uint128>,
+) -> StdResult<HandleResponse> {
+    let config = load_config(&deps)?;
+    if let PoolType::ConcentratedLiquidity { .. } = config.pool_type {
+        return Err(concentrated_pool_error());
+    }
+    if config.oracle.is_some() {
+        return Err(StdError::generic_err(
+            "Single sided deposits are not supported by oracle pegged pairs.",
+        ));
+    }
+    let index = config.pair.get_token_index(&offer.token).ok_or_else(|| {
+        StdError::generic_err(format!(
+            "The required token {}, is not presented in this contract.",
+            offer.token
+        ))
+    })?;
+    let token_out = config.pair.get_token(index ^ 1).unwrap().clone();
+
+    let total_liquidity = query_liquidity_pair_contract(&deps.querier, &config.lp_token_info)?;
+    if total_liquidity.is_zero() {
+        return Err(StdError::generic_err(
+            "Single sided deposits need a pool which already has liquidity.",
+        ));
+    }
+
+    let mut reserves = config.pair.query_balances(
+        &deps.querier,
+        config.contract_addr.clone(),
+        config.viewing_key.0.clone(),
+    )?;
+    reserves[index] = (reserves[index] - offer.amount)?;
+    record_pool_price(&mut deps.storage, &env, config.pool_type, &reserves)?;
+    let pool_type = pool_type_for_offer(config.pool_type, index);
+    let reserve_in = Uint256::from(reserves[index]);
+    let reserve_out = Uint256::from(reserves[index ^ 1]);
+
+    let amm_settings = query_pair_amm_settings(&deps.querier, &config)?;
+    let mut messages = Vec::with_capacity(4);
+    let total_liquidity = total_liquidity + mint_shade_dao_liquidity(
+        &deps.storage,
+        &amm_settings,
+        config.pool_type,
+        &reserves,
+        total_liquidity,
+        &config.lp_token_info,
+        &mut messages,
+    )?;
+    let discount = query_fee_discount(&deps.querier, &deps.storage, &sender)?;
+    let (fee_nom, fee_denom) = combine_fees(amm_settings.lp_fee, amm_settings.shade_dao_fee, discount)?;
+
+    // swap part of the offer through the pool, same as a regular swap
+    let amount = Uint256::from(offer.amount);
+    let swap_in = match pool_type {
+        PoolType::StableSwap { amp } => stable_swap::calculate_zap_swap_amount(
+            amp,
+            amount,
+            reserve_in,
+            reserve_out,
+            fee_nom,
+            fee_denom,
+        )?,
+        PoolType::Weighted { weight_0, weight_1 } => weighted::calculate_zap_swap_amount(
+            weight_0,
+            weight_1,
+            amount,
+            reserve_in,
+            reserve_out,
+            fee_nom,
+            fee_denom,
+        )?,
+        _ => calculate_zap_swap_amount(amount, reserve_in, fee_nom, fee_denom)?,
+    };
+    let lp_fee_amount = calculate_fee(swap_in, amm_settings.lp_fee, discount)?;
+    let shade_dao_fee_amount = calculate_fee(swap_in, amm_settings.shade_dao_fee, discount)?;
+    let total_fee_amount = lp_fee_amount + shade_dao_fee_amount;
+    let net_in = (swap_in - Uint256::from(total_fee_amount))?;
+    let swap_out = calculate_swap_output(pool_type, net_in, reserve_in, reserve_out)?;
+    let swapped_amount: Uint128 = swap_in.clamp_u128()?.into();
+    let return_amount: Uint128 = swap_out.clamp_u128()?.into();
+
+    // the DAO fee only leaves the pool when it is transferred
+    let mut transferred_dao_fee = Uint128::zero();
+    if shade_dao_fee_amount > Uint128::zero() && transfers_shade_dao_fee(&amm_settings, config.pool_type) {
+        messages.push(create_shade_dao_fee_msg(
+            &env,
+            &amm_settings,
+            &offer.token,
+            shade_dao_fee_amount,
+        )?);
+        transferred_dao_fee = shade_dao_fee_amount;
+    }
+
+    let sell_or_swap = if index == 0 { "Buy" } else { "Sell" };
+    let trade_history = TradeHistory {
+        price: calculate_spot_price(pool_type, (reserve_in + swap_in)?, (reserve_out - swap_out)?)?
+            .clamp_u128()?
+            .into(),
+        amount: return_amount,
+        timestamp: env.block.time,
+        direction: sell_or_swap.to_string(),
+        lp_fee_amount,
+        total_fee_amount,
+        shade_dao_fee_amount,
+    };
+    store_trade_history(deps, &trade_history)?;
+
+    // deposit the rest of the offer together with the swap output
+    let deposit_in = (amount - swap_in)?;
+    let pool_in = ((reserve_in + swap_in)? - Uint256::from(transferred_dao_fee))?;
+    let pool_out = (reserve_out - swap_out)?;
+    let total_share = Uint256::from(total_liquidity);
+    let lp_tokens: Uint128 = std::cmp::min(
+        ((deposit_in * total_share)? / pool_in)?,
+        ((swap_out * total_share)? / pool_out)?,
+    )
+    .clamp_u128()?
+    .into();
+
+    if let Some(min_lp_tokens) = min_lp_tokens {
+        if lp_tokens < min_lp_tokens {
+            return Err(StdError::generic_err(format!(
+                "Operation fell short of min_lp_tokens. Actual: {}, Expected: {}",
+                lp_tokens, min_lp_tokens
+            )));
+        }
+    }
+
+    let mut reserves_after = reserves;
+    reserves_after[index] = (reserves[index] + offer.amount - transferred_dao_fee)?;
+    store_root_k_after_change(&mut deps.storage, &amm_settings, config.pool_type, &reserves_after)?;
+
+    messages.push(snip20::mint_msg(
+        sender.clone(),
+        lp_tokens,
+        None,
+        BLOCK_SIZE,
+        config.lp_token_info.code_hash,
+        config.lp_token_info.address,
+    )?);
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![
+            log("action", "zap_in"),
+            log("offer_token", offer.token),
+            log("offer_amount", offer.amount),
+            log("swapped_amount", swapped_amount),
+            log("return_token", token_out),
+            log("return_amount", return_amount),
+            log("lp_fee", lp_fee_amount),
+            log("shade_dao_fee", shade_dao_fee_amount),
+            log("share_pool", lp_tokens),
+        ],
+        data: None,
+    })
+}
+
+/// Pulls the used part of SNIP20 deposits from the sender and refunds what wasn't used of native ones.
+fn create_deposit_msgs(
+    env: &Env,
+    deposit: &TokenPairAmount<HumanAddr>,
+    used_amounts: &[Uint128; 2],
+) -> StdResult<Vec<CosmosMsg>> {
+    let mut messages = vec![];
+    for (i, (amount, token)) in deposit.into_iter().enumerate() {
+        match &token {
+            TokenType::CustomToken {
+                contract_addr,
+                token_code_hash,
+            } => {
+                messages.push(snip20::transfer_from_msg(
+                    env.message.sender.clone(),
+                    env.contract.address.clone(),
+                    used_amounts[i],
+                    None,
+                    BLOCK_SIZE,
+                    token_code_hash.clone(),
+                    contract_addr.clone(),
+                )?);
+            }
+            TokenType::NativeToken { .. } => {
+                let refund_amount = (amount - used_amounts[i])?;
+                if refund_amount > Uint128::zero() {
+                    messages.push(token.create_send_msg(
+                        env.contract.address.clone(),
+                        env.message.sender.clone(),
+                        refund_amount,
+                    )?);
+                }
+            }
+        }
+    }
+    Ok(messages)
+}
+
+fn concentrated_tick_spacing(config: &Config<HumanAddr>) -> StdResult<u16> {
+    match config.pool_type {
+        PoolType::ConcentratedLiquidity { tick_spacing, .. } => Ok(tick_spacing),
+        _ => Err(StdError::generic_err("This pair is not a concentrated liquidity pool.")),
+    }
+}
+
+/// Swap through the ticks of a concentrated liquidity pool. Both fees are taken from the
+/// input, the LP fee stays in the pool for the positions in range.
+fn calculate_concentrated_swap_result(
+    querier: &impl Querier,
+    settings: &AMMSettings<HumanAddr>,
+    config: &Config<HumanAddr>,
+    offer: &TokenAmount<HumanAddr>,
+    storage: &impl Storage,
+    trader: HumanAddr,
+) -> StdResult<(SwapInfo, ConcentratedSwap)> {
+    let index = config.pair.get_token_index(&offer.token).ok_or_else(|| {
+        StdError::generic_err(format!(
+            "The required token {}, is not presented in this contract.",
+            offer.token
+        ))
+    })?;
+    let zero_for_one = index == 0;
+    let pool = load_concentrated_pool(storage)?;
+
+    let discount = query_fee_discount(querier, storage, &trader)?;
+    let shade_dao_fee_amount = calculate_fee(Uint256::from(offer.amount), settings.shade_dao_fee, discount)?;
+    let (lp_fee_nom, lp_fee_denom) = discounted_fee(settings.lp_fee, discount)?;
+    let amount = (Uint256::from(offer.amount) - Uint256::from(shade_dao_fee_amount))?;
+    let concentrated_swap = concentrated::compute_swap(storage, &pool, amount, zero_for_one, lp_fee_nom, lp_fee_denom)?;
+    let lp_fee_amount: Uint128 = concentrated_swap.lp_fee_amount.clamp_u128()?.into();
+    let return_amount = concentrated_swap.amount_out;
+
+    // offer token per return token, `sqrt_price` is token 1 per token 0
+    let precision = Uint256::from(concentrated::PRICE_PRECISION);
+    let offer_price = |sqrt_price: Uint256, amount: Uint256| -> StdResult<Uint256> {
+        if zero_for_one {
+            ((((amount * precision)? / sqrt_price)? * precision)? / sqrt_price)
+        } else {
+            ((((amount * sqrt_price)? / precision)? * sqrt_price)? / precision)
+        }
+    };
+    // what the offer would have returned without moving the price
+    let spread_amount = (offer_price(Uint256::from(pool.sqrt_price), amount)? - return_amount)
+        .unwrap_or(Uint256::zero());
+
+    let swap_info = SwapInfo {
+        lp_fee_amount,
+        shade_dao_fee_amount,
+        total_fee_amount: lp_fee_amount + shade_dao_fee_amount,
+        result: SwapResult {
+            return_amount: return_amount.clamp_u128()?.into(),
+            spread_amount: spread_amount.clamp_u128()?.into(),
+        },
+        price: offer_price(Uint256::from(concentrated_swap.pool.sqrt_price), Uint256::from(1u8))?
+            .clamp_u128()?
+            .into(),
+    };
+    Ok((swap_info, concentrated_swap))
+}
+
+fn add_range_liquidity<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    deposit: TokenPairAmount<HumanAddr>,
+    lower_tick: i32,
+    upper_tick: i32,
+    min_liquidity: Option<Uint128>,
+) -> StdResult<HandleResponse> {
+    let config = load_config(&deps)?;
+    let tick_spacing = concentrated_tick_spacing(&config)?;
+    if config.pair != deposit.pair {
+        return Err(StdError::generic_err(
+            "The provided tokens dont match those managed by the contract.",
+        ));
+    }
+    concentrated::assert_ticks(lower_tick, upper_tick, tick_spacing)?;
+    deposit.assert_sent_native_token_balance(&env)?;
+
+    let mut pool = load_concentrated_pool(&deps.storage)?;
+    let sqrt_price = Uint256::from(pool.sqrt_price);
+    let sqrt_price_lower = concentrated::sqrt_price_at_tick(lower_tick)?;
+    let sqrt_price_upper = concentrated::sqrt_price_at_tick(upper_tick)?;
+    let liquidity: Uint128 = concentrated::liquidity_for_amounts(
+        sqrt_price,
+        sqrt_price_lower,
+        sqrt_price_upper,
+        Uint256::from(deposit.amount_0),
+        Uint256::from(deposit.amount_1),
+    )?
+    .clamp_u128()?
+    .into();
+    if liquidity.is_zero() {
+        return Err(StdError::generic_err(
+            "The deposit is too small to provide liquidity in this range.",
+        ));
+    }
+    if let Some(min_liquidity) = min_liquidity {
+        if liquidity < min_liquidity {
+            return Err(StdError::generic_err(format!(
+                "Operation fell short of min_liquidity. Actual: {}, Expected: {}",
+                liquidity, min_liquidity
+            )));
+        }
+    }
+
+    let used_amounts = concentrated::deposit_amounts_for_liquidity(
+        sqrt_price,
+        sqrt_price_lower,
+        sqrt_price_upper,
+        liquidity,
+        [deposit.amount_0, deposit.amount_1],
+    )?;
+    let messages = create_deposit_msgs(&env, &deposit, &used_amounts)?;
+
+    let owner = deps.api.canonical_address(&env.message.sender)?;
+    let mut position = load_position(&deps.storage, &owner, lower_tick, upper_tick)?.unwrap_or_default();
+    concentrated::update_position(&mut deps.storage, &mut pool, &mut position, lower_tick, upper_tick, liquidity, true)?;
+    store_position(&mut deps.storage, &owner, lower_tick, upper_tick, &position)?;
+    store_concentrated_pool(&mut deps.storage, &pool)?;
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![
+            log("action", "add_range_liquidity"),
+            log("lower_tick", lower_tick),
+            log("upper_tick", upper_tick),
+            log("liquidity", liquidity),
+            log("amount_0", used_amounts[0]),
+            log("amount_1", used_amounts[1]),
+        ],
+        data: None,
+    })
+}
+
+/// Withdraws `liquidity` from the position of the sender together with all the fees
+/// it earned. A zero `liquidity` only collects the fees.
+fn remove_range_liquidity<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    lower_tick: i32,
+    upper_tick: i32,
+    liquidity: Uint128,
+) -> StdResult<HandleResponse> {
+    let config = load_config(&deps)?;
+    concentrated_tick_spacing(&config)?;
+    let owner = deps.api.canonical_address(&env.message.sender)?;
+    let mut position = load_position(&deps.storage, &owner, lower_tick, upper_tick)?
+        .ok_or_else(|| StdError::generic_err("There is no position between these ticks."))?;
+    if liquidity > position.liquidity {
+        return Err(StdError::generic_err(format!(
+            "Not enough liquidity in the position. Actual: {}, Expected: {}",
+            position.liquidity, liquidity
+        )));
+    }
+
+    let mut pool = load_concentrated_pool(&deps.storage)?;
+    let amounts = concentrated::amounts_for_liquidity(
+        Uint256::from(pool.sqrt_price),
+        concentrated::sqrt_price_at_tick(lower_tick)?,
+        concentrated::sqrt_price_at_tick(upper_tick)?,
+        Uint256::from(liquidity),
+        false,
+    )?;
+    concentrated::update_position(&mut deps.storage, &mut pool, &mut position, lower_tick, upper_tick, liquidity, false)?;
+    let fees = [position.tokens_owed_0, position.tokens_owed_1];
+    let mut withdrawn = [Uint128::zero(); 2];
+    for i in 0..2 {
+        withdrawn[i] = Uint128::from(amounts[i].clamp_u128()?) + fees[i];
+    }
+    position.tokens_owed_0 = Uint128::zero();
+    position.tokens_owed_1 = Uint128::zero();
+    if position.liquidity.is_zero() {
+        remove_position(&mut deps.storage, &owner, lower_tick, upper_tick);
+    } else {
+        store_position(&mut deps.storage, &owner, lower_tick, upper_tick, &position)?;
+    }
+    store_concentrated_pool(&mut deps.storage, &pool)?;
+
+    let mut messages = vec![];
+    for (i, token) in config.pair.into_iter().enumerate() {
+        if withdrawn[i].is_zero() {
+            continue;
+        }
+        messages.push(token.create_send_msg(
+            env.contract.address.clone(),
+            env.message.sender.clone(),
+            withdrawn[i],
+        )?);
+    }
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![
+            log("action", "remove_range_liquidity"),
+            log("lower_tick", lower_tick),
+            log("upper_tick", upper_tick),
+            log("liquidity", liquidity),
+            log("amount_0", withdrawn[0]),
+            log("amount_1", withdrawn[1]),
+            log("fees_0", fees[0]),
+            log("fees_1", fees[1]),
         ],
         data: None,
     })
 }
 
+fn query_position<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    owner: HumanAddr,
+    lower_tick: i32,
+    upper_tick: i32,
+) -> QueryResult {
+    let pool = load_concentrated_pool(&deps.storage)?;
+    let owner = deps.api.canonical_address(&owner)?;
+    let mut position = load_position(&deps.storage, &owner, lower_tick, upper_tick)?.unwrap_or_default();
+    concentrated::settle_position_fees(
+        &pool,
+        &mut position,
+        lower_tick,
+        &load_tick(&deps.storage, lower_tick)?,
+        upper_tick,
+        &load_tick(&deps.storage, upper_tick)?,
+    )?;
+    let amounts = concentrated::amounts_for_liquidity(
+        Uint256::from(pool.sqrt_price),
+        concentrated::sqrt_price_at_tick(lower_tick)?,
+        concentrated::sqrt_price_at_tick(upper_tick)?,
+        Uint256::from(position.liquidity),
+        false,
+    )?;
+
+    to_binary(&QueryMsgResponse::GetPosition {
+        liquidity: position.liquidity,
+        amount_0: amounts[0].clamp_u128()?.into(),
+        amount_1: amounts[1].clamp_u128()?.into(),
+        fees_owed_0: position.tokens_owed_0,
+        fees_owed_1: position.tokens_owed_1,
+    })
+}
+
+/// Sum of the two fees as a single nom/denom pair.
+fn combine_fees(fee_a: Fee, fee_b: Fee, discount: u16) -> StdResult<(Uint256, Uint256)> {
+    let (nom_a, denom_a) = discounted_fee(fee_a, discount)?;
+    let (nom_b, denom_b) = discounted_fee(fee_b, discount)?;
+    let nom = ((nom_a * denom_b)? + (nom_b * denom_a)?)?;
+    let denom = (denom_a * denom_b)?;
+    Ok((nom, denom))
+}
+
+/// Part of a single sided deposit which has to be swapped so that the rest and the
+/// swap output match the pool ratio afterwards, with `fee_nom / fee_denom` taken by the swap:
+/// `(sqrt(r * (r * (2 - f)^2 + 4 * a * (1 - f))) - r * (2 - f)) / (2 * (1 - f))`
+pub fn calculate_zap_swap_amount(
+    amount: Uint256,
+    reserve_in: Uint256,
+    fee_nom: Uint256,
+    fee_denom: Uint256,
+) -> StdResult<Uint256> {
+    let two = Uint256::from(2u8);
+    let net = (fee_denom - fee_nom)?;
+    let factor = ((two * fee_denom)? - fee_nom)?;
+    let reserve_factor = (reserve_in * factor)?;
+    let radicand = (reserve_in
+        * ((reserve_factor * factor)? + (((Uint256::from(4u8) * amount)? * net)? * fee_denom)?)?)?;
+    (radicand.sqrt()? - reserve_factor)? / (two * net)?
+}
+
+/// Largest part of the deposit which matches the ratio of the pools.
+pub fn calculate_balanced_deposit(
+    deposits: &[Uint128; 2],
+    pools: &[Uint128; 2],
+) -> StdResult<[Uint128; 2]> {
+    let amount_1_optimal: Uint128 = ((Uint256::from(deposits[0]) * Uint256::from(pools[1]))?
+        / Uint256::from(pools[0]))?
+        .clamp_u128()?
+        .into();
+    if amount_1_optimal <= deposits[1] {
+        return Ok([deposits[0], amount_1_optimal]);
+    }
+
+    let amount_0_optimal: Uint128 = ((Uint256::from(deposits[1]) * Uint256::from(pools[0]))?
+        / Uint256::from(pools[1]))?
+        .clamp_u128()?
+        .into();
+    Ok([amount_0_optimal, deposits[1]])
+}
+
 fn assert_slippage_acceptance(
     slippage: Option<Decimal>,
     deposits: &[Uint128; 2],
@@ -851,12 +2708,34 @@ fn query_factory_amm_settings(
     }
 }
 
+/// Factory settings with the fees replaced by the custom fee of the pair, if it has one.
+fn query_pair_amm_settings(
+    querier: &impl Querier,
+    config: &Config<HumanAddr>,
+) -> StdResult<AMMSettings<HumanAddr>> {
+    let settings = query_factory_amm_settings(querier, config.factory_info.clone())?;
+    let settings = apply_custom_fee(settings, config.custom_fee);
+    settings.validate()?;
+    Ok(settings)
+}
+
+fn apply_custom_fee(
+    mut settings: AMMSettings<HumanAddr>,
+    custom_fee: Option<CustomFee>,
+) -> AMMSettings<HumanAddr> {
+    if let Some(custom_fee) = custom_fee {
+        settings.lp_fee = custom_fee.lp_fee;
+        settings.shade_dao_fee = custom_fee.shade_dao_fee;
+    }
+    settings
+}
+
 fn query_claim_rewards<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,   
     staker: HumanAddr,
     time: u128
-) -> StdResult<Uint128>{
-    let staking_contract = load_staking_contract(deps)?;
+) -> StdResult<Vec<TokenAmount<HumanAddr>>>{
+    let staking_contract = load_staking_contract(&deps.storage)?;
     if staking_contract.address.clone() != HumanAddr::default() {
         let result: StakingQueryResponse = deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
             callback_code_hash: staking_contract.code_hash,
@@ -865,14 +2744,14 @@ fn query_claim_rewards<S: Storage, A: Api, Q: Querier>(
         }))?;
     
         return match result {
-            StakingQueryResponse::ClaimReward { amount } => Ok(amount),
+            StakingQueryResponse::ClaimReward { rewards } => Ok(rewards),
             _ => Err(StdError::generic_err(
                 "An error occurred while trying to retrieve factory settings.",
             ))
         }
     }
 
-    Ok(Uint128(0u128))
+    Ok(vec![])
 }
 
 
@@ -888,14 +2767,27 @@ fn receiver_callback<S: Storage, A: Api, Q: Querier>(
     })?;
 
     let config = load_config(deps)?;
+    let msg: InvokeMsg = from_binary(&msg)?;
+    match &msg {
+        InvokeMsg::SwapTokens { .. }
+        | InvokeMsg::SwapTokensForExactOutput { .. }
+        | InvokeMsg::ZapIn { .. } => apply_swap_guard(&deps.storage)?,
+        // withdrawing into a single token swaps against the pool
+        InvokeMsg::RemoveLiquidity { withdraw_into, .. } => if withdraw_into.is_some() {
+            apply_swap_guard(&deps.storage)?
+        },
+    }
 
-    match from_binary(&msg)? {
+    match msg {
         InvokeMsg::SwapTokens {
             to,
             expected_return,
             router_link,
             callback_signature,
+            deadline,
+            trader,
         } => {
+            assert_deadline(&env, &deadline)?;
             for token in config.pair.into_iter() {
                 match token {
                     TokenType::CustomToken { contract_addr, .. } => {
@@ -915,6 +2807,41 @@ fn receiver_callback<S: Storage, A: Api, Q: Querier>(
                                 expected_return,
                                 router_link,
                                 callback_signature,
+                                trader,
+                            );
+                        }
+                    }
+                    _ => continue,
+                }
+            }
+
+            Err(StdError::unauthorized())
+        }
+        InvokeMsg::SwapTokensForExactOutput {
+            desired_output,
+            max_input,
+            to,
+            deadline,
+        } => {
+            assert_deadline(&env, &deadline)?;
+            for token in config.pair.into_iter() {
+                match token {
+                    TokenType::CustomToken { contract_addr, .. } => {
+                        if *contract_addr == env.message.sender {
+                            let offer = TokenAmount {
+                                token: token.clone(),
+                                amount,
+                            };
+
+                            return swap_for_exact_output(
+                                deps,
+                                env,
+                                config,
+                                from,
+                                to,
+                                offer,
+                                desired_output,
+                                max_input,
                             );
                         }
                     }
@@ -924,11 +2851,43 @@ fn receiver_callback<S: Storage, A: Api, Q: Querier>(
 
             Err(StdError::unauthorized())
         }
-        InvokeMsg::RemoveLiquidity { recipient } => {
+        InvokeMsg::ZapIn { min_lp_tokens } => {
+            for token in config.pair.into_iter() {
+                match token {
+                    TokenType::CustomToken { contract_addr, .. } => {
+                        if *contract_addr == env.message.sender {
+                            let offer = TokenAmount {
+                                token: token.clone(),
+                                amount,
+                            };
+
+                            return zap_in(deps, env, from, offer, min_lp_tokens);
+                        }
+                    }
+                    _ => continue,
+                }
+            }
+
+            Err(StdError::unauthorized())
+        }
+        InvokeMsg::RemoveLiquidity {
+            recipient,
+            min_amount_0,
+            min_amount_1,
+            withdraw_into,
+        } => {
             if config.lp_token_info.address != env.message.sender {
                 return Err(StdError::unauthorized());
             }
-            remove_liquidity(deps, env, amount, recipient)
+            remove_liquidity(
+                deps,
+                env,
+                amount,
+                from,
+                recipient,
+                [min_amount_0, min_amount_1],
+                withdraw_into,
+            )
         }
     }
 }
@@ -1,6 +1,9 @@
 pub mod contract;
 pub mod state;
 pub mod help_math;
+pub mod stable_swap;
+pub mod concentrated;
+pub mod weighted;
 #[cfg(test)] mod test;
 
 #[cfg(target_arch = "wasm32")]
@@ -0,0 +1,168 @@
+use shadeswap_shared::fadroma::{
+    scrt::{StdError, StdResult},
+    scrt_uint256::Uint256,
+};
+
+/// Highest amplification coefficient a pair can be created with.
+pub const MAX_AMP: u64 = 1_000_000;
+/// Newton iterations before giving up on the invariant converging.
+const MAX_ITERATIONS: u8 = 255;
+/// Coins in the pool, the invariant below is written out for `n = 2`.
+const N_COINS: u8 = 2;
+
+pub fn assert_amp(amp: u64) -> StdResult<()> {
+    if amp == 0 || amp > MAX_AMP {
+        return Err(StdError::generic_err(format!(
+            "The amplification coefficient has to be between 1 and {}.",
+            MAX_AMP
+        )));
+    }
+    Ok(())
+}
+
+/// `A * n^n`
+fn amp_factor(amp: u64) -> Uint256 {
+    Uint256::from(amp as u128 * 4)
+}
+
+fn abs_diff(a: Uint256, b: Uint256) -> StdResult<Uint256> {
+    if a > b {
+        a - b
+    } else {
+        b - a
+    }
+}
+
+/// Solves the StableSwap invariant `A * n^n * S + D = A * D * n^n + D^(n + 1) / (n^n * x * y)`
+/// for `D` with Newton's method.
+pub fn calculate_invariant(amp: u64, x: Uint256, y: Uint256) -> StdResult<Uint256> {
+    let sum = (x + y)?;
+    if sum == Uint256::zero() {
+        return Ok(Uint256::zero());
+    }
+    if x == Uint256::zero() || y == Uint256::zero() {
+        return Err(StdError::generic_err("StableSwap pools need both reserves to be set."));
+    }
+
+    let n = Uint256::from(N_COINS);
+    let ann = amp_factor(amp);
+    let mut d = sum;
+    for _ in 0..MAX_ITERATIONS {
+        // D^3 / (n^n * x * y)
+        let d_product = ((((d * d)? / (x * n)?)? * d)? / (y * n)?)?;
+        let previous = d;
+        let nominator = ((((ann * sum)? + (d_product * n)?)?) * d)?;
+        let denominator = ((((ann - Uint256::from(1u8))? * d)?) + (d_product * Uint256::from(N_COINS + 1))?)?;
+        d = (nominator / denominator)?;
+        if abs_diff(d, previous)? <= Uint256::from(1u8) {
+            return Ok(d);
+        }
+    }
+
+    Err(StdError::generic_err("StableSwap invariant did not converge."))
+}
+
+/// Balance of the other side of the pool which keeps the invariant at `d` when one
+/// side holds `x`.
+pub fn calculate_y(amp: u64, x: Uint256, d: Uint256) -> StdResult<Uint256> {
+    if x == Uint256::zero() {
+        return Err(StdError::generic_err("StableSwap pools need both reserves to be set."));
+    }
+
+    let n = Uint256::from(N_COINS);
+    let ann = amp_factor(amp);
+    // c = D^3 / (n^n * x * Ann), b = x + D / Ann
+    let c = ((((d * d)? / (x * n)?)? * d)? / (ann * n)?)?;
+    let b = (x + (d / ann)?)?;
+    let mut y = d;
+    for _ in 0..MAX_ITERATIONS {
+        let previous = y;
+        let denominator = ((((y * n)? + b)?) - d)?;
+        y = (((y * y)? + c)? / denominator)?;
+        if abs_diff(y, previous)? <= Uint256::from(1u8) {
+            return Ok(y);
+        }
+    }
+
+    Err(StdError::generic_err("StableSwap invariant did not converge."))
+}
+
+/// Amount of token 1 received for putting `amount` of token 0 into the pool, rounded down.
+pub fn calculate_swap_output(
+    amp: u64,
+    amount: Uint256,
+    token0_pool_balance: Uint256,
+    token1_pool_balance: Uint256,
+) -> StdResult<Uint256> {
+    let d = calculate_invariant(amp, token0_pool_balance, token1_pool_balance)?;
+    let y = calculate_y(amp, (token0_pool_balance + amount)?, d)?;
+    // One unit less so rounding in the invariant always goes in favour of the pool.
+    Ok(((token1_pool_balance - y)? - Uint256::from(1u8)).unwrap_or(Uint256::zero()))
+}
+
+/// Amount of token 0 which has to go into the pool to get `amount` of token 1 out, rounded up.
+pub fn calculate_swap_input(
+    amp: u64,
+    amount: Uint256,
+    token0_pool_balance: Uint256,
+    token1_pool_balance: Uint256,
+) -> StdResult<Uint256> {
+    let d = calculate_invariant(amp, token0_pool_balance, token1_pool_balance)?;
+    let x = calculate_y(amp, (token1_pool_balance - amount)?, d)?;
+    ((x - token0_pool_balance)? + Uint256::from(1u8))
+}
+
+/// Price of token 0 in token 1 for an infinitely small trade, scaled by `precision`. It is
+/// the ratio of the partial derivatives of the invariant, `(Ann + D_P / x) / (Ann + D_P / y)`
+/// with `D_P = D^(n + 1) / (n^n * x * y)`.
+pub fn calculate_spot_price(
+    amp: u64,
+    token0_pool_balance: Uint256,
+    token1_pool_balance: Uint256,
+    precision: Uint256,
+) -> StdResult<Uint256> {
+    let (x, y) = (token0_pool_balance, token1_pool_balance);
+    let d = calculate_invariant(amp, x, y)?;
+    let n = Uint256::from(N_COINS);
+    let ann = amp_factor(amp);
+    let d_product = ((((d * d)? / (x * n)?)? * d)? / (y * n)?)?;
+    let marginal_x = ((((ann * x)? + d_product)? * precision)? / x)?;
+    let marginal_y = ((((ann * y)? + d_product)? * precision)? / y)?;
+    (marginal_x * precision)? / marginal_y
+}
+
+/// Pegged assets are expected to trade 1:1, the spread is how much less than that is returned.
+pub fn calculate_spread(amount: Uint256, swap_amount: Uint256) -> Uint256 {
+    (amount - swap_amount).unwrap_or(Uint256::zero())
+}
+
+/// Part of a single sided deposit which has to be swapped so that the rest and the
+/// swap output match the pool ratio afterwards. There is no closed form like for the
+/// constant product, so it is found by bisection.
+pub fn calculate_zap_swap_amount(
+    amp: u64,
+    amount: Uint256,
+    reserve_in: Uint256,
+    reserve_out: Uint256,
+    fee_nom: Uint256,
+    fee_denom: Uint256,
+) -> StdResult<Uint256> {
+    let d = calculate_invariant(amp, reserve_in, reserve_out)?;
+    let mut low = Uint256::zero();
+    let mut high = amount;
+    while low < high {
+        let swap_in = ((low + high)? / Uint256::from(2u8))?;
+        let net_in = (swap_in - ((swap_in * fee_nom)? / fee_denom)?)?;
+        let y = calculate_y(amp, (reserve_in + net_in)?, d)?;
+        let swap_out = (reserve_out - y)?;
+        // rest of the deposit against the pool ratio after the swap
+        let deposit_ratio = ((amount - swap_in)? * (reserve_out - swap_out)?)?;
+        let pool_ratio = (swap_out * (reserve_in + swap_in)?)?;
+        if deposit_ratio > pool_ratio {
+            low = (swap_in + Uint256::from(1u8))?;
+        } else {
+            high = swap_in;
+        }
+    }
+    Ok(low)
+}
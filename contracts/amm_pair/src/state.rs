@@ -6,11 +6,13 @@ use shadeswap_shared::{
             Api, CanonicalAddr, Extern, HumanAddr, Uint128,
             Querier, StdResult, Storage, StdError
         },
-        scrt_storage::{load, save, ns_save, ns_load},
+        scrt_storage::{load, save, ns_save, ns_load, ns_remove},
         scrt_vk::ViewingKey,
     },
-    token_pair::TokenPair
+    token_pair::TokenPair,
+    amm_pair::{CustomFee, FeeDiscounts, OracleSettings, PoolType},
 };
+pub use shadeswap_shared::amm_pair::PriceObservation;
 
 use serde::{Deserialize, Serialize};
 
@@ -21,8 +23,25 @@ pub static CONFIG_KEY: &[u8] = b"config";
 pub static STAKINGCONTRACT_LINK: &[u8] = b"staking_contract_link";
 pub static TRADE_COUNT: &[u8] = b"tradecount";
 pub static TRADE_HISTORY: &[u8] = b"trade_history";
-pub static WHITELIST: &[u8] = b"whitelist";
+pub static FEE_DISCOUNTS: &[u8] = b"fee_discounts";
+pub static DISCOUNT_VIEWING_KEYS: &[u8] = b"discount_viewing_keys";
+pub static PRICE_ACCUMULATOR: &[u8] = b"price_accumulator";
+pub static PRICE_OBSERVATIONS: &[u8] = b"price_observations";
+pub static PRICE_OBSERVATION_COUNT: &[u8] = b"price_observation_count";
+pub static LOCKED_LIQUIDITY: &[u8] = b"locked_liquidity";
+pub static ROOT_K_LAST: &[u8] = b"root_k_last";
+pub static CONCENTRATED_POOL: &[u8] = b"concentrated_pool";
+pub static CONCENTRATED_TICKS: &[u8] = b"concentrated_ticks";
+pub static TICK_BITMAP: &[u8] = b"tick_bitmap";
+pub static TICK_BITMAP_SUMMARY: &[u8] = b"tick_bitmap_summary";
+pub static POSITIONS: &[u8] = b"positions";
+pub static MIGRATION: &[u8] = b"migration";
 pub const BLOCK_SIZE: usize = 256;
+/// Number of observations kept for the TWAP, the oldest one gets overwritten first.
+pub const PRICE_OBSERVATION_LIMIT: u64 = 144;
+/// Minimum number of seconds between two stored observations, with the limit above
+/// the observations cover at least one day.
+pub const PRICE_OBSERVATION_PERIOD: u64 = 600;
 
 #[derive(Serialize, Deserialize,  PartialEq, Debug)]
 pub struct Config<A: Clone> {
@@ -31,6 +50,11 @@ pub struct Config<A: Clone> {
     pub pair:      TokenPair<A>,
     pub contract_addr: A,
     pub viewing_key: ViewingKey,
+    /// Replaces the factory fees for this pair when set.
+    pub custom_fee: Option<CustomFee>,
+    pub pool_type: PoolType,
+    /// Set for oracle pegged pairs, the curve is centered on the oracle price.
+    pub oracle: Option<OracleSettings<A>>,
 }
 
 impl Canonize<Config<CanonicalAddr>> for Config<HumanAddr> {
@@ -41,6 +65,9 @@ impl Canonize<Config<CanonicalAddr>> for Config<HumanAddr> {
             pair:          self.pair.canonize(api)?,
             contract_addr: self.contract_addr.canonize(api)?,
             viewing_key:   self.viewing_key.clone(),
+            custom_fee:    self.custom_fee,
+            pool_type:     self.pool_type,
+            oracle:        self.oracle.as_ref().map(|oracle| oracle.canonize(api)).transpose()?,
         })
     }
 }
@@ -52,10 +79,54 @@ impl Humanize<Config<HumanAddr>> for Config<CanonicalAddr> {
             pair:          self.pair.humanize(api)?,
             contract_addr: self.contract_addr.humanize(api)?,
             viewing_key:   self.viewing_key.clone(),
+            custom_fee:    self.custom_fee,
+            pool_type:     self.pool_type,
+            oracle:        self.oracle.as_ref().map(|oracle| oracle.humanize(api)).transpose()?,
         })
     }
 }
 
+/// Price and in range liquidity of a concentrated liquidity pool. Fee growth is per
+/// unit of liquidity, scaled by `PRICE_PRECISION` and wraps around like the TWAP accumulators.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ConcentratedPool {
+    pub sqrt_price: Uint128,
+    pub tick: i32,
+    pub liquidity: Uint128,
+    pub fee_growth_global_0: Uint128,
+    pub fee_growth_global_1: Uint128,
+}
+
+/// Liquidity of the positions starting (`liquidity_lower`) and ending (`liquidity_upper`)
+/// at a tick, together with the fee growth on the other side of the current price.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct TickInfo {
+    pub liquidity_gross: Uint128,
+    pub liquidity_lower: Uint128,
+    pub liquidity_upper: Uint128,
+    pub fee_growth_outside_0: Uint128,
+    pub fee_growth_outside_1: Uint128,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct Position {
+    pub liquidity: Uint128,
+    pub fee_growth_inside_0_last: Uint128,
+    pub fee_growth_inside_1_last: Uint128,
+    pub tokens_owed_0: Uint128,
+    pub tokens_owed_1: Uint128,
+}
+
+/// Set once the factory starts upgrading the pair, from then on it only waits for its
+/// replacement to call back with `key`.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Migration {
+    pub key: String,
+    pub new_pair: Option<HumanAddr>,
+    /// Code hash of the replacement, handed to the staking contract.
+    pub new_code_hash: String,
+}
+
 pub mod tradehistory{
     use super::*;
     use shadeswap_shared::fadroma::Humanize;
@@ -112,10 +183,10 @@ pub mod amm_pair_storage{
         Ok(count)
     }
 
-    pub fn load_staking_contract<S: Storage, A: Api, Q: Querier>(
-        deps: &Extern<S, A, Q>
+    pub fn load_staking_contract(
+        storage: &impl Storage
     ) -> StdResult<ContractLink<HumanAddr>> {
-        let staking_contract: ContractLink<HumanAddr> = load(&deps.storage, STAKINGCONTRACT_LINK)?.unwrap_or(
+        let staking_contract: ContractLink<HumanAddr> = load(storage, STAKINGCONTRACT_LINK)?.unwrap_or(
             ContractLink { 
                 address:  HumanAddr::default(),
                 code_hash: "".to_string(),
@@ -133,34 +204,22 @@ pub mod amm_pair_storage{
     
  
 
-    // WHITELIST
-    pub fn add_whitelist_address(storage: &mut impl Storage, address: HumanAddr) -> StdResult<()> {
-        let mut unwrap_data = load_whitelist_address(storage)?;
-        unwrap_data.push(address);    
-        save(storage, WHITELIST, &unwrap_data)
+    // FEE DISCOUNTS
+    pub fn load_fee_discounts(storage: &impl Storage) -> StdResult<Option<FeeDiscounts<HumanAddr>>> {
+        let discounts = load(storage, FEE_DISCOUNTS)?.unwrap_or(None);
+        Ok(discounts)
     }
 
-    pub fn load_whitelist_address(storage: &impl Storage) -> StdResult<Vec<HumanAddr>> {
-        let raw_data = load(storage, WHITELIST)?.unwrap_or(Vec::new());  
-        Ok(raw_data)
+    pub fn store_fee_discounts(storage: &mut impl Storage, discounts: &Option<FeeDiscounts<HumanAddr>>) -> StdResult<()> {
+        save(storage, FEE_DISCOUNTS, discounts)
     }
 
-    
-    pub fn remove_whitelist_address(storage: &mut impl Storage, address_to_remove: Vec<HumanAddr>) -> StdResult<()> {
-        let mut addresses = load_whitelist_address(storage)?;
-        for address in address_to_remove {
-            addresses.retain(|x| x != &address);
-        }
-        save(storage, WHITELIST,&addresses)
+    pub fn load_discount_viewing_key(storage: &impl Storage, address: &HumanAddr) -> StdResult<Option<String>> {
+        ns_load(storage, DISCOUNT_VIEWING_KEYS, address.as_str().as_bytes())
     }
 
-    pub fn is_address_in_whitelist(storage: &impl Storage, address: HumanAddr) -> StdResult<bool>{
-        let addrs = load_whitelist_address(storage)?;
-        if addrs.contains(&address) {
-           return Ok(true)
-        } else {
-            return Ok(false)
-        }      
+    pub fn store_discount_viewing_key(storage: &mut impl Storage, address: &HumanAddr, key: &String) -> StdResult<()> {
+        ns_save(storage, DISCOUNT_VIEWING_KEYS, address.as_str().as_bytes(), key)
     }
 
     pub fn load_trade_history<S: Storage, A: Api, Q: Querier>(
@@ -181,4 +240,144 @@ pub mod amm_pair_storage{
         store_trade_counter(deps, update_count)?;
         ns_save(&mut deps.storage, TRADE_HISTORY, update_count.to_string().as_bytes(), &trade_history)
     }   
+
+    pub fn load_locked_liquidity(storage: &impl Storage) -> StdResult<Uint128> {
+        let locked = load(storage, LOCKED_LIQUIDITY)?.unwrap_or(Uint128::zero());
+        Ok(locked)
+    }
+
+    pub fn store_locked_liquidity(storage: &mut impl Storage, amount: Uint128) -> StdResult<()> {
+        save(storage, LOCKED_LIQUIDITY, &amount)
+    }
+
+    /// `sqrt(x * y)` (or the invariant of other pool types) after the last liquidity
+    /// change, zero while the DAO fee is transferred with every swap.
+    pub fn load_root_k_last(storage: &impl Storage) -> StdResult<Uint128> {
+        let root_k = load(storage, ROOT_K_LAST)?.unwrap_or(Uint128::zero());
+        Ok(root_k)
+    }
+
+    pub fn store_root_k_last(storage: &mut impl Storage, root_k: Uint128) -> StdResult<()> {
+        save(storage, ROOT_K_LAST, &root_k)
+    }
+
+    pub fn load_migration(storage: &impl Storage) -> StdResult<Option<Migration>> {
+        load(storage, MIGRATION)
+    }
+
+    pub fn store_migration(storage: &mut impl Storage, migration: &Migration) -> StdResult<()> {
+        save(storage, MIGRATION, migration)
+    }
+
+    // TWAP
+    pub fn load_price_accumulator(storage: &impl Storage) -> StdResult<Option<PriceObservation>> {
+        load(storage, PRICE_ACCUMULATOR)
+    }
+
+    pub fn store_price_accumulator(storage: &mut impl Storage, accumulator: &PriceObservation) -> StdResult<()> {
+        save(storage, PRICE_ACCUMULATOR, accumulator)
+    }
+
+    pub fn load_price_observation_count(storage: &impl Storage) -> StdResult<u64> {
+        let count = load(storage, PRICE_OBSERVATION_COUNT)?.unwrap_or(0);
+        Ok(count)
+    }
+
+    pub fn load_price_observation(storage: &impl Storage, index: u64) -> StdResult<PriceObservation> {
+        let slot = index % PRICE_OBSERVATION_LIMIT;
+        ns_load(storage, PRICE_OBSERVATIONS, slot.to_string().as_bytes())?
+            .ok_or_else(|| StdError::generic_err("Price observation doesn't exist in storage."))
+    }
+
+    /// Observations still kept, oldest first.
+    pub fn load_price_observations(storage: &impl Storage) -> StdResult<Vec<PriceObservation>> {
+        let count = load_price_observation_count(storage)?;
+        (count.saturating_sub(PRICE_OBSERVATION_LIMIT)..count)
+            .map(|index| load_price_observation(storage, index))
+            .collect()
+    }
+
+    pub fn store_price_observation(storage: &mut impl Storage, observation: &PriceObservation) -> StdResult<()> {
+        let count = load_price_observation_count(storage)?;
+        let slot = count % PRICE_OBSERVATION_LIMIT;
+        ns_save(storage, PRICE_OBSERVATIONS, slot.to_string().as_bytes(), observation)?;
+        save(storage, PRICE_OBSERVATION_COUNT, &(count + 1))
+    }
+
+    // CONCENTRATED LIQUIDITY
+    pub fn load_concentrated_pool(storage: &impl Storage) -> StdResult<ConcentratedPool> {
+        load(storage, CONCENTRATED_POOL)?.ok_or_else(|| {
+            StdError::generic_err("This pair is not a concentrated liquidity pool.")
+        })
+    }
+
+    pub fn store_concentrated_pool(storage: &mut impl Storage, pool: &ConcentratedPool) -> StdResult<()> {
+        save(storage, CONCENTRATED_POOL, pool)
+    }
+
+    pub fn load_tick(storage: &impl Storage, tick: i32) -> StdResult<TickInfo> {
+        let info = ns_load(storage, CONCENTRATED_TICKS, &tick.to_be_bytes())?.unwrap_or_default();
+        Ok(info)
+    }
+
+    pub fn store_tick(storage: &mut impl Storage, tick: i32, info: &TickInfo) -> StdResult<()> {
+        ns_save(storage, CONCENTRATED_TICKS, &tick.to_be_bytes(), info)
+    }
+
+    /// Word `word` of the bitmap of ticks which have at least one position starting or
+    /// ending at them, 128 ticks per word.
+    pub fn load_tick_word(storage: &impl Storage, word: u32) -> StdResult<u128> {
+        let bits: Option<Uint128> = ns_load(storage, TICK_BITMAP, &word.to_be_bytes())?;
+        Ok(bits.map_or(0, |bits| bits.u128()))
+    }
+
+    pub fn store_tick_word(storage: &mut impl Storage, word: u32, bits: u128) -> StdResult<()> {
+        ns_save(storage, TICK_BITMAP, &word.to_be_bytes(), &Uint128(bits))
+    }
+
+    /// One bit per word of the tick bitmap which isn't empty, so a search skips empty
+    /// words without loading them.
+    pub fn load_tick_summary(storage: &impl Storage) -> StdResult<Vec<Uint128>> {
+        let summary = load(storage, TICK_BITMAP_SUMMARY)?.unwrap_or(Vec::new());
+        Ok(summary)
+    }
+
+    pub fn store_tick_summary(storage: &mut impl Storage, summary: &Vec<Uint128>) -> StdResult<()> {
+        save(storage, TICK_BITMAP_SUMMARY, summary)
+    }
+
+    fn position_key(owner: &CanonicalAddr, lower_tick: i32, upper_tick: i32) -> Vec<u8> {
+        let mut key = owner.as_slice().to_vec();
+        key.extend_from_slice(&lower_tick.to_be_bytes());
+        key.extend_from_slice(&upper_tick.to_be_bytes());
+        key
+    }
+
+    pub fn load_position(
+        storage: &impl Storage,
+        owner: &CanonicalAddr,
+        lower_tick: i32,
+        upper_tick: i32,
+    ) -> StdResult<Option<Position>> {
+        ns_load(storage, POSITIONS, &position_key(owner, lower_tick, upper_tick))
+    }
+
+    pub fn store_position(
+        storage: &mut impl Storage,
+        owner: &CanonicalAddr,
+        lower_tick: i32,
+        upper_tick: i32,
+        position: &Position,
+    ) -> StdResult<()> {
+        ns_save(storage, POSITIONS, &position_key(owner, lower_tick, upper_tick), position)
+    }
+
+    pub fn remove_position(
+        storage: &mut impl Storage,
+        owner: &CanonicalAddr,
+        lower_tick: i32,
+        upper_tick: i32,
+    ) {
+        ns_remove(storage, POSITIONS, &position_key(owner, lower_tick, upper_tick))
+    }
 }
\ No newline at end of file
@@ -1,16 +1,27 @@
-use shadeswap_shared::msg::amm_pair::{{InitMsg,QueryMsg, HandleMsg,SwapInfo, SwapResult,  InvokeMsg, QueryMsgResponse}};
+use shadeswap_shared::msg::amm_pair::{{InitMsg,QueryMsg, HandleMsg,SwapInfo, SwapResult,  InvokeMsg, QueryMsgResponse, TWAP_PRICE_PRECISION}};
 use shadeswap_shared::token_amount::{{TokenAmount}};
 use shadeswap_shared::token_pair::{{TokenPair}};
 use shadeswap_shared::token_pair_amount::{{TokenPairAmount}};
 use shadeswap_shared::token_type::{{TokenType}};
-use shadeswap_shared::amm_pair::{{AMMPair, AMMSettings, Fee}};
-use crate::state::{Config};
+use shadeswap_shared::amm_pair::{{AMMPair, AMMSettings, Fee, CustomFee, FeeDiscounts, FeeDiscountSource, FeeDiscountTier,
+    OracleSettings, PoolType, ShadeDaoFeeMode}};
+use shadeswap_shared::querier::MockOracle;
+use shadeswap_shared::deadline::Deadline;
+use shadeswap_shared::admin::{ContractStatus, load_status};
+use crate::state::{Config, ConcentratedPool};
 use shadeswap_shared::msg::amm_pair::{{ TradeHistory}};
-use crate::state::amm_pair_storage::{{ store_config, load_config,
-    remove_whitelist_address,is_address_in_whitelist, add_whitelist_address,load_whitelist_address, }};
+use crate::state::amm_pair_storage::{{ store_config, load_config, store_staking_contract,
+    store_fee_discounts, store_discount_viewing_key, store_root_k_last, store_concentrated_pool, load_fee_discounts,
+    load_discount_viewing_key, load_root_k_last, store_locked_liquidity, load_locked_liquidity, load_price_accumulator,
+    load_price_observations }};
 use crate::contract::init;
-use shadeswap_shared::fadroma::secret_toolkit::snip20::Balance;
-use crate::contract::{{create_viewing_key, calculate_price, calculate_swap_result,swap, query, handle}};
+use crate::concentrated::{initialized_tick_at_or_below, initialized_tick_above, deposit_amounts_for_liquidity,
+    sqrt_price_at_tick};
+use shadeswap_shared::fadroma::secret_toolkit::snip20::{Balance, TokenInfo};
+use crate::contract::{{create_viewing_key, calculate_price, calculate_swap_result,swap, query, handle,
+    calculate_exact_output_swap_result, swap_for_exact_output, record_pool_price, calculate_zap_swap_amount,
+    calculate_balanced_deposit, calculate_swap_output, calculate_swap_input, pool_type_for_offer, mint_shade_dao_liquidity,
+    query_fee_discount}};
 use std::hash::Hash;
 
 use shadeswap_shared::{ 
@@ -37,7 +48,8 @@ use shadeswap_shared::{
         },
     }
 };
-use composable_snip20::msg::{{InitMsg as Snip20ComposableMsg, InitConfig as Snip20ComposableConfig}};
+use composable_snip20::msg::{{InitMsg as Snip20ComposableMsg, InitConfig as Snip20ComposableConfig,
+    HandleMsg as Snip20ComposableHandleMsg}};
 
 #[cfg(test)]
 pub mod tests {
@@ -59,6 +71,14 @@ pub mod tests {
     pub const CUSTOM_TOKEN_1: &str = "CUSTOM_TOKEN_1";
     pub const CUSTOM_TOKEN_2: &str = "CUSTOM_TOKEN_2";
     pub const CONTRACT_ADDRESS: &str = "CONTRACT_ADDRESS";
+    pub const LP_TOKEN: &str = "LP_TOKEN";
+    pub const EMPTY_LP_TOKEN: &str = "EMPTY_LP_TOKEN";
+    pub const ORACLE_ADDRESS: &str = "ORACLE_ADDRESS";
+    /// Answers every query with an error, like a token rejecting a viewing key.
+    pub const FAILING_CONTRACT: &str = "FAILING_CONTRACT";
+    /// Token 0 is worth two of token 1.
+    pub const ORACLE_PRICE: u128 = 2_000_000_000_000_000_000;
+    pub const ORACLE_UPDATED: u64 = 1_571_797_419;
     
     //#[test]
     fn assert_init_config() -> StdResult<()> {       
@@ -90,7 +110,10 @@ pub mod tests {
                 },
                 msg: to_binary(&String::from("Welcome bytes"))?
             }),
-            staking_contract: None
+            staking_contract: None,
+            pool_type: None,
+            oracle: None,
+            migration: None,
         };     
         assert!(init(deps, env.clone(), msg).is_ok());
       
@@ -126,6 +149,8 @@ pub mod tests {
             &mk_custom_token_amount(Uint128::from(offer_amount),token_pair), 
             & mut deps.storage,
             HumanAddr("Test".to_string().clone()),
+            false,
+            None,
         );
 
         assert_eq!(Uint128::from(expected_amount), swap_result?.result.return_amount);
@@ -143,7 +168,7 @@ pub mod tests {
         let expected_amount: u128 = 34028236692093846346337460;
         let swap_result = calculate_swap_result(&deps.querier, &amm_settings, &config, 
             &mk_custom_token_amount(Uint128::from(offer_amount), config.pair.clone()), 
-            &mut deps.storage, HumanAddr("Test".to_string().clone()));
+            &mut deps.storage, HumanAddr("Test".to_string().clone()), false, None);
         assert_eq!(Uint128::from(expected_amount), swap_result?.result.return_amount);
         Ok(())
     }
@@ -191,80 +216,101 @@ pub mod tests {
         Ok(())
     }
 
-    //#[test]
-    fn assert_add_address_to_whitelist_success()-> StdResult<()>{
-        let mut deps = mkdeps();
-        let env = mkenv("sender");       
-        let addressA = HumanAddr::from("TESTA".to_string());
-        let addressB = HumanAddr::from("TESTB".to_string());
-        let addressC = HumanAddr::from("TESTC").to_string();
-        add_whitelist_address(&mut deps.storage, addressA.clone())?;
-        let current_index = load_whitelist_address(&deps.storage)?;
-        assert_eq!(1, current_index.len());        
-        add_whitelist_address(&mut deps.storage, addressB.clone())?;
-        let current_index = load_whitelist_address(&deps.storage)?;
-        assert_eq!(2, current_index.len());
-        Ok(())
-    }
+    #[test]
+    fn assert_swap_fee_discounted_by_tier() -> StdResult<()> {
+        let mut deps = mock_deps();
+        let token_pair = mk_native_token_pair();
+        let config = make_init_config(&mut deps, token_pair.clone())?;
+        let address_a = HumanAddr("TESTA".to_string());
+        let token_amount = mk_custom_token_amount(Uint128(1000), config.pair.clone());
+        let amm_settings = shadeswap_shared::amm_pair::AMMSettings {
+            lp_fee: Fee::new(28, 10000),
+            shade_dao_fee: Fee::new(2, 10000),
+            shade_dao_address: ContractLink {
+                address: HumanAddr(String::from("DAO")),
+                code_hash: "".to_string(),
+            },
+            shade_dao_fee_mode: ShadeDaoFeeMode::Transfer,
+        };
+        let discounts = FeeDiscounts {
+            source: FeeDiscountSource::TokenBalance {
+                token: ContractLink {
+                    address: HumanAddr(CUSTOM_TOKEN_2.to_string()),
+                    code_hash: CUSTOM_TOKEN_2.to_string(),
+                },
+            },
+            tiers: vec![
+                FeeDiscountTier { min_amount: Uint128(1000), discount: 5000 },
+                FeeDiscountTier { min_amount: Uint128(2_000_000), discount: 10000 },
+            ],
+            router: None,
+        };
+        assert_eq!(discounts.discount_for(Uint128(999)), 0);
+        assert_eq!(discounts.discount_for(Uint128(1_000_000)), 5000);
+        assert_eq!(discounts.discount_for(Uint128(2_000_000)), 10000);
+        store_fee_discounts(&mut deps.storage, &Some(discounts))?;
 
-    //#[test]
-    fn assert_remove_address_from_whitelist_success()-> StdResult<()>{
-        let mut deps = mkdeps();
-        let env = mkenv("sender");       
-        let addressA = HumanAddr::from("TESTA".to_string());
-        let addressB = HumanAddr::from("TESTB".to_string());
-        let addressC = HumanAddr::from("TESTC".to_string());
-        add_whitelist_address(&mut deps.storage, addressA.clone())?;
-        let current_index = load_whitelist_address(&deps.storage)?;
-        assert_eq!(1, current_index.len());        
-        add_whitelist_address(&mut deps.storage, addressB.clone())?;
-        let current_index = load_whitelist_address(&deps.storage)?;
-        assert_eq!(2, current_index.len());   
-        let mut list_addresses_remove  = Vec::new();
-        list_addresses_remove.push(addressB.clone());
-        remove_whitelist_address(&mut deps.storage, list_addresses_remove)?;
-        add_whitelist_address(&mut deps.storage, addressC.clone())?;
-        let current_index = load_whitelist_address(&deps.storage)?;
-        assert_eq!(2, current_index.len());        
+        // No viewing key registered, so the balance can't be read and the full fee is paid.
+        let swap_result = calculate_swap_result(&deps.querier, &amm_settings, &config, &token_amount,
+            &mut deps.storage, address_a.clone(), false, None)?;
+        assert_eq!(swap_result.lp_fee_amount, Uint128(2u128));
+        assert_eq!(swap_result.result.return_amount, Uint128(997u128));
+
+        store_discount_viewing_key(&mut deps.storage, &address_a, &"key".to_string())?;
+        let swap_result = calculate_swap_result(&deps.querier, &amm_settings, &config, &token_amount,
+            &mut deps.storage, address_a, false, None)?;
+        assert_eq!(swap_result.lp_fee_amount, Uint128(1u128));
+        assert_eq!(swap_result.shade_dao_fee_amount, Uint128(0u128));
+        assert_eq!(swap_result.result.return_amount, Uint128(998u128));
         Ok(())
     }
 
-    
-    //#[test]
-    fn assert_load_address_from_whitelist_success()-> StdResult<()>{
-        let mut deps = mkdeps();
-        let env = mkenv("sender");       
-        let address_a = HumanAddr::from("TESTA".to_string());
-        let address_b = HumanAddr::from("TESTB".to_string());
-        let address_c = HumanAddr::from("TESTC".to_string());
-        add_whitelist_address(&mut deps.storage, address_a.clone())?;
-        add_whitelist_address(&mut deps.storage, address_b.clone())?;
-        add_whitelist_address(&mut deps.storage, address_c.clone())?;
-        let stub_list = load_whitelist_address(&deps.storage)?;
-        assert_eq!(3, stub_list.len());
-        let is_addr = is_address_in_whitelist(&mut deps.storage, address_b.clone())?;  
-        assert_eq!(true, is_addr);      
-        let is_addr = is_address_in_whitelist(&mut deps.storage, HumanAddr("TESTD".to_string()).clone())?;  
-        assert_eq!(false, is_addr);   
+    #[test]
+    fn assert_fee_discount_of_trader_forwarded_by_router() -> StdResult<()> {
+        let mut deps = mock_deps();
+        let token_pair = mk_native_token_pair();
+        make_init_config(&mut deps, token_pair.clone())?;
+        let address_a = HumanAddr("TESTA".to_string());
+        let router = HumanAddr("ROUTER".to_string());
+        store_fee_discounts(&mut deps.storage, &Some(FeeDiscounts {
+            source: FeeDiscountSource::TokenBalance { token: mock_contract_link(CUSTOM_TOKEN_2.to_string()) },
+            tiers: vec![FeeDiscountTier { min_amount: Uint128(1000), discount: 5000 }],
+            router: Some(router.clone()),
+        }))?;
+        store_discount_viewing_key(&mut deps.storage, &address_a, &"key".to_string())?;
+
+        // the router swaps for TESTA and sends the output to itself
+        let config = load_config(&deps)?;
+        let routed_swap = swap(&mut deps, mock_env(CONTRACT_ADDRESS, &[]), config, router.clone(),
+            Some(router.clone()), mk_custom_token_amount(Uint128::from(1000u128), token_pair.clone()), None,
+            None, None, Some(address_a.clone()))?;
+        assert_eq!(routed_swap.log[3].value, "999".to_string());
+
+        // any other contract naming TESTA as the trader pays the full fee
+        let config = load_config(&deps)?;
+        let other_swap = swap(&mut deps, mock_env(CONTRACT_ADDRESS, &[]), config, HumanAddr("OTHER".to_string()),
+            None, mk_custom_token_amount(Uint128::from(1000u128), token_pair.clone()), None,
+            None, None, Some(address_a.clone()))?;
+        assert_eq!(other_swap.log[3].value, "998".to_string());
         Ok(())
     }
 
-      
-    //#[test]
-    fn assert_initial_swap_with_zero_fee_for_whitelist_address()-> StdResult<()>{
-        let mut deps = mkdeps();
-        let amm_settings = mk_amm_settings();
-        let token_pair = mk_token_pair();
-        let config = make_init_config(&mut deps, token_pair)?;         
-        let offer_amount: u128 = 34028236692093846346337460;
-        let expected_amount: u128 = 34028236692093846346337460;           
-        let address_a = HumanAddr::from("TESTA".to_string());
-        add_whitelist_address(&mut deps.storage, address_a.clone())?;    
-        let swap_result = calculate_swap_result(&deps.querier, &amm_settings, &config, 
-            &mk_custom_token_amount(Uint128::from(offer_amount), config.pair.clone()), 
-            &mut deps.storage, HumanAddr("TESTA".to_string().clone()))?;
-        assert_eq!(Uint128::from(expected_amount), swap_result.result.return_amount);
-        assert_eq!(Uint128::zero(), swap_result.lp_fee_amount);
+    #[test]
+    fn assert_unreadable_fee_discount_source_gives_no_discount() -> StdResult<()> {
+        let mut deps = mock_deps();
+        let address_a = HumanAddr("TESTA".to_string());
+        let tiers = vec![FeeDiscountTier { min_amount: Uint128(0), discount: 5000 }];
+        store_fee_discounts(&mut deps.storage, &Some(FeeDiscounts {
+            source: FeeDiscountSource::TokenBalance { token: mock_contract_link(FAILING_CONTRACT.to_string()) },
+            tiers: tiers.clone(),
+            router: None,
+        }))?;
+        store_discount_viewing_key(&mut deps.storage, &address_a, &"stale key".to_string())?;
+        assert_eq!(query_fee_discount(&deps.querier, &deps.storage, &address_a)?, 0);
+
+        store_fee_discounts(&mut deps.storage, &Some(FeeDiscounts { source: FeeDiscountSource::Stake, tiers, router: None }))?;
+        store_staking_contract(&mut deps, &mock_contract_link(FAILING_CONTRACT.to_string()))?;
+        assert_eq!(query_fee_discount(&deps.querier, &deps.storage, &address_a)?, 0);
         Ok(())
     }
 
@@ -281,11 +327,12 @@ pub mod tests {
             shade_dao_address: ContractLink {
                 address: HumanAddr(String::from("DAO")),
                 code_hash: "".to_string(),
-            }
+            },
+            shade_dao_fee_mode: ShadeDaoFeeMode::Transfer,
         };
         assert_eq!(config.factory_info.address.as_str(), FACTORY_CONTRACT_ADDRESS.clone());
         let swap_result = calculate_swap_result(&deps.querier, &amm_settings, &config, &token_amount,
-            &mut deps.storage, address_a)?;
+            &mut deps.storage, address_a, false, None)?;
         assert_eq!(swap_result.result.return_amount, Uint128(997u128));
         assert_eq!(swap_result.lp_fee_amount, Uint128(2u128));
         assert_eq!(swap_result.shade_dao_fee_amount, Uint128(0u128));
@@ -304,53 +351,1086 @@ pub mod tests {
         assert_eq!(config.factory_info.address.as_str(), FACTORY_CONTRACT_ADDRESS.clone());
         let native_swap = swap(&mut deps, env, config, address_a.clone(), 
             None,  mk_custom_token_amount(Uint128::from(1000u128), token_pair.clone()),None, 
-            None, None)?;      
-        assert_eq!(native_swap.log[3].value, "997".to_string());
+            None, None, None)?;      
+        assert_eq!(native_swap.log[3].value, "998".to_string());
         assert_eq!(native_swap.messages.len(), 1);
         Ok(())
     }
 
-    //#[test]
-    fn assert_query_get_amm_pairs_success()-> StdResult<()>{
-        let mut deps = mkdeps();
-        let env = mkenv("sender");
+    #[test]
+    fn assert_swap_pays_out_the_simulated_amount()-> StdResult<()>{
+        assert_quote_matches_swap(None)
+    }
+
+    #[test]
+    fn assert_swap_with_custom_fee()-> StdResult<()>{
+        let mut deps = mock_deps();
+        let token_pair = mk_native_token_pair();
+        make_init_config(&mut deps, token_pair.clone())?;
+        let fee = Some(CustomFee {
+            lp_fee: Fee::new(1, 100),
+            shade_dao_fee: Fee::new(0, 100),
+        });
+        let result = handle(&mut deps, mock_env("TESTA", &[]), HandleMsg::SetCustomFee { fee });
+        match result {
+            Err(StdError::Unauthorized { .. }) => {}
+            _ => panic!("Only the factory can set the fee"),
+        }
+        handle(&mut deps, mock_env(FACTORY_CONTRACT_ADDRESS, &[]), HandleMsg::SetCustomFee { fee })?;
+
+        let config = load_config(&deps)?;
+        let native_swap = swap(&mut deps, mock_env(CONTRACT_ADDRESS, &[]), config, HumanAddr("TESTA".to_string()),
+            None, mk_custom_token_amount(Uint128::from(1000u128), token_pair.clone()), None,
+            None, None, None)?;
+        // 1% of the offer instead of the factory fees, the other 990 are swapped
+        assert_eq!(native_swap.log[3].value, "990".to_string());
+        assert_eq!(native_swap.messages.len(), 1);
+
+        // without a custom fee the factory fees apply again
+        handle(&mut deps, mock_env(FACTORY_CONTRACT_ADDRESS, &[]), HandleMsg::SetCustomFee { fee: None })?;
+        let config = load_config(&deps)?;
+        let native_swap = swap(&mut deps, mock_env(CONTRACT_ADDRESS, &[]), config, HumanAddr("TESTA".to_string()),
+            None, mk_custom_token_amount(Uint128::from(1000u128), token_pair.clone()), None,
+            None, None, None)?;
+        assert_eq!(native_swap.log[3].value, "998".to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn assert_withdrawal_checks_custom_fee()-> StdResult<()>{
+        let mut deps = mock_deps();
+        let token_pair = mk_native_token_pair();
+        let mut config = make_init_config(&mut deps, token_pair.clone())?;
+        config.lp_token_info.address = HumanAddr(LP_TOKEN.to_string());
+        // above the fee limit, SetCustomFee would have refused it
+        config.custom_fee = Some(CustomFee {
+            lp_fee: Fee::new(50, 100),
+            shade_dao_fee: Fee::new(0, 100),
+        });
+        store_config(&mut deps, &config)?;
+
+        let result = handle(&mut deps, mock_env(LP_TOKEN, &[]), HandleMsg::Receive {
+            from: HumanAddr("TESTA".to_string()),
+            amount: Uint128(10000u128),
+            msg: Some(to_binary(&InvokeMsg::RemoveLiquidity {
+                recipient: HumanAddr("TESTA".to_string()),
+                min_amount_0: None,
+                min_amount_1: None,
+                withdraw_into: None,
+            })?),
+        });
+        match result {
+            Err(StdError::GenericErr { msg, .. }) => assert!(msg.starts_with("Combined fees can't be more than")),
+            _ => panic!("A withdrawal must check the fee like every other path"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn assert_stable_swap_has_less_slippage() -> StdResult<()>{
+        let pool = Uint256::from(1_000_000u128);
+        let amount = Uint256::from(10_000u128);
+        let constant_product = calculate_swap_output(PoolType::ConstantProduct, amount, pool, pool)?;
+        let stable = calculate_swap_output(PoolType::StableSwap { amp: 100 }, amount, pool, pool)?;
+        assert_eq!(constant_product, Uint256::from(9900u128));
+        assert_eq!(stable, Uint256::from(9999u128));
+        // going back from the output costs the original amount
+        let required_input = calculate_swap_input(PoolType::StableSwap { amp: 100 }, stable, pool, pool)?;
+        assert_eq!(required_input, amount);
+        Ok(())
+    }
+
+    #[test]
+    fn assert_stable_swap_pays_out_the_simulated_amount() -> StdResult<()>{
+        assert_quote_matches_swap(Some(PoolType::StableSwap { amp: 100 }))
+    }
+
+    #[test]
+    fn assert_init_stable_swap_with_zero_amp_fails() -> StdResult<()>{
+        let ref mut deps = mock_dependencies(30, &[]);
+        let env = mkenv(CONTRACT_ADDRESS);
+        let msg = InitMsg {
+            pair: mk_token_pair(),
+            lp_token_contract: ContractInstantiationInfo{
+                  code_hash: "CODE_HASH".to_string(),
+                  id :0
+            },
+            factory_info: ContractLink {
+                address: HumanAddr(String::from(FACTORY_CONTRACT_ADDRESS)),
+                code_hash: "TEST".to_string()
+            },
+            prng_seed: to_binary(&"SEED".to_string())?,
+            entropy: to_binary(&"ENTROPY".to_string())?,
+            admin: None,
+            callback: None,
+            staking_contract: None,
+            pool_type: Some(PoolType::StableSwap { amp: 0 }),
+            oracle: None,
+            migration: None,
+        };
+        assert!(init(deps, env, msg).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn assert_weighted_pool_swap_follows_weights() -> StdResult<()>{
+        let pool_type = PoolType::Weighted { weight_0: 80, weight_1: 20 };
+        let token0_pool = Uint256::from(1_000_000u128);
+        let token1_pool = Uint256::from(4_000_000u128);
+        let amount = Uint256::from(1000u128);
+        // 4 times the reserve at a quarter of the weight, so the spot price is 1:16
+        let output = calculate_swap_output(pool_type, amount, token0_pool, token1_pool)?;
+        assert_eq!(output, Uint256::from(15960u128));
+        let input = calculate_swap_input(pool_type, Uint256::from(3990u128), token0_pool, token1_pool)?;
+        assert_eq!(input, Uint256::from(250u128));
+        // offering token 1 swaps the weights around
+        assert_eq!(pool_type_for_offer(pool_type, 1), PoolType::Weighted { weight_0: 20, weight_1: 80 });
+        Ok(())
+    }
+
+    #[test]
+    fn assert_weighted_swap_pays_out_the_simulated_amount() -> StdResult<()>{
+        assert_quote_matches_swap(Some(PoolType::Weighted { weight_0: 80, weight_1: 20 }))
+    }
+
+    #[test]
+    fn assert_oracle_pegged_swap_is_priced_around_oracle() -> StdResult<()>{
+        let deps = mock_deps();
+        let token_pair = mk_native_token_pair();
         let amm_settings = mk_amm_settings();
-        let token_pair = mk_token_pair();
-        let config = make_init_config(&mut deps, token_pair)?;         
-        let offer_amount: u128 = 34028236692093846346337460;
-        let expected_amount: u128 = 34028236692093846346337460;           
-        let address_a = HumanAddr::from("TESTA".to_string());
-        handle(
-            &mut deps,
-            env,
-            HandleMsg::AddWhiteListAddress {
-                address: address_a.clone()
+        let config = Config {
+            pair: token_pair.clone(),
+            oracle: Some(OracleSettings {
+                contract: mock_contract_link(ORACLE_ADDRESS.to_string()),
+                max_deviation: 100,
+                max_age: 60,
+            }),
+            ..mock_config(mkenv(CONTRACT_ADDRESS))?
+        };
+        // the pool holds both tokens 1:1, yet the 779 left after fees swap at about 2
+        let offer = mk_custom_token_amount(Uint128(1000u128), token_pair.clone());
+        let swap_info = calculate_swap_result(&deps.querier, &amm_settings, &config, &offer,
+            &deps.storage, HumanAddr("Test".to_string()), false, None)?;
+        assert_eq!(swap_info.total_fee_amount, Uint128(221u128));
+        assert_eq!(swap_info.result.return_amount, Uint128(1556u128));
+        // moves the price more than 1% away from the oracle
+        let offer = mk_custom_token_amount(Uint128(100_000u128), token_pair);
+        assert!(calculate_swap_result(&deps.querier, &amm_settings, &config, &offer,
+            &deps.storage, HumanAddr("Test".to_string()), false, None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn assert_oracle_pegged_swap_rejects_stale_price() -> StdResult<()>{
+        let deps = mock_deps();
+        let token_pair = mk_native_token_pair();
+        let amm_settings = mk_amm_settings();
+        let config = Config {
+            pair: token_pair.clone(),
+            oracle: Some(OracleSettings {
+                contract: mock_contract_link(ORACLE_ADDRESS.to_string()),
+                max_deviation: 100,
+                max_age: 60,
+            }),
+            ..mock_config(mkenv(CONTRACT_ADDRESS))?
+        };
+        let offer = mk_custom_token_amount(Uint128(1000u128), token_pair);
+        let swap_at = |time: u64| calculate_swap_result(&deps.querier, &amm_settings, &config, &offer,
+            &deps.storage, HumanAddr("Test".to_string()), false, Some(time));
+        assert!(swap_at(ORACLE_UPDATED + 60).is_ok());
+        match swap_at(ORACLE_UPDATED + 61) {
+            Err(StdError::GenericErr { msg, .. }) => {
+                assert_eq!(msg, "The oracle price is more than 60 seconds old.".to_string())
+            }
+            _ => panic!("A stale oracle price must be rejected"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn assert_factory_rotates_oracle() -> StdResult<()>{
+        let mut deps = mock_deps();
+        let token_pair = mk_native_token_pair();
+        let mut config = make_init_config(&mut deps, token_pair)?;
+        let oracle = OracleSettings {
+            contract: mock_contract_link("NEW_ORACLE".to_string()),
+            max_deviation: 50,
+            max_age: 30,
+        };
+        match handle(&mut deps, mkenv(FACTORY_CONTRACT_ADDRESS), HandleMsg::SetOracle { oracle: oracle.clone() }) {
+            Err(StdError::GenericErr { .. }) => {}
+            _ => panic!("A pair without an oracle must not get one"),
+        }
+
+        config.oracle = Some(OracleSettings {
+            contract: mock_contract_link(ORACLE_ADDRESS.to_string()),
+            max_deviation: 100,
+            max_age: 60,
+        });
+        store_config(&mut deps, &config)?;
+        match handle(&mut deps, mkenv("TESTA"), HandleMsg::SetOracle { oracle: oracle.clone() }) {
+            Err(StdError::Unauthorized { .. }) => {}
+            _ => panic!("Only the factory can replace the oracle"),
+        }
+        let invalid = OracleSettings { max_age: 0, ..oracle.clone() };
+        assert!(handle(&mut deps, mkenv(FACTORY_CONTRACT_ADDRESS), HandleMsg::SetOracle { oracle: invalid }).is_err());
+        handle(&mut deps, mkenv(FACTORY_CONTRACT_ADDRESS), HandleMsg::SetOracle { oracle: oracle.clone() })?;
+        assert_eq!(load_config(&deps)?.oracle, Some(oracle));
+        Ok(())
+    }
+
+    #[test]
+    fn assert_shade_dao_fee_minted_from_root_k_growth() -> StdResult<()>{
+        let mut deps = mock_deps();
+        let lp_token = mock_contract_link(LP_TOKEN.to_string());
+        let mut amm_settings = mk_amm_settings();
+        // sqrt(k) grew by 0.1% from fees since the last liquidity change
+        store_root_k_last(&mut deps.storage, Uint128(1_000_000u128))?;
+        let reserves = [Uint128(1_001_000u128), Uint128(1_001_000u128)];
+
+        let mut messages = vec![];
+        let minted = mint_shade_dao_liquidity(&deps.storage, &amm_settings, PoolType::ConstantProduct,
+            &reserves, Uint128(1_000_000u128), &lp_token, &mut messages)?;
+        assert_eq!(minted, Uint128::zero());
+        assert!(messages.is_empty());
+
+        // the DAO gets a quarter of the fees (1/18 of 4/18) as LP tokens
+        amm_settings.shade_dao_fee_mode = ShadeDaoFeeMode::MintLiquidity;
+        let minted = mint_shade_dao_liquidity(&deps.storage, &amm_settings, PoolType::ConstantProduct,
+            &reserves, Uint128(1_000_000u128), &lp_token, &mut messages)?;
+        assert_eq!(minted, Uint128(249u128));
+        assert_eq!(messages.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn assert_concentrated_liquidity_position_earns_swap_fees() -> StdResult<()>{
+        let mut deps = mock_deps();
+        let token_pair = mk_native_token_pair();
+        make_init_config_with_pool_type(&mut deps, token_pair.clone(),
+            Some(PoolType::ConcentratedLiquidity { tick_spacing: 60, initial_tick: 0 }))?;
+        let lp_env = mock_env("LP_A", &[Coin {
+            denom: "uscrt".into(),
+            amount: Uint128(1_000_000u128),
+        }]);
+        let deposit = TokenPairAmount {
+            pair: token_pair.clone(),
+            amount_0: Uint128(1_000_000u128),
+            amount_1: Uint128(1_000_000u128),
+        };
+        let result = handle(&mut deps, lp_env.clone(), HandleMsg::AddRangeLiquidity {
+            deposit: deposit.clone(), lower_tick: -610, upper_tick: 600, min_liquidity: None });
+        assert!(result.is_err());
+        let result = handle(&mut deps, lp_env, HandleMsg::AddRangeLiquidity {
+            deposit, lower_tick: -600, upper_tick: 600, min_liquidity: None })?;
+        assert_eq!(result.log[3].value, "33837499".to_string());
+        // the price is in the middle of the range, the whole deposit is used
+        assert_eq!(result.messages.len(), 1);
+
+        let config = load_config(&deps)?;
+        let native_swap = swap(&mut deps, mock_env(CONTRACT_ADDRESS, &[]), config, HumanAddr("TESTA".to_string()),
+            None, mk_custom_token_amount(Uint128::from(1000u128), token_pair.clone()), None,
+            None, None, None)?;
+        assert_eq!(native_swap.log[3].value, "996".to_string());
+        assert_eq!(native_swap.log[5].value, "3".to_string());
+
+        let position: QueryMsgResponse = from_binary(&query(&deps, QueryMsg::GetPosition {
+            owner: HumanAddr("LP_A".to_string()), lower_tick: -600, upper_tick: 600 })?)?;
+        match position {
+            QueryMsgResponse::GetPosition { liquidity, fees_owed_0, fees_owed_1, .. } => {
+                assert_eq!(liquidity, Uint128(33837499u128));
+                assert_eq!(fees_owed_0, Uint128(2u128));
+                assert_eq!(fees_owed_1, Uint128::zero());
+            }
+            _ => panic!("Wrong response for GetPosition"),
+        }
+
+        let result = handle(&mut deps, mock_env("LP_A", &[]), HandleMsg::RemoveRangeLiquidity {
+            lower_tick: -600, upper_tick: 600, liquidity: Uint128(33837499u128) })?;
+        assert_eq!(result.log[4].value, "1000998".to_string());
+        assert_eq!(result.log[5].value, "999003".to_string());
+        assert_eq!(result.log[6].value, "2".to_string());
+        assert_eq!(result.messages.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn assert_range_deposit_has_to_cover_rounded_up_amounts() -> StdResult<()>{
+        let sqrt_price = sqrt_price_at_tick(0)?;
+        let sqrt_price_lower = sqrt_price_at_tick(-600)?;
+        let sqrt_price_upper = sqrt_price_at_tick(600)?;
+        let liquidity = Uint128(33837499u128);
+        assert_eq!(deposit_amounts_for_liquidity(sqrt_price, sqrt_price_lower, sqrt_price_upper, liquidity,
+            [Uint128(1_000_000u128), Uint128(1_000_000u128)])?, [Uint128(1_000_000u128), Uint128(1_000_000u128)]);
+        // the exact amounts are just below 1000000, rounding them up takes one more than deposited
+        match deposit_amounts_for_liquidity(sqrt_price, sqrt_price_lower, sqrt_price_upper, liquidity,
+            [Uint128(999_999u128), Uint128(1_000_000u128)]) {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg,
+                "The deposit doesn't cover the liquidity. Actual: 999999, Expected: 1000000".to_string()),
+            _ => panic!("A deposit short of the rounded up amount must be rejected"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn assert_concentrated_swap_crosses_initialized_ticks() -> StdResult<()>{
+        let mut deps = mock_deps();
+        let token_pair = mk_native_token_pair();
+        make_init_config_with_pool_type(&mut deps, token_pair.clone(),
+            Some(PoolType::ConcentratedLiquidity { tick_spacing: 60, initial_tick: 0 }))?;
+        handle(&mut deps, mock_env("LP_A", &[Coin { denom: "uscrt".into(), amount: Uint128(1_000_000u128) }]),
+            HandleMsg::AddRangeLiquidity {
+                deposit: TokenPairAmount {
+                    pair: token_pair.clone(),
+                    amount_0: Uint128(1_000_000u128),
+                    amount_1: Uint128(1_000_000u128),
+                },
+                lower_tick: -600, upper_tick: 600, min_liquidity: None })?;
+        // far enough below to sit in another word of the tick bitmap and of its summary
+        let result = handle(&mut deps, mock_env("LP_B", &[Coin { denom: "uscrt".into(), amount: Uint128(1_000_000u128) }]),
+            HandleMsg::AddRangeLiquidity {
+                deposit: TokenPairAmount {
+                    pair: token_pair.clone(),
+                    amount_0: Uint128::zero(),
+                    amount_1: Uint128(1_000_000u128),
+                },
+                lower_tick: -30000, upper_tick: -1200, min_liquidity: None })?;
+        let liquidity_b = Uint128(result.log[3].value.parse::<u128>().unwrap());
+        assert_eq!(initialized_tick_at_or_below(&deps.storage, -601)?, Some(-1200));
+        assert_eq!(initialized_tick_at_or_below(&deps.storage, -1201)?, Some(-30000));
+        assert_eq!(initialized_tick_at_or_below(&deps.storage, -30001)?, None);
+        assert_eq!(initialized_tick_above(&deps.storage, -30000)?, Some(-1200));
+        assert_eq!(initialized_tick_above(&deps.storage, 0)?, Some(600));
+        assert_eq!(initialized_tick_above(&deps.storage, 600)?, None);
+
+        // runs through the range of LP_A and the empty ticks below it into the range of LP_B
+        let config = load_config(&deps)?;
+        swap(&mut deps, mock_env(CONTRACT_ADDRESS, &[]), config, HumanAddr("TESTA".to_string()),
+            None, mk_custom_token_amount(Uint128::from(2_000_000u128), token_pair.clone()), None,
+            None, None, None)?;
+        let pool: QueryMsgResponse = from_binary(&query(&deps, QueryMsg::GetConcentratedPool)?)?;
+        match pool {
+            QueryMsgResponse::GetConcentratedPool { tick, liquidity, .. } => {
+                assert!(tick < -1200 && tick > -30000);
+                assert_eq!(liquidity, liquidity_b);
+            }
+            _ => panic!("Wrong response for GetConcentratedPool"),
+        }
+
+        // the ticks of an emptied range aren't found anymore
+        handle(&mut deps, mock_env("LP_A", &[]), HandleMsg::RemoveRangeLiquidity {
+            lower_tick: -600, upper_tick: 600, liquidity: Uint128(33837499u128) })?;
+        assert_eq!(initialized_tick_above(&deps.storage, -1200)?, None);
+        assert_eq!(initialized_tick_at_or_below(&deps.storage, -1201)?, Some(-30000));
+        Ok(())
+    }
+
+    #[test]
+    fn assert_out_of_range_position_provides_no_liquidity() -> StdResult<()>{
+        let mut deps = mock_deps();
+        let token_pair = mk_native_token_pair();
+        make_init_config_with_pool_type(&mut deps, token_pair.clone(),
+            Some(PoolType::ConcentratedLiquidity { tick_spacing: 60, initial_tick: 0 }))?;
+        // the range is above the price, it only takes the first token
+        let result = handle(&mut deps, mock_env("LP_A", &[]), HandleMsg::AddRangeLiquidity {
+            deposit: TokenPairAmount {
+                pair: token_pair.clone(),
+                amount_0: Uint128(1_000_000u128),
+                amount_1: Uint128::zero(),
+            },
+            lower_tick: 600, upper_tick: 1200, min_liquidity: None })?;
+        assert_ne!(result.log[4].value, "0".to_string());
+        assert_eq!(result.log[5].value, "0".to_string());
+        let pool: QueryMsgResponse = from_binary(&query(&deps, QueryMsg::GetConcentratedPool)?)?;
+        match pool {
+            QueryMsgResponse::GetConcentratedPool { tick, liquidity, .. } => {
+                assert_eq!(tick, 0);
+                assert_eq!(liquidity, Uint128::zero());
+            }
+            _ => panic!("Wrong response for GetConcentratedPool"),
+        }
+
+        // selling the first token moves the price away from the range
+        let config = load_config(&deps)?;
+        let result = swap(&mut deps, mock_env(CONTRACT_ADDRESS, &[]), config, HumanAddr("TESTA".to_string()),
+            None, mk_custom_token_amount(Uint128::from(1000u128), token_pair.clone()), None,
+            None, None, None);
+        match result {
+            Err(StdError::GenericErr { msg, .. }) => {
+                assert_eq!(msg, "Not enough liquidity in the pool for this swap.".to_string())
+            }
+            _ => panic!("Swap away from the only range must fail"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn assert_collect_range_fees_keeps_position() -> StdResult<()>{
+        let mut deps = mock_deps();
+        let token_pair = mk_native_token_pair();
+        make_init_config_with_pool_type(&mut deps, token_pair.clone(),
+            Some(PoolType::ConcentratedLiquidity { tick_spacing: 60, initial_tick: 0 }))?;
+        handle(&mut deps, mock_env("LP_A", &[Coin { denom: "uscrt".into(), amount: Uint128(1_000_000u128) }]),
+            HandleMsg::AddRangeLiquidity {
+                deposit: TokenPairAmount {
+                    pair: token_pair.clone(),
+                    amount_0: Uint128(1_000_000u128),
+                    amount_1: Uint128(1_000_000u128),
+                },
+                lower_tick: -600, upper_tick: 600, min_liquidity: None })?;
+        let config = load_config(&deps)?;
+        swap(&mut deps, mock_env(CONTRACT_ADDRESS, &[]), config, HumanAddr("TESTA".to_string()),
+            None, mk_custom_token_amount(Uint128::from(1000u128), token_pair.clone()), None,
+            None, None, None)?;
+
+        let result = handle(&mut deps, mock_env("LP_A", &[]), HandleMsg::CollectRangeFees {
+            lower_tick: -600, upper_tick: 600 })?;
+        assert_eq!(result.log[4].value, "2".to_string());
+        assert_eq!(result.log[5].value, "0".to_string());
+        assert_eq!(result.log[6].value, "2".to_string());
+        assert_eq!(result.messages.len(), 1);
+        assert_eq!(result.messages[0], token_pair.0.create_send_msg(
+            HumanAddr::from("cosmos2contract"), HumanAddr("LP_A".to_string()), Uint128(2u128))?);
+
+        // the liquidity stays, the fees are paid out only once
+        let position: QueryMsgResponse = from_binary(&query(&deps, QueryMsg::GetPosition {
+            owner: HumanAddr("LP_A".to_string()), lower_tick: -600, upper_tick: 600 })?)?;
+        match position {
+            QueryMsgResponse::GetPosition { liquidity, fees_owed_0, fees_owed_1, .. } => {
+                assert_eq!(liquidity, Uint128(33837499u128));
+                assert_eq!(fees_owed_0, Uint128::zero());
+                assert_eq!(fees_owed_1, Uint128::zero());
+            }
+            _ => panic!("Wrong response for GetPosition"),
+        }
+        let result = handle(&mut deps, mock_env("LP_A", &[]), HandleMsg::CollectRangeFees {
+            lower_tick: -600, upper_tick: 600 })?;
+        assert_eq!(result.messages.len(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn assert_calculate_exact_output_swap_result() -> StdResult<()>{
+        let mut deps = mock_deps();
+        let token_pair = mk_native_token_pair();
+        let config = make_init_config(&mut deps, token_pair.clone())?;
+        let address_a = HumanAddr("TESTA".to_string());
+        let token_amount = mk_custom_token_amount(Uint128(2000), config.pair.clone());
+        let amm_settings = shadeswap_shared::amm_pair::AMMSettings {
+            lp_fee: Fee::new(28, 10000),
+            shade_dao_fee: Fee::new(2, 10000),
+            shade_dao_address: ContractLink {
+                address: HumanAddr(String::from("DAO")),
+                code_hash: "".to_string(),
             },
-        )
-        .unwrap();
+            shade_dao_fee_mode: ShadeDaoFeeMode::Transfer,
+        };
+        let (required_input, swap_result) = calculate_exact_output_swap_result(&deps.querier, &amm_settings,
+            &config, &token_amount, Uint128(997u128), &deps.storage, address_a, None)?;
+        // 996 are swapped for the 997, the fees are charged on top of that
+        assert_eq!(required_input, Uint128(998u128));
+        assert_eq!(swap_result.result.return_amount, Uint128(997u128));
+        assert_eq!(swap_result.lp_fee_amount, Uint128(2u128));
+        assert_eq!(swap_result.shade_dao_fee_amount, Uint128(0u128));
+        Ok(())
+    }
+
+    #[test]
+    fn assert_swap_for_exact_output_refunds_unused_input()-> StdResult<()>{
+        let mut deps = mock_deps();
+        let env = mock_env(CONTRACT_ADDRESS, &[]);
+        let token_pair = mk_native_token_pair();
+        let config = make_init_config(&mut deps, token_pair.clone())?;
+        let address_a = HumanAddr("TESTA".to_string());
+        let result = swap_for_exact_output(&mut deps, env, config, address_a.clone(), None,
+            mk_custom_token_amount(Uint128::from(2000u128), token_pair.clone()), Uint128(997u128), Uint128(1500u128))?;
+        assert_eq!(result.messages.len(), 2);
+        assert_eq!(result.messages[0], token_pair.1.create_send_msg(
+            HumanAddr::from("cosmos2contract"), address_a.clone(), Uint128(997u128))?);
+        assert_eq!(result.messages[1], token_pair.0.create_send_msg(
+            HumanAddr::from("cosmos2contract"), address_a.clone(), Uint128(1002u128))?);
+        Ok(())
+    }
+
+    #[test]
+    fn assert_swap_for_exact_output_above_max_input_fails()-> StdResult<()>{
+        let mut deps = mock_deps();
+        let env = mock_env(CONTRACT_ADDRESS, &[]);
+        let token_pair = mk_native_token_pair();
+        let config = make_init_config(&mut deps, token_pair.clone())?;
+        let address_a = HumanAddr("TESTA".to_string());
+        let result = swap_for_exact_output(&mut deps, env, config, address_a.clone(), None,
+            mk_custom_token_amount(Uint128::from(2000u128), token_pair.clone()), Uint128(997u128), Uint128(997u128));
+        match result {
+            Err(StdError::GenericErr { .. }) => {}
+            _ => panic!("Must return error when required input exceeds max_input"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn assert_swap_after_deadline_fails()-> StdResult<()>{
+        let mut deps = mock_deps();
+        let env = mock_env(CONTRACT_ADDRESS, &[]);
+        let token_pair = mk_native_token_pair();
+        make_init_config(&mut deps, token_pair.clone())?;
+        let offer = TokenAmount {
+            token: mk_native_token(),
+            amount: Uint128(1000u128),
+        };
+        let deadline = Some(Deadline::Time(env.block.time - 1));
+        let result = handle(&mut deps, env, HandleMsg::SwapTokens {
+            offer,
+            expected_return: None,
+            to: None,
+            router_link: None,
+            callback_signature: None,
+            deadline,
+            trader: None,
+        });
+        match result {
+            Err(StdError::GenericErr { .. }) => {}
+            _ => panic!("Must return error when the deadline has passed"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn assert_twap_averages_price_over_window()-> StdResult<()>{
+        let mut deps = mock_deps();
+        let mut env = mock_env(CONTRACT_ADDRESS, &[]);
+        env.block.time = 1_000;
+        record_pool_price(&mut deps.storage, &env, PoolType::ConstantProduct, &[Uint128(1000), Uint128(2000)])?;
+        env.block.time = 1_600;
+        record_pool_price(&mut deps.storage, &env, PoolType::ConstantProduct, &[Uint128(1000), Uint128(2000)])?;
+        env.block.time = 2_200;
+        record_pool_price(&mut deps.storage, &env, PoolType::ConstantProduct, &[Uint128(1000), Uint128(4000)])?;
+
+        let precision = TWAP_PRICE_PRECISION;
+        let result: QueryMsgResponse = from_binary(&query(&deps, QueryMsg::GetTwap { window: 1200, time: 2_200 })?)?;
+        match result {
+            QueryMsgResponse::GetTwap { price_0_average, price_1_average, window_start, window_end } => {
+                assert_eq!(price_0_average, Uint128(3 * precision));
+                assert_eq!(price_1_average, Uint128(precision * 3 / 8));
+                assert_eq!(window_start, 1_000);
+                assert_eq!(window_end, 2_200);
+            }
+            _ => panic!("Must return GetTwap"),
+        }
+
+        let result: QueryMsgResponse = from_binary(&query(&deps, QueryMsg::GetTwap { window: 600, time: 2_200 })?)?;
+        match result {
+            QueryMsgResponse::GetTwap { price_0_average, window_start, .. } => {
+                assert_eq!(price_0_average, Uint128(4 * precision));
+                assert_eq!(window_start, 1_600);
+            }
+            _ => panic!("Must return GetTwap"),
+        }
+
+        match query(&deps, QueryMsg::GetTwap { window: 1500, time: 2_200 }) {
+            Err(StdError::GenericErr { .. }) => {}
+            _ => panic!("Must return error when the history doesn't cover the window"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn assert_twap_carries_current_price_to_query_time()-> StdResult<()>{
+        let mut deps = mock_deps();
+        let token_pair = mk_native_token_pair();
+        make_init_config(&mut deps, token_pair)?;
+        let mut env = mock_env(CONTRACT_ADDRESS, &[]);
+        env.block.time = 1_000;
+        record_pool_price(&mut deps.storage, &env, PoolType::ConstantProduct, &[Uint128(1000), Uint128(2000)])?;
+        env.block.time = 1_600;
+        record_pool_price(&mut deps.storage, &env, PoolType::ConstantProduct, &[Uint128(1000), Uint128(2000)])?;
+
+        // the pool has held equal reserves since the last update
+        let precision = TWAP_PRICE_PRECISION;
+        match from_binary(&query(&deps, QueryMsg::GetTwap { window: 1200, time: 2_200 })?)? {
+            QueryMsgResponse::GetTwap { price_0_average, price_1_average, window_start, window_end } => {
+                assert_eq!(price_0_average, Uint128(precision * 3 / 2));
+                assert_eq!(price_1_average, Uint128(precision * 3 / 4));
+                assert_eq!(window_start, 1_000);
+                assert_eq!(window_end, 2_200);
+            }
+            _ => panic!("Must return GetTwap"),
+        }
+
+        // the window is as long as requested, not stretched back to the last observation
+        match from_binary(&query(&deps, QueryMsg::GetTwap { window: 300, time: 2_200 })?)? {
+            QueryMsgResponse::GetTwap { price_0_average, price_1_average, window_start, window_end } => {
+                assert_eq!(price_0_average, Uint128(precision));
+                assert_eq!(price_1_average, Uint128(precision));
+                assert_eq!(window_start, 1_900);
+                assert_eq!(window_end, 2_200);
+            }
+            _ => panic!("Must return GetTwap"),
+        }
+
+        match query(&deps, QueryMsg::GetTwap { window: 300, time: 1_599 }) {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(msg,
+                "The TWAP time can't be before the last price update at 1600.".to_string()),
+            _ => panic!("Must return error for a time before the last price update"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn assert_twap_follows_pool_spot_price()-> StdResult<()>{
+        let precision = TWAP_PRICE_PRECISION;
+        // a stable pool holding a quarter of token 0 prices it just above 1, not at 4
+        let mut deps = mock_deps();
+        let mut env = mock_env(CONTRACT_ADDRESS, &[]);
+        let stable = PoolType::StableSwap { amp: 100 };
+        env.block.time = 1_000;
+        record_pool_price(&mut deps.storage, &env, stable, &[Uint128(1000), Uint128(4000)])?;
+        env.block.time = 2_000;
+        record_pool_price(&mut deps.storage, &env, stable, &[Uint128(1000), Uint128(4000)])?;
+        match from_binary(&query(&deps, QueryMsg::GetTwap { window: 1000, time: 2_000 })?)? {
+            QueryMsgResponse::GetTwap { price_0_average, price_1_average, .. } => {
+                assert!(price_0_average > Uint128(precision) && price_0_average < Uint128(precision * 102 / 100));
+                assert!(price_1_average < Uint128(precision) && price_1_average > Uint128(precision * 98 / 100));
+            }
+            _ => panic!("Must return GetTwap"),
+        }
+
+        // a concentrated pool prices token 0 at the square of its sqrt price, whatever it holds
+        let mut deps = mock_deps();
+        store_concentrated_pool(&mut deps.storage, &ConcentratedPool {
+            sqrt_price: Uint128(2 * precision),
+            tick: 13_863,
+            liquidity: Uint128::zero(),
+            fee_growth_global_0: Uint128::zero(),
+            fee_growth_global_1: Uint128::zero(),
+        })?;
+        let concentrated = PoolType::ConcentratedLiquidity { tick_spacing: 60, initial_tick: 13_863 };
+        env.block.time = 1_000;
+        record_pool_price(&mut deps.storage, &env, concentrated, &[Uint128(1000), Uint128(1000)])?;
+        env.block.time = 2_000;
+        record_pool_price(&mut deps.storage, &env, concentrated, &[Uint128(1000), Uint128(1000)])?;
+        match from_binary(&query(&deps, QueryMsg::GetTwap { window: 1000, time: 2_000 })?)? {
+            QueryMsgResponse::GetTwap { price_0_average, price_1_average, .. } => {
+                assert_eq!(price_0_average, Uint128(4 * precision));
+                assert_eq!(price_1_average, Uint128(precision / 4));
+            }
+            _ => panic!("Must return GetTwap"),
+        }
+        Ok(())
+    }
 
-        let result = query(
-            &deps,
-            QueryMsg::GetWhiteListAddress {
+    #[test]
+    fn assert_calculate_zap_swap_amount() -> StdResult<()>{
+        // without fees it is sqrt(r * (r + a)) - r
+        let amount = calculate_zap_swap_amount(Uint256::from(10000u128), Uint256::from(1000000u128),
+            Uint256::zero(), Uint256::from(1u128))?;
+        assert_eq!(amount, Uint256::from(4987u128));
+        let amount = calculate_zap_swap_amount(Uint256::from(10000u128), Uint256::from(990000u128),
+            Uint256::from(300000u128), Uint256::from(100000000u128))?;
+        assert_eq!(amount, Uint256::from(4994u128));
+        Ok(())
+    }
+
+    #[test]
+    fn assert_zap_in_native_success()-> StdResult<()>{
+        let mut deps = mock_deps();
+        let env = mock_env("TESTA", &[Coin { denom: "uscrt".into(), amount: Uint128(10000u128) }]);
+        let token_pair = mk_native_token_pair();
+        let mut config = make_init_config(&mut deps, token_pair.clone())?;
+        config.lp_token_info.address = HumanAddr(LP_TOKEN.to_string());
+        store_config(&mut deps, &config)?;
+        let offer = TokenAmount { token: mk_native_token(), amount: Uint128(10000u128) };
+
+        let result = handle(&mut deps, env.clone(), HandleMsg::ZapIn {
+            offer: offer.clone(),
+            min_lp_tokens: Some(Uint128(5032u128)),
+        });
+        match result {
+            Err(StdError::GenericErr { .. }) => {}
+            _ => panic!("Must return error when fewer LP tokens than min_lp_tokens are minted"),
+        }
+
+        let result = handle(&mut deps, env, HandleMsg::ZapIn {
+            offer,
+            min_lp_tokens: Some(Uint128(5031u128)),
+        })?;
+        // 4994 swapped for 5006 of the other token, the rest is deposited with it
+        assert_eq!(result.log[3].value, "4994".to_string());
+        assert_eq!(result.log[5].value, "5006".to_string());
+        assert_eq!(result.log[8].value, "5031".to_string());
+        // only the lp mint, the dao fee on 4994 rounds down to zero
+        assert_eq!(result.messages.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn assert_calculate_balanced_deposit() -> StdResult<()>{
+        let pools = [Uint128(1000000u128), Uint128(2000000u128)];
+        let used = calculate_balanced_deposit(&[Uint128(100u128), Uint128(500u128)], &pools)?;
+        assert_eq!(used, [Uint128(100u128), Uint128(200u128)]);
+        let used = calculate_balanced_deposit(&[Uint128(500u128), Uint128(100u128)], &pools)?;
+        assert_eq!(used, [Uint128(50u128), Uint128(100u128)]);
+        Ok(())
+    }
+
+    #[test]
+    fn assert_add_liquidity_refunds_unbalanced_native_deposit()-> StdResult<()>{
+        let mut deps = mock_deps();
+        let env = mock_env("TESTA", &[Coin { denom: "uscrt".into(), amount: Uint128(2000u128) }]);
+        let token_pair = mk_native_token_pair();
+        let mut config = make_init_config(&mut deps, token_pair.clone())?;
+        config.lp_token_info.address = HumanAddr(LP_TOKEN.to_string());
+        store_config(&mut deps, &config)?;
+        let result = handle(&mut deps, env, HandleMsg::AddLiquidityToAMMContract {
+            deposit: TokenPairAmount {
+                pair: token_pair,
+                amount_0: Uint128(1000u128),
+                amount_1: Uint128(2000u128),
             },
-        )
-        .unwrap();
+            slippage: None,
+        })?;
+        // the native pool is 998000 without the deposit, so only 998 of it is used
+        assert_eq!(result.log[2].value, "1000".to_string());
+        assert_eq!(result.log[3].value, "1000".to_string());
+        assert_eq!(result.log[4].value, "998".to_string());
+        assert_eq!(result.log[6].value, "1002".to_string());
+        // transfer from, native refund and lp mint
+        assert_eq!(result.messages.len(), 3);
+        Ok(())
+    }
 
-        let response: QueryMsgResponse = from_binary(&result).unwrap();
+    #[test]
+    fn assert_first_deposit_locks_minimum_liquidity()-> StdResult<()>{
+        let mut deps = mock_deps();
+        let env = mock_env("TESTA", &[Coin { denom: "uscrt".into(), amount: Uint128(10000u128) }]);
+        let token_pair = mk_native_token_pair();
+        let mut config = make_init_config(&mut deps, token_pair.clone())?;
+        config.lp_token_info.address = HumanAddr(EMPTY_LP_TOKEN.to_string());
+        store_config(&mut deps, &config)?;
+        let result = handle(&mut deps, env, HandleMsg::AddLiquidityToAMMContract {
+            deposit: TokenPairAmount {
+                pair: token_pair,
+                amount_0: Uint128(10000u128),
+                amount_1: Uint128(10000u128),
+            },
+            slippage: None,
+        })?;
+        assert_eq!(result.log[2].value, "9000".to_string());
+        // transfer from, locked mint and depositor mint
+        assert_eq!(result.messages.len(), 3);
 
+        let response: QueryMsgResponse = from_binary(&query(&deps, QueryMsg::GetPairInfo)?)?;
         match response {
-            QueryMsgResponse::GetWhiteListAddress { addresses: stored } => {
-                assert_eq!(1, stored.len())
+            QueryMsgResponse::GetPairInfo { locked_liquidity, .. } => {
+                assert_eq!(locked_liquidity, Uint128(1000u128));
             }
-            _ => panic!("QueryResponse::ListExchanges"),
+            _ => panic!("Must return GetPairInfo"),
         }
         Ok(())
     }
 
-    
+    #[test]
+    fn assert_remove_liquidity_single_sided_success()-> StdResult<()>{
+        let mut deps = mock_deps();
+        let env = mock_env(LP_TOKEN, &[]);
+        let token_pair = mk_native_token_pair();
+        let mut config = make_init_config(&mut deps, token_pair.clone())?;
+        config.lp_token_info.address = HumanAddr(LP_TOKEN.to_string());
+        store_config(&mut deps, &config)?;
+        let result = handle(&mut deps, env, HandleMsg::Receive {
+            from: HumanAddr("TESTA".to_string()),
+            amount: Uint128(10000u128),
+            msg: Some(to_binary(&InvokeMsg::RemoveLiquidity {
+                recipient: HumanAddr("TESTA".to_string()),
+                min_amount_0: None,
+                min_amount_1: Some(Uint128(19870u128)),
+                withdraw_into: Some(mk_native_token()),
+            })?),
+        })?;
+        // 10000 of both sides, the token side less fees is swapped against the remaining 990000/990000 pool
+        assert_eq!(result.log[3].value, "0, 19870".to_string());
+        // dao fee, native refund and lp burn
+        assert_eq!(result.messages.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn assert_remove_liquidity_below_min_amount_fails()-> StdResult<()>{
+        let mut deps = mock_deps();
+        let env = mock_env(LP_TOKEN, &[]);
+        let token_pair = mk_native_token_pair();
+        let mut config = make_init_config(&mut deps, token_pair.clone())?;
+        config.lp_token_info.address = HumanAddr(LP_TOKEN.to_string());
+        store_config(&mut deps, &config)?;
+        let result = handle(&mut deps, env, HandleMsg::Receive {
+            from: HumanAddr("TESTA".to_string()),
+            amount: Uint128(10000u128),
+            msg: Some(to_binary(&InvokeMsg::RemoveLiquidity {
+                recipient: HumanAddr("TESTA".to_string()),
+                min_amount_0: Some(Uint128(10001u128)),
+                min_amount_1: None,
+                withdraw_into: None,
+            })?),
+        });
+        match result {
+            Err(StdError::GenericErr { .. }) => {}
+            _ => panic!("Must return error when the withdrawn amount is below min_amount_0"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn assert_paused_pair_still_allows_withdrawals()-> StdResult<()>{
+        let mut deps = mock_deps();
+        let token_pair = mk_native_token_pair();
+        let mut config = make_init_config(&mut deps, token_pair.clone())?;
+        config.lp_token_info.address = HumanAddr(LP_TOKEN.to_string());
+        store_config(&mut deps, &config)?;
+
+        match handle(&mut deps, mkenv("TESTA"), HandleMsg::SetAMMPairStatus { status: ContractStatus::AllPaused }) {
+            Err(StdError::Unauthorized { .. }) => {}
+            _ => panic!("Only the admin or the factory can pause the pair"),
+        }
+        handle(&mut deps, mkenv(FACTORY_CONTRACT_ADDRESS), HandleMsg::SetAMMPairStatus {
+            status: ContractStatus::AllPaused,
+        })?;
+
+        assert!(handle(&mut deps, mock_env(CUSTOM_TOKEN_2, &[]), HandleMsg::Receive {
+            from: HumanAddr("TESTA".to_string()),
+            amount: Uint128(1000u128),
+            msg: Some(to_binary(&InvokeMsg::SwapTokens {
+                expected_return: None,
+                to: None,
+                router_link: None,
+                callback_signature: None,
+                deadline: None,
+                trader: None,
+            })?),
+        }).is_err());
+        assert!(handle(&mut deps, mkenv("TESTA"), HandleMsg::AddLiquidityToAMMContract {
+            deposit: TokenPairAmount {
+                pair: token_pair.clone(),
+                amount_0: Uint128(1000u128),
+                amount_1: Uint128(1000u128),
+            },
+            slippage: None,
+        }).is_err());
+        let remove_liquidity = |withdraw_into| HandleMsg::Receive {
+            from: HumanAddr("TESTA".to_string()),
+            amount: Uint128(10000u128),
+            msg: Some(to_binary(&InvokeMsg::RemoveLiquidity {
+                recipient: HumanAddr("TESTA".to_string()),
+                min_amount_0: None,
+                min_amount_1: None,
+                withdraw_into,
+            }).unwrap()),
+        };
+        // withdrawing into one token swaps the other side
+        assert!(handle(&mut deps, mock_env(LP_TOKEN, &[]), remove_liquidity(Some(mk_native_token()))).is_err());
+        handle(&mut deps, mock_env(LP_TOKEN, &[]), remove_liquidity(None))?;
+        Ok(())
+    }
+
+    #[test]
+    fn assert_migration_hands_reserves_to_replacement()-> StdResult<()>{
+        let mut deps = mock_deps();
+        let token_pair = mk_native_token_pair();
+        let mut config = make_init_config(&mut deps, token_pair.clone())?;
+        config.lp_token_info.address = HumanAddr(LP_TOKEN.to_string());
+        store_config(&mut deps, &config)?;
+
+        let env = mkenv(FACTORY_CONTRACT_ADDRESS);
+        let seed = to_binary(&"SEED".to_string())?;
+        let entropy = to_binary(&"ENTROPY".to_string())?;
+        let callback = to_binary(&String::from("Welcome bytes"))?;
+        let migrate_to = || HandleMsg::MigrateTo {
+            new_code: ContractInstantiationInfo { code_hash: "NEW_CODE_HASH".to_string(), id: 1 },
+            prng_seed: seed.clone(),
+            entropy: entropy.clone(),
+            callback: Callback {
+                contract: mock_contract_link(FACTORY_CONTRACT_ADDRESS.to_string()),
+                msg: callback.clone(),
+            },
+        };
+        match handle(&mut deps, mkenv("TESTA"), migrate_to()) {
+            Err(StdError::Unauthorized { .. }) => {}
+            _ => panic!("Only the factory can upgrade the pair"),
+        }
+        let result = handle(&mut deps, env.clone(), migrate_to())?;
+        assert_eq!(result.messages.len(), 1);
+
+        // everything but the callback of the replacement is rejected from now on
+        match handle(&mut deps, env.clone(), HandleMsg::SetCustomFee { fee: None }) {
+            Err(StdError::GenericErr { .. }) => {}
+            _ => panic!("A pair being upgraded must reject messages"),
+        }
+        let key = create_viewing_key(&env, seed, entropy).0;
+        assert!(handle(&mut deps, mkenv("NEW_PAIR"), HandleMsg::CompleteMigration {
+            key: "WRONG_KEY".to_string(),
+        }).is_err());
+        let result = handle(&mut deps, mkenv("NEW_PAIR"), HandleMsg::CompleteMigration { key: key.clone() })?;
+        // both reserves, the minter and the admin of the LP token
+        assert_eq!(result.messages.len(), 4);
+        assert_eq!(result.log[1].value, "NEW_PAIR".to_string());
+        assert!(handle(&mut deps, mkenv("OTHER_PAIR"), HandleMsg::CompleteMigration { key }).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn assert_replacement_pair_can_be_upgraded_again()-> StdResult<()>{
+        let mut deps = mock_deps();
+        let token_pair = mk_native_token_pair();
+        let mut config = make_init_config(&mut deps, token_pair.clone())?;
+        config.lp_token_info.address = HumanAddr(LP_TOKEN.to_string());
+        store_config(&mut deps, &config)?;
+
+        let (init_msg, result) = upgrade_pair(&mut deps, "NEW_PAIR")?;
+        assert!(result.messages.contains(&lp_token_admin_msg("NEW_PAIR")?));
+
+        // the replacement is instantiated with the message sent by the old pair
+        let mut deps_v2 = mock_deps();
+        init(&mut deps_v2, mkenv(FACTORY_CONTRACT_ADDRESS), init_msg)?;
+        let (_, result) = upgrade_pair(&mut deps_v2, "THIRD_PAIR")?;
+        assert!(result.messages.contains(&snip20::set_minters_msg(
+            vec![HumanAddr::from("THIRD_PAIR")],
+            None,
+            256,
+            config.lp_token_info.code_hash.clone(),
+            HumanAddr::from(LP_TOKEN),
+        )?));
+        assert!(result.messages.contains(&lp_token_admin_msg("THIRD_PAIR")?));
+        Ok(())
+    }
+
+    #[test]
+    fn assert_replacement_of_paused_pair_stays_paused()-> StdResult<()>{
+        let mut deps = mock_deps();
+        let token_pair = mk_native_token_pair();
+        let mut config = make_init_config(&mut deps, token_pair.clone())?;
+        config.lp_token_info.address = HumanAddr(LP_TOKEN.to_string());
+        store_config(&mut deps, &config)?;
+        handle(&mut deps, mkenv(FACTORY_CONTRACT_ADDRESS), HandleMsg::SetAMMPairStatus {
+            status: ContractStatus::SwapsPaused,
+        })?;
+
+        let (init_msg, _) = upgrade_pair(&mut deps, "NEW_PAIR")?;
+        assert_eq!(init_msg.migration.as_ref().map(|migration| migration.status), Some(ContractStatus::SwapsPaused));
+        let mut deps_v2 = mock_deps();
+        init(&mut deps_v2, mkenv(FACTORY_CONTRACT_ADDRESS), init_msg)?;
+        assert_eq!(load_status(&deps_v2.storage)?, ContractStatus::SwapsPaused);
+        Ok(())
+    }
+
+    #[test]
+    fn assert_replacement_keeps_discounts_twap_and_liquidity_state()-> StdResult<()>{
+        let mut deps = mock_deps();
+        let token_pair = mk_native_token_pair();
+        let mut config = make_init_config(&mut deps, token_pair.clone())?;
+        config.lp_token_info.address = HumanAddr(LP_TOKEN.to_string());
+        store_config(&mut deps, &config)?;
+        let discounts = Some(FeeDiscounts {
+            source: FeeDiscountSource::TokenBalance { token: mock_contract_link(CUSTOM_TOKEN_2.to_string()) },
+            tiers: vec![FeeDiscountTier { min_amount: Uint128(1000), discount: 5000 }],
+            router: Some(HumanAddr("ROUTER".to_string())),
+        });
+        store_fee_discounts(&mut deps.storage, &discounts)?;
+        handle(&mut deps, mkenv("TESTA"), HandleMsg::SetDiscountViewingKey { key: "key".to_string() })?;
+        handle(&mut deps, mkenv("TESTB"), HandleMsg::SetDiscountViewingKey { key: "other key".to_string() })?;
+        store_root_k_last(&mut deps.storage, Uint128(1_000_000))?;
+        store_locked_liquidity(&mut deps.storage, Uint128(1000))?;
+        let mut env = mock_env(CONTRACT_ADDRESS, &[]);
+        env.block.time = 1_000;
+        record_pool_price(&mut deps.storage, &env, PoolType::ConstantProduct, &[Uint128(1000), Uint128(2000)])?;
+        env.block.time = 1_600;
+        record_pool_price(&mut deps.storage, &env, PoolType::ConstantProduct, &[Uint128(1000), Uint128(4000)])?;
+
+        let (init_msg, _) = upgrade_pair(&mut deps, "NEW_PAIR")?;
+        let mut deps_v2 = mock_deps();
+        init(&mut deps_v2, mkenv(FACTORY_CONTRACT_ADDRESS), init_msg)?;
+        assert_eq!(load_fee_discounts(&deps_v2.storage)?, discounts);
+        // viewing keys stay with the pair they were given to
+        assert_eq!(load_discount_viewing_key(&deps_v2.storage, &HumanAddr("TESTA".to_string()))?, None);
+        assert_eq!(load_discount_viewing_key(&deps_v2.storage, &HumanAddr("TESTB".to_string()))?, None);
+        assert_eq!(load_root_k_last(&deps_v2.storage)?, Uint128(1_000_000));
+        assert_eq!(load_locked_liquidity(&deps_v2.storage)?, Uint128(1000));
+        assert_eq!(load_price_accumulator(&deps_v2.storage)?, load_price_accumulator(&deps.storage)?);
+        assert_eq!(load_price_observations(&deps_v2.storage)?.len(), 2);
+        assert_eq!(load_price_observations(&deps_v2.storage)?, load_price_observations(&deps.storage)?);
+        Ok(())
+    }
+
+/// Quotes a swap of 1000 of token 0, then executes it once the offer is part of the
+/// balance of the pair. Both have to price it against the reserves from before the swap.
+fn assert_quote_matches_swap(pool_type: Option<PoolType>) -> StdResult<()> {
+    let mut deps = mock_deps();
+    let token_pair = mk_native_token_pair();
+    let config = make_init_config_with_pool_type(&mut deps, token_pair.clone(), pool_type)?;
+    let offer = mk_custom_token_amount(Uint128::from(1000u128), token_pair.clone());
+    let quote: QueryMsgResponse = from_binary(&query(&deps, QueryMsg::SwapSimulation { offer: offer.clone() })?)?;
+    let quoted_amount = match quote {
+        QueryMsgResponse::SwapSimulation { result, .. } => result.return_amount,
+        _ => panic!("Wrong response for SwapSimulation"),
+    };
+    // the factory fees take 2 of the 1000
+    let pool = Uint256::from(1_000_000u128);
+    let expected_amount = calculate_swap_output(config.pool_type, Uint256::from(998u128), pool, pool)?;
+    assert_eq!(quoted_amount, Uint128(expected_amount.clamp_u128()?));
+
+    deps.querier.token_balance += 1000;
+    let result = swap(&mut deps, mock_env(CONTRACT_ADDRESS, &[]), config, HumanAddr("TESTA".to_string()),
+        None, offer, None, None, None, None)?;
+    assert_eq!(result.log[3].value, quoted_amount.to_string());
+    Ok(())
+}
+
+/// Upgrades the pair through the factory and completes it from `new_pair`, returns the
+/// init message of the replacement.
+fn upgrade_pair<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    new_pair: &str) -> StdResult<(InitMsg, HandleResponse)> {
+    let env = mkenv(FACTORY_CONTRACT_ADDRESS);
+    let seed = to_binary(&"SEED".to_string())?;
+    let entropy = to_binary(&"ENTROPY".to_string())?;
+    let result = handle(deps, env.clone(), HandleMsg::MigrateTo {
+        new_code: ContractInstantiationInfo { code_hash: "NEW_CODE_HASH".to_string(), id: 1 },
+        prng_seed: seed.clone(),
+        entropy: entropy.clone(),
+        callback: Callback {
+            contract: mock_contract_link(FACTORY_CONTRACT_ADDRESS.to_string()),
+            msg: to_binary(&String::from("Welcome bytes"))?,
+        },
+    })?;
+    let init_msg: InitMsg = match &result.messages[0] {
+        CosmosMsg::Wasm(WasmMsg::Instantiate { msg, .. }) => from_binary(msg)?,
+        _ => panic!("The replacement has to be instantiated"),
+    };
+    let key = create_viewing_key(&env, seed, entropy).0;
+    let result = handle(deps, mkenv(new_pair), HandleMsg::CompleteMigration { key })?;
+    Ok((init_msg, result))
+}
+
+fn lp_token_admin_msg(new_pair: &str) -> StdResult<CosmosMsg> {
+    Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: HumanAddr::from(LP_TOKEN),
+        callback_code_hash: "CODE_HASH".to_string(),
+        msg: to_binary(&Snip20ComposableHandleMsg::ChangeAdmin {
+            address: HumanAddr::from(new_pair),
+            padding: None,
+        })?,
+        send: vec![],
+    }))
+}
+
 fn make_init_config<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>, 
     token_pair: TokenPair<HumanAddr>) -> StdResult<Config<HumanAddr>> {    
+    make_init_config_with_pool_type(deps, token_pair, None)
+}
+
+fn make_init_config_with_pool_type<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>, 
+    token_pair: TokenPair<HumanAddr>,
+    pool_type: Option<PoolType>) -> StdResult<Config<HumanAddr>> {    
     let seed = to_binary(&"SEED".to_string())?;
     let entropy = to_binary(&"ENTROPY".to_string())?;
     let env = mkenv(CONTRACT_ADDRESS);  
@@ -375,6 +1455,9 @@ fn make_init_config<S: Storage, A: Api, Q: Querier>(
             msg: to_binary(&String::from("Welcome bytes"))?,
         }),
         staking_contract: None,
+        pool_type,
+        oracle: None,
+        migration: None,
     };         
     assert!(init(deps, env.clone(), msg).is_ok());
     let config = load_config(deps)?;
@@ -451,7 +1534,8 @@ fn mk_amm_settings() -> AMMSettings<HumanAddr>{
         shade_dao_address: ContractLink{
             code_hash: "CODEHAS".to_string(),
             address: HumanAddr("TEST".to_string())
-        }
+        },
+        shade_dao_fee_mode: ShadeDaoFeeMode::Transfer,
     }
 }
 
@@ -466,6 +1550,9 @@ fn mock_config(env: Env) -> StdResult<Config<HumanAddr>>
         pair:      mk_token_pair(),
         contract_addr: HumanAddr::from(MOCK_CONTRACT_ADDR),
         viewing_key:  create_viewing_key(&env, seed.clone(), entropy.clone()),
+        custom_fee: None,
+        pool_type: PoolType::ConstantProduct,
+        oracle: None,
     })
 }
 
@@ -487,12 +1574,14 @@ fn mock_deps() -> Extern<MockStorage, MockApi, MockQuerier> {
     Extern {
         storage: MockStorage::default(),
         api: MockApi::new(123),
-        querier: MockQuerier { portion: 2500 },
+        querier: MockQuerier { portion: 2500, token_balance: 1000000 },
     }
 }
 
 struct MockQuerier{
     portion: u128,
+    /// Balance of the pair, and of every trader, in `CUSTOM_TOKEN_2`.
+    token_balance: u128,
 }
 
 impl Querier for MockQuerier {
@@ -501,7 +1590,7 @@ impl Querier for MockQuerier {
         match &request {
             QueryRequest::Wasm(msg) => {
                 match msg {
-                    WasmQuery::Smart { contract_addr, .. } => {
+                    WasmQuery::Smart { contract_addr, msg, .. } => {
                         println!("Factory Address :: {}", contract_addr);
                         match contract_addr.as_str() {
                             FACTORY_CONTRACT_ADDRESS => {
@@ -511,7 +1600,8 @@ impl Querier for MockQuerier {
                                     shade_dao_address: ContractLink {
                                         address: HumanAddr(String::from("DAO")),
                                         code_hash: "".to_string(),
-                                    }
+                                    },
+                                    shade_dao_fee_mode: ShadeDaoFeeMode::Transfer,
                                 };
                                 let response = FactoryQueryResponse::GetAMMSettings {
                                     settings: amm_settings
@@ -521,7 +1611,7 @@ impl Querier for MockQuerier {
                             CUSTOM_TOKEN_2 => {                                
                                 QuerierResult::Ok(to_binary(&IntBalanceResponse {
                                     balance: Balance {
-                                        amount: Uint128(1000000),
+                                        amount: Uint128(self.token_balance),
                                     },
                                 }))
                             },
@@ -533,6 +1623,38 @@ impl Querier for MockQuerier {
                                     }
                                 }))
                             }
+                            LP_TOKEN => {
+                                QuerierResult::Ok(to_binary(&IntTokenInfoResponse {
+                                    token_info: TokenInfo {
+                                        name: "LP".to_string(),
+                                        symbol: "LP".to_string(),
+                                        decimals: 18,
+                                        total_supply: Some(Uint128(1000000u128)),
+                                    },
+                                }))
+                            }
+                            ORACLE_ADDRESS => {
+                                MockOracle {
+                                    instance: mock_contract_link(ORACLE_ADDRESS.to_string()),
+                                    price: Uint128(ORACLE_PRICE),
+                                    last_updated: ORACLE_UPDATED,
+                                }.query(msg)
+                            }
+                            FAILING_CONTRACT => {
+                                QuerierResult::Ok(Err(StdError::generic_err(
+                                    "Wrong viewing key for this address or viewing key not set",
+                                )))
+                            }
+                            EMPTY_LP_TOKEN => {
+                                QuerierResult::Ok(to_binary(&IntTokenInfoResponse {
+                                    token_info: TokenInfo {
+                                        name: "LP".to_string(),
+                                        symbol: "LP".to_string(),
+                                        decimals: 18,
+                                        total_supply: Some(Uint128::zero()),
+                                    },
+                                }))
+                            }
                             _ => unimplemented!()
                         }
                     },                  
@@ -625,6 +1747,11 @@ struct IntBalanceResponse {
     pub balance: Balance,
 }
 
+#[derive(Serialize, Deserialize)]
+struct IntTokenInfoResponse {
+    pub token_info: TokenInfo,
+}
+
 }
 
 
@@ -0,0 +1,204 @@
+use shadeswap_shared::fadroma::{
+    scrt::{StdError, StdResult},
+    scrt_uint256::Uint256,
+};
+
+/// Scale of the fixed point numbers used by the weighted math.
+pub const ONE: u128 = 1_000_000_000_000_000_000;
+/// Weights are percentages of the pool value and have to add up to this.
+pub const TOTAL_WEIGHT: u8 = 100;
+const LN_2: i128 = 693_147_180_559_945_309;
+/// Upper bound of the error of `pow`, 10^-14. Results are moved by it in favour of the pool.
+const MAX_POW_RELATIVE_ERROR: u128 = 10_000;
+
+pub fn assert_weights(weight_0: u8, weight_1: u8) -> StdResult<()> {
+    if weight_0 == 0 || weight_1 == 0 || weight_0 as u16 + weight_1 as u16 != TOTAL_WEIGHT as u16 {
+        return Err(StdError::generic_err(format!(
+            "Pool weights have to be greater than zero and add up to {}.",
+            TOTAL_WEIGHT
+        )));
+    }
+    Ok(())
+}
+
+fn one() -> Uint256 {
+    Uint256::from(ONE)
+}
+
+fn overflow() -> StdError {
+    StdError::generic_err("Weighted pool math overflow.")
+}
+
+/// Natural logarithm of `x / ONE`, scaled by `ONE`.
+fn ln(x: Uint256) -> StdResult<i128> {
+    if x == Uint256::zero() {
+        return Err(StdError::generic_err("Logarithm of zero."));
+    }
+
+    // x = m * 2^k with m in [1, 2)
+    let two = Uint256::from(2u8);
+    let upper = (one() * two)?;
+    let mut m = x;
+    let mut k: i128 = 0;
+    while m >= upper {
+        m = (m / two)?;
+        k += 1;
+    }
+    while m < one() {
+        m = (m * two)?;
+        k -= 1;
+    }
+
+    // ln(m) = 2 * atanh((m - 1) / (m + 1)), the series converges quickly for m in [1, 2)
+    let unit = ONE as i128;
+    let m = m.clamp_u128()? as i128;
+    let z = (m - unit) * unit / (m + unit);
+    let z_squared = z * z / unit;
+    let mut term = z;
+    let mut sum = 0i128;
+    let mut n = 1i128;
+    while term != 0 {
+        sum += term / n;
+        term = term * z_squared / unit;
+        n += 2;
+    }
+
+    Ok(k * LN_2 + 2 * sum)
+}
+
+/// `e^(x / ONE)`, scaled by `ONE`.
+fn exp(x: i128) -> StdResult<Uint256> {
+    // x = k * ln(2) + r with r in [0, ln(2))
+    let mut k = x / LN_2;
+    if x < 0 && x % LN_2 != 0 {
+        k -= 1;
+    }
+    let r = x - k * LN_2;
+
+    let unit = ONE as i128;
+    let mut term = unit;
+    let mut sum = unit;
+    let mut n = 1i128;
+    while term != 0 {
+        term = term * r / unit / n;
+        sum += term;
+        n += 1;
+    }
+
+    let two = Uint256::from(2u8);
+    let mut result = Uint256::from(sum as u128);
+    for _ in 0..k.abs() {
+        result = if k > 0 { (result * two)? } else { (result / two)? };
+    }
+    Ok(result)
+}
+
+/// `base^exponent`, both scaled by `ONE`, rounded up by the maximum error.
+fn pow_up(base: Uint256, exponent: Uint256) -> StdResult<Uint256> {
+    let logarithm = ln(base)?;
+    let magnitude = ((Uint256::from(logarithm.abs() as u128) * exponent)? / one())?;
+    let magnitude = magnitude.clamp_u128()?;
+    if magnitude > i128::MAX as u128 {
+        return Err(overflow());
+    }
+    let power = if logarithm < 0 {
+        exp(-(magnitude as i128))?
+    } else {
+        exp(magnitude as i128)?
+    };
+    let error = (((power * Uint256::from(MAX_POW_RELATIVE_ERROR))? / one())? + Uint256::from(1u8))?;
+    power + error
+}
+
+fn ceil_div(nominator: Uint256, denominator: Uint256) -> StdResult<Uint256> {
+    Ok((((nominator + denominator)? - Uint256::from(1u8))? / denominator)?)
+}
+
+/// `weight_in / weight_out` scaled by `ONE`.
+fn weight_ratio(weight_in: u8, weight_out: u8) -> StdResult<Uint256> {
+    (Uint256::from(weight_in) * one())? / Uint256::from(weight_out)
+}
+
+/// `out = y * (1 - (x / (x + amount))^(w_in / w_out))`
+pub fn calculate_swap_output(
+    weight_in: u8,
+    weight_out: u8,
+    amount: Uint256,
+    token0_pool_balance: Uint256,
+    token1_pool_balance: Uint256,
+) -> StdResult<Uint256> {
+    let base = ceil_div((token0_pool_balance * one())?, (token0_pool_balance + amount)?)?;
+    let power = pow_up(base, weight_ratio(weight_in, weight_out)?)?;
+    if power >= one() {
+        return Ok(Uint256::zero());
+    }
+    (token1_pool_balance * (one() - power)?)? / one()
+}
+
+/// `in = x * ((y / (y - amount))^(w_out / w_in) - 1)`
+pub fn calculate_swap_input(
+    weight_in: u8,
+    weight_out: u8,
+    amount: Uint256,
+    token0_pool_balance: Uint256,
+    token1_pool_balance: Uint256,
+) -> StdResult<Uint256> {
+    let base = ceil_div((token1_pool_balance * one())?, (token1_pool_balance - amount)?)?;
+    let power = pow_up(base, weight_ratio(weight_out, weight_in)?)?;
+    ceil_div((token0_pool_balance * (power - one())?)?, one())
+}
+
+/// Price of the offered token in the returned token, `(x / w_in) / (y / w_out)`.
+pub fn calculate_spot_price(
+    weight_in: u8,
+    weight_out: u8,
+    token0_pool_balance: Uint256,
+    token1_pool_balance: Uint256,
+) -> StdResult<Uint256> {
+    (token0_pool_balance * Uint256::from(weight_out))?
+        / (token1_pool_balance * Uint256::from(weight_in))?
+}
+
+/// Value of the pool `x^w_0 * y^w_1`, used as the LP supply of the first deposit.
+pub fn calculate_invariant(
+    weight_0: u8,
+    weight_1: u8,
+    token0_pool_balance: Uint256,
+    token1_pool_balance: Uint256,
+) -> StdResult<Uint256> {
+    let total = TOTAL_WEIGHT as i128;
+    let logarithm = (ln((token0_pool_balance * one())?)? * weight_0 as i128
+        + ln((token1_pool_balance * one())?)? * weight_1 as i128)
+        / total;
+    // rounded down, the invariant is only ever handed out as LP tokens
+    exp(logarithm)? / one()
+}
+
+/// Part of a single sided deposit which has to be swapped so that the rest and the
+/// swap output match the pool ratio afterwards, found by bisection like for stable pools.
+pub fn calculate_zap_swap_amount(
+    weight_in: u8,
+    weight_out: u8,
+    amount: Uint256,
+    reserve_in: Uint256,
+    reserve_out: Uint256,
+    fee_nom: Uint256,
+    fee_denom: Uint256,
+) -> StdResult<Uint256> {
+    let mut low = Uint256::zero();
+    let mut high = amount;
+    while low < high {
+        let swap_in = ((low + high)? / Uint256::from(2u8))?;
+        let net_in = (swap_in - ((swap_in * fee_nom)? / fee_denom)?)?;
+        let swap_out = calculate_swap_output(weight_in, weight_out, net_in, reserve_in, reserve_out)?;
+        // rest of the deposit against the pool ratio after the swap
+        let deposit_ratio = ((amount - swap_in)? * (reserve_out - swap_out)?)?;
+        let pool_ratio = (swap_out * (reserve_in + swap_in)?)?;
+        if deposit_ratio > pool_ratio {
+            low = (swap_in + Uint256::from(1u8))?;
+        } else {
+            high = swap_in;
+        }
+    }
+    Ok(low)
+}
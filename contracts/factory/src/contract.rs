@@ -1,21 +1,23 @@
 use crate::state::{
-    config_read, config_write, get_address_for_pair, load_amm_pairs, load_prng_seed,
-    save_amm_pairs, save_prng_seed, Config,
+    config_read, config_write, get_address_for_pair, load_amm_pairs, load_amm_pairs_count, load_pending_upgrade,
+    load_pair_code_hash, load_prng_seed, remove_pending_upgrade, replace_amm_pair_address, save_amm_pairs,
+    save_pair_code_hash, save_pending_upgrade, save_prng_seed, Config, PendingUpgrade, PAGINATION_LIMIT,
 };
 use shadeswap_shared::{
-    admin::{{apply_admin_guard, set_admin_guard, store_admin, load_admin }},
-    amm_pair::AMMPair,
+    admin::{{apply_admin_guard, set_admin_guard, store_admin, load_admin, load_status,
+        set_status_guard, ContractStatus }},
+    amm_pair::{AMMPair, CustomFee, OracleSettings, PoolType},
     fadroma::{
         scrt::{
             log, to_binary, Api, Binary, CosmosMsg, Env, Extern, HandleResponse, HumanAddr,
             InitResponse, Querier, StdError, StdResult, Storage, WasmMsg,
         },
         scrt_callback::Callback,
-        scrt_link::ContractLink,
+        scrt_link::{ContractInstantiationInfo, ContractLink},
         scrt_storage::{load, remove, save},
     },
     msg::{
-        amm_pair::{{InitMsg as AMMPairInitMsg }},
+        amm_pair::{{InitMsg as AMMPairInitMsg, HandleMsg as AMMPairHandleMsg }},
         factory::{HandleMsg, InitMsg, QueryMsg, QueryResponse},
     },
     stake_contract::StakingContractInit,
@@ -29,6 +31,7 @@ pub fn init<S: Storage, A: Api, Q: Querier>(
     env: Env,
     msg: InitMsg,
 ) -> StdResult<InitResponse> {
+    msg.amm_settings.validate()?;
     save_prng_seed(&mut deps.storage, &msg.prng_seed)?;
     config_write(deps, &Config::from_init_msg(msg))?;
     store_admin(deps, &env.message.sender.clone())?;
@@ -41,13 +44,23 @@ pub fn handle<S: Storage, A: Api, Q: Querier>(
     msg: HandleMsg,
 ) -> StdResult<HandleResponse> {
     return match msg {
-        HandleMsg::CreateAMMPair { pair, entropy, staking_contract } => create_pair(deps, env, pair, entropy, staking_contract),
+        HandleMsg::CreateAMMPair { pair, entropy, staking_contract, pool_type, oracle } => {
+            create_pair(deps, env, pair, entropy, staking_contract, pool_type, oracle)
+        }
         HandleMsg::SetConfig { .. } => set_config(deps, env, msg),
         HandleMsg::AddAMMPairs { amm_pairs } => add_amm_pairs(deps, env, amm_pairs),
         HandleMsg::RegisterAMMPair { pair, signature } => {
             register_amm_pair(deps, env, pair, signature)
         },
         HandleMsg::SetFactoryAdmin {admin} => set_admin_guard(deps,env,admin),       
+        HandleMsg::SetFactoryStatus { status } => set_status_guard(deps, env, status),
+        HandleMsg::SetPairsStatus { status } => set_pairs_status(deps, env, status),
+        HandleMsg::SetPairFee { pair, fee } => set_pair_fee(deps, env, pair, fee),
+        HandleMsg::SetPairOracle { pair, oracle } => set_pair_oracle(deps, env, pair, oracle),
+        HandleMsg::UpgradePairs { pairs, new_code } => upgrade_pairs(deps, env, pairs, new_code),
+        HandleMsg::RegisterUpgradedAMMPair { pair, signature } => {
+            register_upgraded_amm_pair(deps, env, pair, signature)
+        }
     };
 }
 
@@ -76,6 +89,8 @@ fn register_amm_pair<S: Storage, A: Api, Q: Querier>(
     signature: Binary,
 ) -> StdResult<HandleResponse> {  
     ensure_correct_signature(&mut deps.storage, signature)?;
+    let config = config_read(&deps)?;
+    save_pair_code_hash(deps, &pair, &config.pair_contract.code_hash)?;
     let amm_pair = AMMPair {
         pair,
         address: env.message.sender.clone(),
@@ -99,6 +114,11 @@ pub fn add_amm_pairs<S: Storage, A: Api, Q: Querier>(
     amm_pairs: Vec<AMMPair<HumanAddr>>,
 ) -> StdResult<HandleResponse> {
     apply_admin_guard(env.message.sender.clone(), &deps.storage)?;
+    // pairs added by hand run the current pair code
+    let config = config_read(&deps)?;
+    for amm_pair in amm_pairs.iter() {
+        save_pair_code_hash(deps, &amm_pair.pair, &config.pair_contract.code_hash)?;
+    }
     save_amm_pairs(deps, amm_pairs)?;
 
     Ok(HandleResponse {
@@ -146,19 +166,32 @@ pub fn set_config<S: Storage, A: Api, Q: Querier>(
         amm_settings,
     } = msg
     {
+        apply_admin_guard(env.message.sender.clone(), &deps.storage)?;
         let mut config = config_read(&deps)?;
+        let mut logs = vec![log("action", "set_config")];
         if let Some(new_value) = pair_contract {
+            logs.push(log("old_pair_contract", format!("{:?}", config.pair_contract)));
+            logs.push(log("new_pair_contract", format!("{:?}", new_value)));
             config.pair_contract = new_value;
         }
 
         if let Some(new_value) = lp_token_contract {
+            logs.push(log("old_lp_token_contract", format!("{:?}", config.lp_token_contract)));
+            logs.push(log("new_lp_token_contract", format!("{:?}", new_value)));
             config.lp_token_contract = new_value;
         }
+
+        if let Some(new_value) = amm_settings {
+            new_value.validate()?;
+            logs.push(log("old_amm_settings", format!("{:?}", config.amm_settings)));
+            logs.push(log("new_amm_settings", format!("{:?}", new_value)));
+            config.amm_settings = new_value;
+        }
         config_write(deps, &config)?;
 
         Ok(HandleResponse {
             messages: vec![],
-            log: vec![],
+            log: logs,
             data: None,
         })
     } else {
@@ -185,11 +218,16 @@ pub fn create_pair<S: Storage, A: Api, Q: Querier>(
     env: Env,
     pair: TokenPair<HumanAddr>,
     entropy: Binary,
-    staking_contract: Option<StakingContractInit>
+    staking_contract: Option<StakingContractInit>,
+    pool_type: Option<PoolType>,
+    oracle: Option<OracleSettings<HumanAddr>>,
 ) -> StdResult<HandleResponse> {
     let mut config = config_read(&deps)?;
     println!("create_pair caller {}", env.message.sender.clone());
     apply_admin_guard(env.message.sender.clone(), &deps.storage)?;
+    if load_status(&deps.storage)? != ContractStatus::Normal {
+        return Err(StdError::generic_err("Creating pairs is paused."));
+    }
     //Used for verifying callback
     let signature = create_signature(&env)?;
     save(&mut deps.storage, EPHEMERAL_STORAGE_KEY, &signature)?;
@@ -222,7 +260,10 @@ pub fn create_pair<S: Storage, A: Api, Q: Querier>(
                 entropy,
                 prng_seed: load_prng_seed(&deps.storage)?,
                 admin: Some(env.message.sender.clone()),
-                staking_contract: staking_contract
+                staking_contract: staking_contract,
+                pool_type,
+                oracle,
+                migration: None,
             },)?,
         })],
         log: vec![log("action", "create_exchange"), log("pair", pair)],
@@ -230,6 +271,172 @@ pub fn create_pair<S: Storage, A: Api, Q: Querier>(
     })
 }
 
+pub fn set_pair_fee<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    pair: TokenPair<HumanAddr>,
+    fee: Option<CustomFee>,
+) -> StdResult<HandleResponse> {
+    apply_admin_guard(env.message.sender.clone(), &deps.storage)?;
+    if let Some(fee) = &fee {
+        fee.validate()?;
+    }
+    let address = get_address_for_pair(deps, &pair)?;
+
+    Ok(HandleResponse {
+        messages: vec![CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: address.clone(),
+            callback_code_hash: load_pair_code_hash(deps, &pair)?,
+            msg: to_binary(&AMMPairHandleMsg::SetCustomFee { fee })?,
+            send: vec![],
+        })],
+        log: vec![
+            log("action", "set_pair_fee"),
+            log("pair", pair),
+            log("address", address),
+            log("fee", format!("{:?}", fee)),
+        ],
+        data: None,
+    })
+}
+
+pub fn set_pair_oracle<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    pair: TokenPair<HumanAddr>,
+    oracle: OracleSettings<HumanAddr>,
+) -> StdResult<HandleResponse> {
+    apply_admin_guard(env.message.sender.clone(), &deps.storage)?;
+    let address = get_address_for_pair(deps, &pair)?;
+
+    Ok(HandleResponse {
+        messages: vec![CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: address.clone(),
+            callback_code_hash: load_pair_code_hash(deps, &pair)?,
+            msg: to_binary(&AMMPairHandleMsg::SetOracle { oracle: oracle.clone() })?,
+            send: vec![],
+        })],
+        log: vec![
+            log("action", "set_pair_oracle"),
+            log("pair", pair),
+            log("address", address),
+            log("oracle", oracle.contract.address),
+        ],
+        data: None,
+    })
+}
+
+pub fn set_pairs_status<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    status: ContractStatus,
+) -> StdResult<HandleResponse> {
+    apply_admin_guard(env.message.sender.clone(), &deps.storage)?;
+    let count = load_amm_pairs_count(&deps.storage)?;
+
+    let mut messages = vec![];
+    let mut start = 0;
+    while start < count {
+        for amm_pair in load_amm_pairs(deps, Pagination { start, limit: PAGINATION_LIMIT })? {
+            messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: amm_pair.address,
+                callback_code_hash: load_pair_code_hash(deps, &amm_pair.pair)?,
+                msg: to_binary(&AMMPairHandleMsg::SetAMMPairStatus { status })?,
+                send: vec![],
+            }));
+        }
+        start += PAGINATION_LIMIT as u64;
+    }
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![
+            log("action", "set_pairs_status"),
+            log("status", format!("{:?}", status)),
+            log("pairs", count),
+        ],
+        data: None,
+    })
+}
+
+pub fn upgrade_pairs<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    pairs: Vec<TokenPair<HumanAddr>>,
+    new_code: ContractInstantiationInfo,
+) -> StdResult<HandleResponse> {
+    apply_admin_guard(env.message.sender.clone(), &deps.storage)?;
+    let mut config = config_read(&deps)?;
+    let signature = create_signature(&env)?;
+    let prng_seed = load_prng_seed(&deps.storage)?;
+
+    let mut messages = vec![];
+    let mut logs = vec![log("action", "upgrade_pairs"), log("code_id", new_code.id)];
+    for pair in pairs {
+        let address = get_address_for_pair(deps, &pair)?;
+        let code_hash = load_pair_code_hash(deps, &pair)?;
+        save_pending_upgrade(deps, &pair, &PendingUpgrade {
+            signature: signature.clone(),
+            code_hash: new_code.code_hash.clone(),
+        })?;
+        messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: address.clone(),
+            callback_code_hash: code_hash,
+            msg: to_binary(&AMMPairHandleMsg::MigrateTo {
+                new_code: new_code.clone(),
+                prng_seed: prng_seed.clone(),
+                entropy: signature.clone(),
+                callback: Callback {
+                    contract: ContractLink {
+                        address: env.contract.address.clone(),
+                        code_hash: env.contract_code_hash.clone(),
+                    },
+                    msg: to_binary(&HandleMsg::RegisterUpgradedAMMPair {
+                        pair: pair.clone(),
+                        signature: signature.clone(),
+                    })?,
+                },
+            })?,
+            send: vec![],
+        }));
+        logs.push(log("pair", address));
+    }
+    // pairs created from now on use the new code as well
+    config.pair_contract = new_code;
+    config_write(deps, &config)?;
+
+    Ok(HandleResponse {
+        messages,
+        log: logs,
+        data: None,
+    })
+}
+
+fn register_upgraded_amm_pair<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    pair: TokenPair<HumanAddr>,
+    signature: Binary,
+) -> StdResult<HandleResponse> {
+    let pending = match load_pending_upgrade(deps, &pair)? {
+        Some(pending) if pending.signature == signature => pending,
+        _ => return Err(StdError::unauthorized()),
+    };
+    remove_pending_upgrade(deps, &pair)?;
+    replace_amm_pair_address(deps, &pair, &env.message.sender)?;
+    save_pair_code_hash(deps, &pair, &pending.code_hash)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "register_upgraded_amm_pair"),
+            log("pair", pair),
+            log("address", env.message.sender),
+        ],
+        data: None,
+    })
+}
+
 fn ensure_correct_signature(storage: &mut impl Storage, signature: Binary) -> StdResult<()> {
     let stored_signature: Binary = load(storage, EPHEMERAL_STORAGE_KEY)?.unwrap_or_default();
 
@@ -19,6 +19,8 @@ use secret_toolkit::utils::{HandleCallback, InitCallback, Query};
 const NS_AMM_PAIRS: &[u8] = b"amm_pairs";
 const AMM_PAIR_COUNT_KEY: &[u8] = b"amm_pairs_count";
 const PRNG_KEY: &[u8] = b"prng_seed";
+const NS_PENDING_UPGRADES: &[u8] = b"pending_upgrades";
+const NS_PAIR_CODE_HASHES: &[u8] = b"pair_code_hashes";
 
 pub static CONFIG_KEY: &[u8] = b"config";
 pub const PAGINATION_LIMIT: u8 = 30;
@@ -135,6 +137,85 @@ pub(crate) fn get_address_for_pair<S: Storage, A: Api, Q: Querier>(
     deps.api.human_address(&canonical)
 }
 
+/// Points an upgraded pair at the address of its replacement.
+pub(crate) fn replace_amm_pair_address<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    pair: &TokenPair<HumanAddr>,
+    address: &HumanAddr,
+) -> StdResult<()> {
+    let key = generate_pair_key(&pair.canonize(&deps.api)?);
+    let address = deps.api.canonical_address(address)?;
+    ns_save(&mut deps.storage, NS_AMM_PAIRS, &key, &address)?;
+
+    let count = load_amm_pairs_count(&deps.storage)?;
+    for i in 0..count {
+        let index = i.to_string();
+        let mut exchange: AMMPair<CanonicalAddr> =
+            ns_load(&deps.storage, NS_AMM_PAIRS, index.as_bytes())?
+                .ok_or_else(|| StdError::generic_err("AMMPair doesn't exist in storage."))?;
+        if generate_pair_key(&exchange.pair) == key {
+            exchange.address = address;
+            return ns_save(&mut deps.storage, NS_AMM_PAIRS, index.as_bytes(), &exchange);
+        }
+    }
+
+    Err(StdError::generic_err("AMMPair doesn't exist in storage."))
+}
+
+/// Code hash of the contract currently managing each pair, keyed by the pair. Pairs keep
+/// their code hash when others are upgraded.
+pub(crate) fn save_pair_code_hash<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    pair: &TokenPair<HumanAddr>,
+    code_hash: &String,
+) -> StdResult<()> {
+    let key = generate_pair_key(&pair.canonize(&deps.api)?);
+    ns_save(&mut deps.storage, NS_PAIR_CODE_HASHES, &key, code_hash)
+}
+
+pub(crate) fn load_pair_code_hash<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    pair: &TokenPair<HumanAddr>,
+) -> StdResult<String> {
+    let key = generate_pair_key(&pair.canonize(&deps.api)?);
+    ns_load(&deps.storage, NS_PAIR_CODE_HASHES, &key)?
+        .ok_or_else(|| StdError::generic_err("Code hash of the pair doesn't exist in storage."))
+}
+
+/// Handed out to a pair being upgraded, its replacement calls back with `signature`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub(crate) struct PendingUpgrade {
+    pub signature: Binary,
+    pub code_hash: String,
+}
+
+/// Upgrades started for pairs, keyed by the pair.
+pub(crate) fn save_pending_upgrade<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    pair: &TokenPair<HumanAddr>,
+    pending: &PendingUpgrade,
+) -> StdResult<()> {
+    let key = generate_pair_key(&pair.canonize(&deps.api)?);
+    ns_save(&mut deps.storage, NS_PENDING_UPGRADES, &key, pending)
+}
+
+pub(crate) fn load_pending_upgrade<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    pair: &TokenPair<HumanAddr>,
+) -> StdResult<Option<PendingUpgrade>> {
+    let key = generate_pair_key(&pair.canonize(&deps.api)?);
+    ns_load(&deps.storage, NS_PENDING_UPGRADES, &key)
+}
+
+pub(crate) fn remove_pending_upgrade<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    pair: &TokenPair<HumanAddr>,
+) -> StdResult<()> {
+    let key = generate_pair_key(&pair.canonize(&deps.api)?);
+    ns_remove(&mut deps.storage, NS_PENDING_UPGRADES, &key);
+    Ok(())
+}
+
 pub(crate) fn load_amm_pairs<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
     pagination: Pagination,
@@ -1,6 +1,6 @@
 
 use shadeswap_shared::amm_pair::Fee;
-use shadeswap_shared::amm_pair::AMMSettings;
+use shadeswap_shared::amm_pair::{AMMSettings, ShadeDaoFeeMode};
 use shadeswap_shared::msg::factory::InitMsg;
 pub use shadeswap_shared::{
     fadroma::{
@@ -34,6 +34,7 @@ use super::*;
     use crate::state::config_read;
     use crate::state::config_write;
     use shadeswap_shared::amm_pair::AMMPair;
+    use shadeswap_shared::admin::ContractStatus;
     use shadeswap_shared::msg::factory::HandleMsg;
     use shadeswap_shared::msg::factory::QueryMsg;
     pub use shadeswap_shared::{
@@ -87,6 +88,40 @@ use super::*;
         Ok(())
     }
 
+    #[test]
+    fn set_config_updates_amm_settings() -> StdResult<()> {
+        let ref mut deps = mkdeps();
+        let env = mkenv("admin");
+        init(deps, env.clone(), (&mkconfig(0)).into())?;
+
+        let mut amm_settings = mkconfig(0).amm_settings;
+        amm_settings.lp_fee = Fee::new(25, 100_000);
+        let msg = HandleMsg::SetConfig {
+            pair_contract: None,
+            lp_token_contract: None,
+            amm_settings: Some(amm_settings.clone()),
+        };
+        match handle(deps, mkenv("user"), msg.clone()) {
+            Err(StdError::Unauthorized { .. }) => {}
+            _ => panic!("Only the admin can set the config"),
+        }
+
+        let result = handle(deps, env.clone(), msg)?;
+        assert_eq!(result.log[1].key, "old_amm_settings");
+        assert_eq!(result.log[2].key, "new_amm_settings");
+        assert_eq!(config_read(deps)?.amm_settings, amm_settings);
+
+        // 10.1% in total is above the fee ceiling
+        amm_settings.lp_fee = Fee::new(81, 1000);
+        amm_settings.shade_dao_fee = Fee::new(2, 100);
+        assert!(handle(deps, env, HandleMsg::SetConfig {
+            pair_contract: None,
+            lp_token_contract: None,
+            amm_settings: Some(amm_settings),
+        }).is_err());
+        Ok(())
+    }
+
     #[test]
     fn register_amm_pair_ok() -> StdResult<()> {
         let ref mut deps = mkdeps();
@@ -144,7 +179,7 @@ use super::*;
             },
         );
 
-        let result = create_pair(deps, mkenv("sender"), pair, to_binary(&"entropy").unwrap(), None);
+        let result = create_pair(deps, mkenv("sender"), pair, to_binary(&"entropy").unwrap(), None, None, None);
         //let error: StdError = result.unwrap_err();
         print!("BOPOOOOOOOOOOOOOOOOOOOOOOOOOOOOOOOOOOOO");
         //print!("{}",error);
@@ -206,6 +241,273 @@ use super::*;
 
     }
 
+    #[test]
+    fn pause_stops_pair_creation_and_reaches_pairs() -> StdResult<()> {
+        let ref mut deps = mkdeps();
+        let env = mkenv("admin");
+        init(deps, env.clone(), (&mkconfig(0)).into())?;
+
+        let amm_pairs: Vec<AMMPair<HumanAddr>> = (0..2).map(|i| AMMPair {
+            pair: TokenPair::<HumanAddr>(
+                TokenType::CustomToken {
+                    contract_addr: format!("token_0_addr_{}", i).into(),
+                    token_code_hash: format!("token_0_hash_{}", i),
+                },
+                TokenType::NativeToken {
+                    denom: "uscrt".into(),
+                },
+            ),
+            address: format!("pair_addr_{}", i).into(),
+        }).collect();
+        handle(deps, env.clone(), HandleMsg::AddAMMPairs { amm_pairs: amm_pairs.clone() })?;
+
+        let pause = HandleMsg::SetPairsStatus { status: ContractStatus::SwapsPaused };
+        match handle(deps, mkenv("user"), pause.clone()) {
+            Err(StdError::Unauthorized { .. }) => {}
+            _ => panic!("Only the admin can pause the pairs"),
+        }
+        let result = handle(deps, env.clone(), pause)?;
+        assert_eq!(result.messages.len(), amm_pairs.len());
+
+        handle(deps, env.clone(), HandleMsg::SetFactoryStatus { status: ContractStatus::SwapsPaused })?;
+        let pair = TokenPair(
+            TokenType::CustomToken {
+                contract_addr: HumanAddr("token_addr".into()),
+                token_code_hash: "13123adasd".into(),
+            },
+            TokenType::NativeToken {
+                denom: "test1".into(),
+            },
+        );
+        assert!(create_pair(deps, env.clone(), pair.clone(), to_binary(&"entropy")?, None, None, None).is_err());
+        handle(deps, env.clone(), HandleMsg::SetFactoryStatus { status: ContractStatus::Normal })?;
+        assert!(create_pair(deps, env.clone(), pair, to_binary(&"entropy")?, None, None, None).is_ok());
+
+        // every pair is reached through its own code hash once some are upgraded
+        handle(deps, env.clone(), HandleMsg::UpgradePairs {
+            pairs: vec![amm_pairs[0].pair.clone()],
+            new_code: ContractInstantiationInfo { id: 7, code_hash: "new_hash".into() },
+        })?;
+        handle(deps, mkenv("new_pair"), HandleMsg::RegisterUpgradedAMMPair {
+            pair: amm_pairs[0].pair.clone(),
+            signature: create_signature(&env)?,
+        })?;
+        let result = handle(deps, env, HandleMsg::SetPairsStatus { status: ContractStatus::AllPaused })?;
+        let code_hashes: Vec<String> = result.messages.iter().map(|msg| match msg {
+            shadeswap_shared::fadroma::scrt::CosmosMsg::Wasm(
+                shadeswap_shared::fadroma::scrt::WasmMsg::Execute { callback_code_hash, .. }
+            ) => callback_code_hash.clone(),
+            _ => panic!("Pairs are sent executes"),
+        }).collect();
+        assert_eq!(code_hashes, vec!["new_hash".to_string(), mkconfig(0).pair_contract.code_hash]);
+        Ok(())
+    }
+
+    #[test]
+    fn upgrade_pairs_replaces_pair_address() -> StdResult<()> {
+        let ref mut deps = mkdeps();
+        let config = mkconfig(0);
+        let env = mkenv("admin");
+        init(deps, env.clone(), (&config).into())?;
+
+        let pair = TokenPair::<HumanAddr>(
+            TokenType::CustomToken {
+                contract_addr: "token_0_addr".into(),
+                token_code_hash: "token_0_hash".into(),
+            },
+            TokenType::NativeToken {
+                denom: "uscrt".into(),
+            },
+        );
+        handle(deps, env.clone(), HandleMsg::AddAMMPairs {
+            amm_pairs: vec![AMMPair { pair: pair.clone(), address: "pair_addr".into() }],
+        })?;
+
+        let new_code = ContractInstantiationInfo { id: 7, code_hash: "new_hash".into() };
+        let upgrade = HandleMsg::UpgradePairs { pairs: vec![pair.clone()], new_code: new_code.clone() };
+        match handle(deps, mkenv("user"), upgrade.clone()) {
+            Err(StdError::Unauthorized { .. }) => {}
+            _ => panic!("Only the admin can upgrade pairs"),
+        }
+        let result = handle(deps, env.clone(), upgrade)?;
+        assert_eq!(result.messages.len(), 1);
+        assert_eq!(config_read(deps)?.pair_contract, new_code);
+
+        let signature = create_signature(&env)?;
+        assert!(handle(deps, mkenv("new_pair"), HandleMsg::RegisterUpgradedAMMPair {
+            pair: pair.clone(),
+            signature: to_binary(&"wrong")?,
+        }).is_err());
+        handle(deps, mkenv("new_pair"), HandleMsg::RegisterUpgradedAMMPair {
+            pair: pair.clone(),
+            signature: signature.clone(),
+        })?;
+
+        let response: QueryResponse = from_binary(&query(deps, QueryMsg::GetAMMPairAddress { pair: pair.clone() })?)?;
+        assert_eq!(response, QueryResponse::GetAMMPairAddress { address: "new_pair".into() });
+        let response: QueryResponse = from_binary(&query(deps, QueryMsg::ListAMMPairs {
+            pagination: pagination(0, PAGINATION_LIMIT),
+        })?)?;
+        assert_eq!(response, QueryResponse::ListAMMPairs {
+            amm_pairs: vec![AMMPair { pair: pair.clone(), address: "new_pair".into() }],
+        });
+        // the signature is only good once
+        assert!(handle(deps, mkenv("other"), HandleMsg::RegisterUpgradedAMMPair { pair, signature }).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn partial_upgrade_keeps_code_hash_of_other_pairs() -> StdResult<()> {
+        use shadeswap_shared::fadroma::scrt::{CosmosMsg, WasmMsg};
+        fn code_hash_of(result: &HandleResponse) -> String {
+            match &result.messages[0] {
+                CosmosMsg::Wasm(WasmMsg::Execute { callback_code_hash, .. }) => callback_code_hash.clone(),
+                _ => panic!("Pairs are sent executes"),
+            }
+        }
+        let ref mut deps = mkdeps();
+        let config = mkconfig(0);
+        let env = mkenv("admin");
+        init(deps, env.clone(), (&config).into())?;
+
+        let pairs: Vec<TokenPair<HumanAddr>> = (0..2).map(|i| TokenPair::<HumanAddr>(
+            TokenType::CustomToken {
+                contract_addr: format!("token_0_addr_{}", i).into(),
+                token_code_hash: format!("token_0_hash_{}", i),
+            },
+            TokenType::NativeToken {
+                denom: "uscrt".into(),
+            },
+        )).collect();
+        handle(deps, env.clone(), HandleMsg::AddAMMPairs {
+            amm_pairs: pairs.iter().enumerate().map(|(i, pair)| AMMPair {
+                pair: pair.clone(),
+                address: format!("pair_addr_{}", i).into(),
+            }).collect(),
+        })?;
+
+        let new_code = ContractInstantiationInfo { id: 7, code_hash: "new_hash".into() };
+        handle(deps, env.clone(), HandleMsg::UpgradePairs { pairs: vec![pairs[0].clone()], new_code })?;
+        handle(deps, mkenv("new_pair"), HandleMsg::RegisterUpgradedAMMPair {
+            pair: pairs[0].clone(),
+            signature: create_signature(&env)?,
+        })?;
+
+        let set_fee = |pair: &TokenPair<HumanAddr>| HandleMsg::SetPairFee { pair: pair.clone(), fee: None };
+        let result = handle(deps, env.clone(), set_fee(&pairs[0]))?;
+        assert_eq!(code_hash_of(&result), "new_hash".to_string());
+        let result = handle(deps, env.clone(), set_fee(&pairs[1]))?;
+        assert_eq!(code_hash_of(&result), config.pair_contract.code_hash);
+        // the pair left out is still upgraded through its own code
+        let result = handle(deps, env.clone(), HandleMsg::UpgradePairs {
+            pairs: vec![pairs[1].clone()],
+            new_code: ContractInstantiationInfo { id: 8, code_hash: "newer_hash".into() },
+        })?;
+        assert_eq!(code_hash_of(&result), config.pair_contract.code_hash);
+        Ok(())
+    }
+
+    #[test]
+    fn set_pair_fee_sends_custom_fee_to_pair() -> StdResult<()> {
+        use shadeswap_shared::amm_pair::CustomFee;
+        use shadeswap_shared::fadroma::scrt::{CosmosMsg, WasmMsg};
+        use shadeswap_shared::msg::amm_pair::HandleMsg as AMMPairHandleMsg;
+        let ref mut deps = mkdeps();
+        let config = mkconfig(0);
+        let env = mkenv("admin");
+        init(deps, env.clone(), (&config).into())?;
+
+        let pair = TokenPair::<HumanAddr>(
+            TokenType::CustomToken {
+                contract_addr: "token_0_addr".into(),
+                token_code_hash: "token_0_hash".into(),
+            },
+            TokenType::NativeToken {
+                denom: "uscrt".into(),
+            },
+        );
+        handle(deps, env.clone(), HandleMsg::AddAMMPairs {
+            amm_pairs: vec![AMMPair { pair: pair.clone(), address: "pair_addr".into() }],
+        })?;
+
+        let fee = Some(CustomFee { lp_fee: Fee::new(1, 100), shade_dao_fee: Fee::new(0, 100) });
+        let set_fee = |fee: Option<CustomFee>| HandleMsg::SetPairFee { pair: pair.clone(), fee };
+        match handle(deps, mkenv("user"), set_fee(fee)) {
+            Err(StdError::Unauthorized { .. }) => {}
+            _ => panic!("Only the admin can set the fee of a pair"),
+        }
+
+        // `None` is passed on as is, the pair falls back to the factory fees
+        for fee in vec![fee, None] {
+            let result = handle(deps, env.clone(), set_fee(fee))?;
+            assert_eq!(result.messages.len(), 1);
+            match &result.messages[0] {
+                CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, callback_code_hash, msg, .. }) => {
+                    assert_eq!(contract_addr, &HumanAddr("pair_addr".into()));
+                    assert_eq!(callback_code_hash, &config.pair_contract.code_hash);
+                    assert_eq!(msg, &to_binary(&AMMPairHandleMsg::SetCustomFee { fee })?);
+                }
+                _ => panic!("The fee is sent to the pair"),
+            }
+        }
+
+        let invalid_fees = vec![
+            CustomFee { lp_fee: Fee::new(1, 0), shade_dao_fee: Fee::new(0, 100) },
+            CustomFee { lp_fee: Fee::new(100, 100), shade_dao_fee: Fee::new(0, 100) },
+            CustomFee { lp_fee: Fee::new(0, 100), shade_dao_fee: Fee::new(101, 100) },
+        ];
+        for fee in invalid_fees {
+            match handle(deps, env.clone(), set_fee(Some(fee))) {
+                Err(StdError::GenericErr { .. }) => {}
+                _ => panic!("Invalid fees are rejected"),
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn set_pair_oracle_is_sent_to_pair() -> StdResult<()> {
+        use shadeswap_shared::amm_pair::OracleSettings;
+        use shadeswap_shared::fadroma::scrt::{CosmosMsg, WasmMsg};
+        use shadeswap_shared::msg::amm_pair::HandleMsg as AMMPairHandleMsg;
+        let ref mut deps = mkdeps();
+        let env = mkenv("admin");
+        init(deps, env.clone(), (&mkconfig(0)).into())?;
+
+        let pair = TokenPair::<HumanAddr>(
+            TokenType::CustomToken {
+                contract_addr: "token_0_addr".into(),
+                token_code_hash: "token_0_hash".into(),
+            },
+            TokenType::NativeToken {
+                denom: "uscrt".into(),
+            },
+        );
+        handle(deps, env.clone(), HandleMsg::AddAMMPairs {
+            amm_pairs: vec![AMMPair { pair: pair.clone(), address: "pair_addr".into() }],
+        })?;
+
+        let oracle = OracleSettings {
+            contract: ContractLink { address: "oracle_addr".into(), code_hash: "oracle_hash".into() },
+            max_deviation: 100,
+            max_age: 60,
+        };
+        let set_oracle = HandleMsg::SetPairOracle { pair: pair.clone(), oracle: oracle.clone() };
+        match handle(deps, mkenv("user"), set_oracle.clone()) {
+            Err(StdError::Unauthorized { .. }) => {}
+            _ => panic!("Only the admin can replace the oracle of a pair"),
+        }
+        let result = handle(deps, env, set_oracle)?;
+        match &result.messages[..] {
+            [CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, msg, .. })] => {
+                assert_eq!(contract_addr, &HumanAddr("pair_addr".into()));
+                assert_eq!(msg, &to_binary(&AMMPairHandleMsg::SetOracle { oracle })?);
+            }
+            _ => panic!("The oracle is sent to the pair"),
+        }
+        Ok(())
+    }
+
     /*
     #[test]
     fn increment() {
@@ -381,6 +683,7 @@ fn mkconfig(id: u64) -> Config<HumanAddr> {
                 address: HumanAddr(String::from("CALLBACKADDR")),
                 code_hash: "Test".to_string()
             },
+            shade_dao_fee_mode: ShadeDaoFeeMode::Transfer,
         },
         lp_token_contract: ContractInstantiationInfo { 
             id,
@@ -412,7 +715,8 @@ impl Into<InitMsg> for &Config<HumanAddr> {
                 shade_dao_address: ContractLink {
                     address: HumanAddr(String::from("CALLBACKADDR")),
                     code_hash: "Test".to_string()
-                }
+                },
+                shade_dao_fee_mode: ShadeDaoFeeMode::Transfer,
             },
             lp_token_contract: self.lp_token_contract.clone(),
             prng_seed: to_binary(&"prng").unwrap()
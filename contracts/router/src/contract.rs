@@ -1,9 +1,9 @@
 use shadeswap_shared::{
-    amm_pair::AMMSettings,
+    amm_pair::{AMMPair, AMMSettings},
     fadroma::{
         debug_print, from_binary,
         scrt::{
-            secret_toolkit::snip20, to_binary, Api, Binary, CosmosMsg, Env, Extern,
+            log, secret_toolkit::snip20, to_binary, Api, Binary, CosmosMsg, Env, Extern,
             HandleResponse, HumanAddr, InitResponse, Querier, StdError, StdResult, Storage,
             WasmMsg,
         },
@@ -16,7 +16,7 @@ use shadeswap_shared::{
             HandleMsg as AMMPairHandleMsg, InvokeMsg as AMMPairInvokeMsg,
             QueryMsg as AMMPairQueryMsg, QueryMsgResponse as AMMPairQueryReponse,
         },
-        router::{HandleMsg, InvokeMsg, QueryMsg},
+        router::{HandleMsg, InvokeMsg, QueryMsg, QueryMsgResponse, SwapHop},
     },
     msg::{
         factory::{QueryMsg as FactoryQueryMsg, QueryResponse as FactoryQueryResponse},
@@ -26,7 +26,9 @@ use shadeswap_shared::{
 use shadeswap_shared::token_pair::TokenPair;
 use shadeswap_shared::token_amount::TokenAmount;
 use shadeswap_shared::token_type::TokenType;
-use shadeswap_shared::admin::{{store_admin, apply_admin_guard}};
+use shadeswap_shared::admin::{{store_admin, apply_admin_guard, apply_swap_guard, set_status_guard}};
+use shadeswap_shared::Pagination;
+use shadeswap_shared::deadline::{assert_deadline, Deadline};
 
 use crate::state::{config_read, config_write, Config, CurrentSwapInfo};
 
@@ -35,6 +37,12 @@ use crate::state::{config_read, config_write, Config, CurrentSwapInfo};
 const BLOCK_SIZE: usize = 256;
 
 pub const EPHEMERAL_STORAGE_KEY: &[u8] = b"ephemeral_storage";
+/// Longest route considered when looking for the best route
+pub const MAX_ROUTE_HOPS: u8 = 3;
+/// Most routes simulated when looking for the best route, shorter routes are tried first
+pub const MAX_CANDIDATE_ROUTES: usize = 10;
+/// Page size used when listing pairs from the factory
+const PAIR_PAGE_LIMIT: u8 = 30;
 
 pub fn init<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
@@ -53,6 +61,14 @@ pub fn handle<S: Storage, A: Api, Q: Querier>(
     env: Env,
     msg: HandleMsg,
 ) -> StdResult<HandleResponse> {
+    match &msg {
+        // the pairs send the output of a hop without a message
+        HandleMsg::Receive { msg: Some(_), .. }
+        | HandleMsg::SwapTokensForExact { .. }
+        | HandleMsg::SwapTokensForBestRoute { .. } => apply_swap_guard(&deps.storage)?,
+        _ => {}
+    }
+
     match msg {
         HandleMsg::Receive {
             from, amount, msg, ..
@@ -62,6 +78,7 @@ pub fn handle<S: Storage, A: Api, Q: Querier>(
             expected_return,
             path,
             recipient,
+            deadline,
         } => {
             if !offer.token.is_native_token() {
                 return Err(StdError::unauthorized());
@@ -76,6 +93,32 @@ pub fn handle<S: Storage, A: Api, Q: Querier>(
                 &path,
                 sender,
                 recipient,
+                deadline,
+            )
+        }
+        HandleMsg::SwapTokensForBestRoute {
+            offer,
+            target_token,
+            max_hops,
+            expected_return,
+            recipient,
+            deadline,
+        } => {
+            if !offer.token.is_native_token() {
+                return Err(StdError::unauthorized());
+            }
+            offer.assert_sent_native_token_balance(&env)?;
+            let sender = env.message.sender.clone();
+            swap_tokens_for_best_route(
+                deps,
+                env,
+                offer,
+                target_token,
+                max_hops,
+                expected_return,
+                sender,
+                recipient,
+                deadline,
             )
         }
         HandleMsg::SwapCallBack {
@@ -85,6 +128,7 @@ pub fn handle<S: Storage, A: Api, Q: Querier>(
         HandleMsg::RegisterSNIP20Token { token, token_code_hash } => {
             refresh_tokens(deps, env, token, token_code_hash)
         }
+        HandleMsg::SetRouterStatus { status } => set_status_guard(deps, env, status),
     }
 }
 
@@ -121,6 +165,7 @@ fn receiver_callback<S: Storage, A: Api, Q: Querier>(
                     expected_return,
                     paths,
                     recipient,
+                    deadline,
                 } => {
                     let config = config_read(deps)?;
                     let factory_config = query_factory_config(&deps.querier, config.factory_address.clone())?;
@@ -142,6 +187,7 @@ fn receiver_callback<S: Storage, A: Api, Q: Querier>(
                                         &paths,
                                         from,
                                         recipient,
+                                        deadline,
                                     );
                                 }
                             }
@@ -150,6 +196,44 @@ fn receiver_callback<S: Storage, A: Api, Q: Querier>(
                     }
                     Err(StdError::unauthorized())
                 }
+                InvokeMsg::SwapTokensForBestRoute {
+                    target_token,
+                    max_hops,
+                    expected_return,
+                    recipient,
+                    deadline,
+                } => {
+                    let config = config_read(deps)?;
+                    let amm_pairs = query_factory_amm_pairs(&deps.querier, config.factory_address)?;
+                    for amm_pair in amm_pairs.iter() {
+                        for token in amm_pair.pair.into_iter() {
+                            match token {
+                                TokenType::CustomToken { contract_addr, .. } => {
+                                    if *contract_addr == env.message.sender {
+                                        let offer = TokenAmount {
+                                            token: token.clone(),
+                                            amount,
+                                        };
+
+                                        return swap_tokens_for_best_route(
+                                            deps,
+                                            env,
+                                            offer,
+                                            target_token,
+                                            max_hops,
+                                            expected_return,
+                                            from,
+                                            recipient,
+                                            deadline,
+                                        );
+                                    }
+                                }
+                                _ => continue,
+                            }
+                        }
+                    }
+                    Err(StdError::unauthorized())
+                }
             }
         },
         None => Ok(HandleResponse {
@@ -165,9 +249,78 @@ pub fn query<S: Storage, A: Api, Q: Querier>(
     msg: QueryMsg,
 ) -> StdResult<Binary> {
     match msg {
+        QueryMsg::SimulateSwap { offer, path } => simulate_swap(deps, offer, path),
     }
 }
 
+/// Walks the given path the same way a swap would and returns what every pair
+/// would give back, without executing anything.
+pub fn simulate_swap<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    offer: TokenAmount<HumanAddr>,
+    path: Vec<HumanAddr>,
+) -> StdResult<Binary> {
+    if path.is_empty() {
+        return Err(StdError::generic_err("Swap path cannot be empty."));
+    }
+
+    let config = config_read(deps)?;
+    let factory_config = query_factory_config(&deps.querier, config.factory_address.clone())?;
+    let (hops, return_amount) = simulate_path(
+        &deps.querier,
+        &factory_config.pair_contract.code_hash,
+        offer,
+        path,
+    )?;
+
+    to_binary(&QueryMsgResponse::SimulateSwap {
+        hops,
+        return_amount,
+    })
+}
+
+fn simulate_path(
+    querier: &impl Querier,
+    pair_code_hash: &String,
+    offer: TokenAmount<HumanAddr>,
+    path: Vec<HumanAddr>,
+) -> StdResult<(Vec<SwapHop>, Uint128)> {
+    let mut hops = Vec::with_capacity(path.len());
+    let mut token_in = offer;
+
+    for pair_address in path {
+        let pair_link = ContractLink {
+            address: pair_address.clone(),
+            code_hash: pair_code_hash.clone(),
+        };
+        let pair_config = query_pair_contract_config(querier, pair_link.clone())?;
+        let index = match pair_config.pair.get_token_index(&token_in.token) {
+            Some(index) => index,
+            None => {
+                return Err(StdError::generic_err(format!(
+                    "Pair {} doesn't contain token {}.",
+                    pair_address, token_in.token
+                )))
+            }
+        };
+        let swap_info = query_pair_swap_simulation(querier, pair_link, token_in.clone())?;
+
+        hops.push(SwapHop {
+            pair: pair_address,
+            offer: token_in,
+            return_amount: swap_info.return_amount,
+            lp_fee_amount: swap_info.lp_fee_amount,
+            shade_dao_fee_amount: swap_info.shade_dao_fee_amount,
+        });
+        token_in = TokenAmount {
+            token: pair_config.pair.get_token(index ^ 1).unwrap().clone(),
+            amount: swap_info.return_amount,
+        };
+    }
+
+    Ok((hops, token_in.amount))
+}
+
 pub fn next_swap<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
@@ -183,6 +336,7 @@ pub fn next_swap<S: Storage, A: Api, Q: Querier>(
             if signature != info.signature {
                 return Err(StdError::unauthorized());
             }
+            assert_deadline(&env, &info.deadline)?;
             let pair_contract = query_pair_contract_config(
                 &deps.querier,
                 ContractLink {
@@ -211,9 +365,11 @@ pub fn next_swap<S: Storage, A: Api, Q: Querier>(
                         amount: info.amount.clone(),
                         paths: info.paths.clone(),
                         signature: info.signature.clone(),
+                        trader: info.trader.clone(),
                         recipient: info.recipient,
                         current_index: info.current_index + 1,
-                        amount_out_min: info.amount_out_min
+                        amount_out_min: info.amount_out_min,
+                        deadline: info.deadline
                     }
                 )?;
                 Ok(HandleResponse {
@@ -224,6 +380,7 @@ pub fn next_swap<S: Storage, A: Api, Q: Querier>(
                         info.paths[(info.current_index + 1) as usize].clone(),
                         factory_config.pair_contract.code_hash.clone(),
                         info.signature,
+                        info.trader,
                     )?,
                     log: vec![],
                     data: None,
@@ -267,7 +424,9 @@ pub fn swap_tokens_for_exact_tokens<S: Storage, A: Api, Q: Querier>(
     paths: &Vec<HumanAddr>,
     sender: HumanAddr,
     recipient: Option<HumanAddr>,
+    deadline: Option<Deadline>,
 ) -> HandleResult {
+    assert_deadline(&env, &deadline)?;
     let querier = &deps.querier;
     //Validates whether the amount received is greater then the amount_out_min
     let config = config_read(deps)?;
@@ -282,8 +441,10 @@ pub fn swap_tokens_for_exact_tokens<S: Storage, A: Api, Q: Querier>(
             amount_out_min: amount_out_min,
             paths: paths.clone(),
             signature: signature.clone(),
-            recipient: recipient.unwrap_or(sender),
+            trader: sender.clone(),
+            recipient: recipient.unwrap_or(sender.clone()),
             current_index: 0,
+            deadline,
         },
     )?;
 
@@ -295,12 +456,129 @@ pub fn swap_tokens_for_exact_tokens<S: Storage, A: Api, Q: Querier>(
             paths[0].clone(),
             factory_config.pair_contract.code_hash,
             signature.clone(),
+            sender,
         )?,
         log: vec![],
         data: None,
     })
 }
 
+pub fn swap_tokens_for_best_route<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    amount_in: TokenAmount<HumanAddr>,
+    target_token: TokenType<HumanAddr>,
+    max_hops: Option<u8>,
+    amount_out_min: Option<Uint128>,
+    sender: HumanAddr,
+    recipient: Option<HumanAddr>,
+    deadline: Option<Deadline>,
+) -> HandleResult {
+    assert_deadline(&env, &deadline)?;
+    if amount_in.token == target_token {
+        return Err(StdError::generic_err("The target token has to differ from the offered token."));
+    }
+    let max_hops = max_hops.unwrap_or(MAX_ROUTE_HOPS).min(MAX_ROUTE_HOPS);
+    let config = config_read(deps)?;
+    let factory_config = query_factory_config(&deps.querier, config.factory_address.clone())?;
+    let amm_pairs = query_factory_amm_pairs(&deps.querier, config.factory_address)?;
+
+    let mut best_route: Option<(Vec<HumanAddr>, Uint128)> = None;
+    for path in find_routes(&amm_pairs, &amount_in.token, &target_token, max_hops) {
+        // pairs which can't fill the trade are simply not considered
+        if let Ok((_, return_amount)) = simulate_path(
+            &deps.querier,
+            &factory_config.pair_contract.code_hash,
+            amount_in.clone(),
+            path.clone(),
+        ) {
+            let is_better = match &best_route {
+                Some((_, best_amount)) => return_amount > *best_amount,
+                None => true,
+            };
+            if is_better {
+                best_route = Some((path, return_amount));
+            }
+        }
+    }
+
+    let (path, return_amount) = best_route.ok_or_else(|| {
+        StdError::generic_err(format!(
+            "No route found from {} to {}.",
+            amount_in.token, target_token
+        ))
+    })?;
+
+    let mut response = swap_tokens_for_exact_tokens(
+        deps,
+        env,
+        amount_in,
+        amount_out_min,
+        &path,
+        sender,
+        recipient,
+        deadline,
+    )?;
+    response.log.push(log("route", format!("{:?}", path)));
+    response.log.push(log("simulated_return_amount", return_amount));
+    Ok(response)
+}
+
+/// Routes of at most `max_hops` pairs that lead from `token_in` to `target_token`, the
+/// shortest first and no more than `MAX_CANDIDATE_ROUTES` of them. A pair is never used
+/// twice in the same route.
+pub fn find_routes(
+    amm_pairs: &[AMMPair<HumanAddr>],
+    token_in: &TokenType<HumanAddr>,
+    target_token: &TokenType<HumanAddr>,
+    max_hops: u8,
+) -> Vec<Vec<HumanAddr>> {
+    let mut routes = vec![];
+    for hops in 1..=max_hops {
+        if routes.len() >= MAX_CANDIDATE_ROUTES {
+            break;
+        }
+        collect_routes(amm_pairs, token_in, target_token, hops, &mut vec![], &mut routes);
+    }
+    routes
+}
+
+fn collect_routes(
+    amm_pairs: &[AMMPair<HumanAddr>],
+    token_in: &TokenType<HumanAddr>,
+    target_token: &TokenType<HumanAddr>,
+    hops_left: u8,
+    current: &mut Vec<HumanAddr>,
+    routes: &mut Vec<Vec<HumanAddr>>,
+) {
+    for amm_pair in amm_pairs {
+        if routes.len() >= MAX_CANDIDATE_ROUTES {
+            return;
+        }
+        if current.contains(&amm_pair.address) {
+            continue;
+        }
+        let index = match amm_pair.pair.get_token_index(token_in) {
+            Some(index) => index,
+            None => continue,
+        };
+        let token_out = amm_pair.pair.get_token(index ^ 1).unwrap();
+
+        // routes reaching the target early were collected with fewer hops
+        if (token_out == target_token) != (hops_left == 1) {
+            continue;
+        }
+
+        current.push(amm_pair.address.clone());
+        if hops_left == 1 {
+            routes.push(current.clone());
+        } else {
+            collect_routes(amm_pairs, token_out, target_token, hops_left - 1, current, routes);
+        }
+        current.pop();
+    }
+}
+
 fn get_trade_with_callback<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
@@ -308,6 +586,7 @@ fn get_trade_with_callback<S: Storage, A: Api, Q: Querier>(
     path: HumanAddr,
     code_hash: String,
     signature: Binary,
+    trader: HumanAddr,
 ) -> StdResult<Vec<CosmosMsg>> {
     let mut messages: Vec<CosmosMsg> = vec![];
 
@@ -321,7 +600,9 @@ fn get_trade_with_callback<S: Storage, A: Api, Q: Querier>(
                     code_hash: env.contract_code_hash.clone(),
                 }),
                 offer: token_in.clone(),
-                callback_signature: Some(signature)
+                callback_signature: Some(signature),
+                deadline: None,
+                trader: Some(trader),
             })?;
 
             messages.push(
@@ -353,6 +634,8 @@ fn get_trade_with_callback<S: Storage, A: Api, Q: Querier>(
                             code_hash: env.contract_code_hash.clone(),
                         }),
                         callback_signature: Some(signature),
+                        deadline: None,
+                        trader: Some(trader),
                     })
                     .unwrap(),
                 ),
@@ -398,6 +681,43 @@ fn query_factory_config(
     }
 }
 
+fn query_factory_amm_pairs(
+    querier: &impl Querier,
+    factory_address: ContractLink<HumanAddr>,
+) -> StdResult<Vec<AMMPair<HumanAddr>>> {
+    let mut amm_pairs = vec![];
+    let mut start = 0u64;
+
+    loop {
+        let result: FactoryQueryResponse = querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+            contract_addr: factory_address.address.clone(),
+            callback_code_hash: factory_address.code_hash.clone(),
+            msg: to_binary(&FactoryQueryMsg::ListAMMPairs {
+                pagination: Pagination {
+                    start,
+                    limit: PAIR_PAGE_LIMIT,
+                },
+            })?,
+        }))?;
+
+        match result {
+            FactoryQueryResponse::ListAMMPairs { amm_pairs: page } => {
+                let count = page.len();
+                amm_pairs.extend(page);
+                if count < PAIR_PAGE_LIMIT as usize {
+                    return Ok(amm_pairs);
+                }
+                start += count as u64;
+            }
+            _ => {
+                return Err(StdError::generic_err(
+                    "An error occurred while trying to retrieve factory pairs.",
+                ))
+            }
+        }
+    }
+}
+
 fn query_pair_contract_config(
     querier: &impl Querier,
     pair_contract_address: ContractLink<HumanAddr>,
@@ -417,6 +737,7 @@ fn query_pair_contract_config(
             amount_1,
             total_liquidity,
             contract_version,
+            ..
         } => Ok(PairConfig {
             liquidity_token: liquidity_token,
             factory: factory,
@@ -432,6 +753,34 @@ fn query_pair_contract_config(
     }
 }
 
+fn query_pair_swap_simulation(
+    querier: &impl Querier,
+    pair_contract_address: ContractLink<HumanAddr>,
+    offer: TokenAmount<HumanAddr>,
+) -> StdResult<PairSwapSimulation> {
+    let result: AMMPairQueryReponse = querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: pair_contract_address.address.clone(),
+        callback_code_hash: pair_contract_address.code_hash.clone(),
+        msg: to_binary(&AMMPairQueryMsg::SwapSimulation { offer })?,
+    }))?;
+
+    match result {
+        AMMPairQueryReponse::SwapSimulation {
+            lp_fee_amount,
+            shade_dao_fee_amount,
+            result,
+            ..
+        } => Ok(PairSwapSimulation {
+            return_amount: result.return_amount,
+            lp_fee_amount,
+            shade_dao_fee_amount,
+        }),
+        _ => Err(StdError::generic_err(
+            "An error occurred while trying to simulate the swap.",
+        )),
+    }
+}
+
 struct FactoryConfig {
     pair_contract: ContractInstantiationInfo,
     amm_settings: AMMSettings<HumanAddr>,
@@ -447,6 +796,12 @@ struct PairConfig {
     contract_version: u32,
 }
 
+struct PairSwapSimulation {
+    return_amount: Uint128,
+    lp_fee_amount: Uint128,
+    shade_dao_fee_amount: Uint128,
+}
+
 pub(crate) fn create_signature(env: &Env) -> StdResult<Binary> {
     to_binary(
         &[
@@ -12,7 +12,7 @@ use shadeswap_shared::{
         scrt_storage::{load, save},
         scrt_vk::ViewingKey, Env, PrefixedStorage, ReadonlyPrefixedStorage, ReadonlyStorage, Binary,
     },
-    amm_pair::AMMPair, TokenPair, TokenAmount, TokenType,
+    amm_pair::AMMPair, TokenPair, TokenAmount, TokenType, Deadline,
     msg::router::InitMsg
 };
 
@@ -85,6 +85,9 @@ pub struct CurrentSwapInfo {
     pub amount_out_min: Option<Uint128>,
     pub paths: Vec<HumanAddr>,
     pub signature: Binary,
+    /// Address which started the swap, the pairs give it their fee discount.
+    pub trader: HumanAddr,
     pub recipient: HumanAddr,
-    pub current_index: u32
+    pub current_index: u32,
+    pub deadline: Option<Deadline>
 }
\ No newline at end of file
@@ -7,15 +7,18 @@ pub mod tests {
     use crate::state::CurrentSwapInfo;
 
     use crate::contract::handle;
+    use crate::contract::query;
+    use crate::contract::find_routes;
+    use crate::contract::MAX_CANDIDATE_ROUTES;
     use serde::{de::DeserializeOwned, Deserialize, Serialize};
     use shadeswap_shared::{
-        amm_pair::Fee,
+        amm_pair::{AMMPair, Fee, PoolType},
         fadroma::{
             from_slice,
             scrt::{
                 from_binary,
                 testing::{mock_dependencies, mock_env, MockApi, MockStorage},
-                to_binary, Api, Env, Extern, HumanAddr, Querier, StdError,
+                to_binary, Api, Binary, Env, Extern, HumanAddr, Querier, StdError,
                 StdResult, Storage, Uint128,
             },
             scrt_link::{ContractInstantiationInfo, ContractLink},
@@ -25,17 +28,23 @@ pub mod tests {
             WasmQuery,
         },
         msg::{
-            amm_pair::QueryMsgResponse as AMMPairQueryMsgResponse,
-            factory::QueryResponse as FactoryQueryResponse,
-            router::{HandleMsg, InitMsg, InvokeMsg},
+            amm_pair::{
+                QueryMsg as AMMPairQueryMsg, QueryMsgResponse as AMMPairQueryMsgResponse,
+                SwapResult,
+            },
+            factory::{QueryMsg as FactoryQueryMsg, QueryResponse as FactoryQueryResponse},
+            router::{HandleMsg, InitMsg, InvokeMsg, QueryMsg, QueryMsgResponse},
         },
-        TokenAmount, TokenPair, TokenType,
+        admin::ContractStatus,
+        Deadline, TokenAmount, TokenPair, TokenType,
     };
 
     pub const FACTORY_ADDRESS: &str = "FACTORY_ADDRESS";
     pub const PAIR_CONTRACT_1: &str = "PAIR_CONTRACT_1";
     pub const PAIR_CONTRACT_2: &str = "PAIR_CONTRACT_2";
+    pub const PAIR_CONTRACT_3: &str = "PAIR_CONTRACT_3";
     pub const CUSTOM_TOKEN_1: &str = "CUSTOM_TOKEN_1";
+    pub const CUSTOM_TOKEN_2: &str = "CUSTOM_TOKEN_2";
 
     #[test]
     fn ok_init() -> StdResult<()> {
@@ -47,6 +56,41 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    fn paused_router_rejects_swaps() -> StdResult<()> {
+        let (init_result, mut deps) = init_helper(100);
+        assert!(init_result.is_ok());
+        let mut env = mkenv("admin");
+        env.message.sent_funds = vec![Coin {
+            denom: "uscrt".into(),
+            amount: Uint128(10),
+        }];
+        let swap = HandleMsg::SwapTokensForExact {
+            offer: TokenAmount {
+                token: TokenType::NativeToken {
+                    denom: "uscrt".into(),
+                },
+                amount: Uint128(10),
+            },
+            expected_return: None,
+            path: vec![HumanAddr("token_addr".into())],
+            recipient: None,
+            deadline: None,
+        };
+
+        let pause = HandleMsg::SetRouterStatus { status: ContractStatus::SwapsPaused };
+        match handle(&mut deps, mkenv("user"), pause.clone()) {
+            Err(StdError::Unauthorized { .. }) => {}
+            _ => panic!("Only the admin can pause the router"),
+        }
+        handle(&mut deps, env.clone(), pause)?;
+        assert!(handle(&mut deps, env.clone(), swap.clone()).is_err());
+
+        handle(&mut deps, env.clone(), HandleMsg::SetRouterStatus { status: ContractStatus::Normal })?;
+        assert!(handle(&mut deps, env, swap).is_ok());
+        Ok(())
+    }
+
     #[test]
     fn swap_native_for_snip20_tokens_ok() -> StdResult<()> {
         let (init_result, mut deps) = init_helper(100);
@@ -76,6 +120,7 @@ pub mod tests {
                 expected_return: None,
                 path: vec![HumanAddr("token_addr".into())],
                 recipient: None,
+                deadline: None,
             },
         )
         .unwrap();
@@ -185,8 +230,10 @@ pub mod tests {
                     HumanAddr(PAIR_CONTRACT_2.into()),
                 ],
                 signature: to_binary("this is signature").unwrap(),
+                trader: HumanAddr("recipient".into()),
                 recipient: HumanAddr("recipient".into()),
                 current_index: 0,
+                deadline: None,
             },
         )?;
 
@@ -200,6 +247,7 @@ pub mod tests {
                         expected_return: Some(Uint128(1000)),
                         paths: vec![PAIR_CONTRACT_1.into()],
                         recipient: None,
+                        deadline: None,
                     })
                     .unwrap(),
                 ),
@@ -247,8 +295,10 @@ pub mod tests {
                     HumanAddr(PAIR_CONTRACT_2.into()),
                 ],
                 signature: to_binary("this is signature").unwrap(),
+                trader: HumanAddr("recipient".into()),
                 recipient: HumanAddr("recipient".into()),
                 current_index: 0,
+                deadline: None,
             },
         )?;
 
@@ -301,8 +351,10 @@ pub mod tests {
                     HumanAddr(PAIR_CONTRACT_2.into()),
                 ],
                 signature: to_binary("this is signature").unwrap(),
+                trader: HumanAddr("recipient".into()),
                 recipient: HumanAddr("recipient".into()),
                 current_index: 0,
+                deadline: None,
             },
         )?;
 
@@ -350,8 +402,10 @@ pub mod tests {
                 },
                 paths: vec![HumanAddr(PAIR_CONTRACT_1.into())],
                 signature: to_binary("this is signature").unwrap(),
+                trader: HumanAddr("recipient".into()),
                 recipient: HumanAddr("recipient".into()),
                 current_index: 0,
+                deadline: None,
             },
         )?;
 
@@ -423,8 +477,10 @@ pub mod tests {
                 },
                 paths: vec![HumanAddr(PAIR_CONTRACT_1.into())],
                 signature: to_binary("this is signature").unwrap(),
+                trader: HumanAddr("recipient".into()),
                 recipient: HumanAddr("recipient".into()),
                 current_index: 0,
+                deadline: None,
             },
         )?;
 
@@ -449,6 +505,322 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    fn swap_callback_after_deadline_fails() -> StdResult<()> {
+        let (init_result, mut deps) = init_helper(100);
+        let env = mkenv("admin");
+
+        assert!(
+            init_result.is_ok(),
+            "Init failed: {}",
+            init_result.err().unwrap()
+        );
+
+        save(
+            &mut deps.storage,
+            EPHEMERAL_STORAGE_KEY,
+            &CurrentSwapInfo {
+                amount_out_min: None,
+                amount: TokenAmount {
+                    token: TokenType::NativeToken {
+                        denom: "uscrt".into(),
+                    },
+                    amount: Uint128(10),
+                },
+                paths: vec![HumanAddr(PAIR_CONTRACT_1.into())],
+                signature: to_binary("this is signature").unwrap(),
+                trader: HumanAddr("recipient".into()),
+                recipient: HumanAddr("recipient".into()),
+                current_index: 0,
+                deadline: Some(Deadline::Height(env.block.height - 1)),
+            },
+        )?;
+
+        let result = handle(
+            &mut deps,
+            env,
+            HandleMsg::SwapCallBack {
+                last_token_out: TokenAmount {
+                    token: TokenType::NativeToken {
+                        denom: "uscrt".into(),
+                    },
+                    amount: Uint128(10),
+                },
+                signature: to_binary("this is signature").unwrap(),
+            },
+        );
+
+        match result {
+            Err(StdError::GenericErr { .. }) => {}
+            _ => panic!("Must return error"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn simulate_swap_multi_hop_ok() -> StdResult<()> {
+        let (init_result, deps) = init_helper(100);
+
+        assert!(
+            init_result.is_ok(),
+            "Init failed: {}",
+            init_result.err().unwrap()
+        );
+
+        let result = query(
+            &deps,
+            QueryMsg::SimulateSwap {
+                offer: TokenAmount {
+                    token: TokenType::CustomToken {
+                        contract_addr: CUSTOM_TOKEN_1.into(),
+                        token_code_hash: "hash".into(),
+                    },
+                    amount: Uint128(100),
+                },
+                path: vec![
+                    HumanAddr(PAIR_CONTRACT_1.into()),
+                    HumanAddr(PAIR_CONTRACT_2.into()),
+                ],
+            },
+        )?;
+
+        match from_binary(&result)? {
+            QueryMsgResponse::SimulateSwap {
+                hops,
+                return_amount,
+            } => {
+                assert_eq!(hops.len(), 2);
+                assert_eq!(hops[0].return_amount, Uint128(90));
+                assert_eq!(hops[0].lp_fee_amount, Uint128(8));
+                assert_eq!(hops[0].shade_dao_fee_amount, Uint128(2));
+                assert_eq!(
+                    hops[1].offer,
+                    TokenAmount {
+                        token: TokenType::NativeToken {
+                            denom: "denom".into(),
+                        },
+                        amount: Uint128(90),
+                    }
+                );
+                assert_eq!(return_amount, Uint128(80));
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn simulate_swap_token_not_in_pair() -> StdResult<()> {
+        let (init_result, deps) = init_helper(100);
+
+        assert!(
+            init_result.is_ok(),
+            "Init failed: {}",
+            init_result.err().unwrap()
+        );
+
+        let result = query(
+            &deps,
+            QueryMsg::SimulateSwap {
+                offer: TokenAmount {
+                    token: TokenType::CustomToken {
+                        contract_addr: CUSTOM_TOKEN_2.into(),
+                        token_code_hash: "hash".into(),
+                    },
+                    amount: Uint128(100),
+                },
+                path: vec![HumanAddr(PAIR_CONTRACT_1.into())],
+            },
+        );
+
+        match result {
+            Err(StdError::GenericErr { .. }) => {}
+            _ => panic!("Must return error"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn find_routes_ok() -> StdResult<()> {
+        let routes = find_routes(
+            &mock_amm_pairs(),
+            &TokenType::CustomToken {
+                contract_addr: CUSTOM_TOKEN_1.into(),
+                token_code_hash: "hash".into(),
+            },
+            &TokenType::CustomToken {
+                contract_addr: CUSTOM_TOKEN_2.into(),
+                token_code_hash: "hash".into(),
+            },
+            3,
+        );
+        assert_eq!(routes.len(), 2);
+        // the direct route comes first
+        assert_eq!(routes[0], vec![HumanAddr(PAIR_CONTRACT_3.into())]);
+        assert_eq!(routes[1], vec![
+            HumanAddr(PAIR_CONTRACT_1.into()),
+            HumanAddr(PAIR_CONTRACT_2.into())
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn find_routes_caps_candidates() -> StdResult<()> {
+        let custom_token_1 = TokenType::CustomToken {
+            contract_addr: CUSTOM_TOKEN_1.into(),
+            token_code_hash: "hash".into(),
+        };
+        let custom_token_2 = TokenType::CustomToken {
+            contract_addr: CUSTOM_TOKEN_2.into(),
+            token_code_hash: "hash".into(),
+        };
+        let mut amm_pairs = mock_amm_pairs();
+        for i in 0..MAX_CANDIDATE_ROUTES {
+            amm_pairs.push(AMMPair {
+                pair: TokenPair(custom_token_1.clone(), custom_token_2.clone()),
+                address: HumanAddr(format!("DIRECT_PAIR_{}", i)),
+            });
+        }
+
+        let routes = find_routes(&amm_pairs, &custom_token_1, &custom_token_2, 3);
+        assert_eq!(routes.len(), MAX_CANDIDATE_ROUTES);
+        assert!(routes.iter().all(|route| route.len() == 1));
+        Ok(())
+    }
+
+    #[test]
+    fn swap_best_route_to_offered_token_fails() -> StdResult<()> {
+        let (init_result, mut deps) = init_helper(100);
+
+        assert!(
+            init_result.is_ok(),
+            "Init failed: {}",
+            init_result.err().unwrap()
+        );
+
+        let result = handle(
+            &mut deps,
+            mkenv(CUSTOM_TOKEN_1),
+            HandleMsg::Receive {
+                from: HumanAddr("recipient".into()),
+                msg: Some(
+                    to_binary(&InvokeMsg::SwapTokensForBestRoute {
+                        target_token: TokenType::CustomToken {
+                            contract_addr: CUSTOM_TOKEN_1.into(),
+                            token_code_hash: "hash".into(),
+                        },
+                        max_hops: None,
+                        expected_return: None,
+                        recipient: None,
+                        deadline: None,
+                    })
+                    .unwrap(),
+                ),
+                amount: Uint128(100),
+            },
+        );
+
+        match result {
+            Err(StdError::GenericErr { msg, .. }) => assert_eq!(
+                msg,
+                "The target token has to differ from the offered token.".to_string()
+            ),
+            _ => panic!("Must return error"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn swap_best_route_picks_highest_return() -> StdResult<()> {
+        let (init_result, mut deps) = init_helper(100);
+
+        assert!(
+            init_result.is_ok(),
+            "Init failed: {}",
+            init_result.err().unwrap()
+        );
+
+        let result = handle(
+            &mut deps,
+            mkenv(CUSTOM_TOKEN_1),
+            HandleMsg::Receive {
+                from: HumanAddr("recipient".into()),
+                msg: Some(
+                    to_binary(&InvokeMsg::SwapTokensForBestRoute {
+                        target_token: TokenType::CustomToken {
+                            contract_addr: CUSTOM_TOKEN_2.into(),
+                            token_code_hash: "hash".into(),
+                        },
+                        max_hops: None,
+                        expected_return: None,
+                        recipient: None,
+                        deadline: None,
+                    })
+                    .unwrap(),
+                ),
+                amount: Uint128(100),
+            },
+        )?;
+
+        assert_eq!(result.messages.len(), 1);
+        let info: Option<CurrentSwapInfo> = load(&deps.storage, EPHEMERAL_STORAGE_KEY)?;
+        match info {
+            Some(info) => {
+                assert_eq!(info.paths, vec![HumanAddr(PAIR_CONTRACT_3.into())]);
+                assert_eq!(info.recipient, HumanAddr("recipient".into()));
+            }
+            None => panic!("Ephemeral storage should not be empty!"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn swap_best_route_no_route() -> StdResult<()> {
+        let (init_result, mut deps) = init_helper(100);
+        let mut env = mkenv("admin");
+        env.message.sent_funds = vec![Coin {
+            denom: "other".into(),
+            amount: Uint128(10),
+        }];
+
+        assert!(
+            init_result.is_ok(),
+            "Init failed: {}",
+            init_result.err().unwrap()
+        );
+
+        let result = handle(
+            &mut deps,
+            env,
+            HandleMsg::SwapTokensForBestRoute {
+                offer: TokenAmount {
+                    token: TokenType::NativeToken {
+                        denom: "other".into(),
+                    },
+                    amount: Uint128(10),
+                },
+                target_token: TokenType::CustomToken {
+                    contract_addr: CUSTOM_TOKEN_2.into(),
+                    token_code_hash: "hash".into(),
+                },
+                max_hops: Some(2),
+                expected_return: None,
+                recipient: None,
+                deadline: None,
+            },
+        );
+
+        match result {
+            Err(StdError::GenericErr { .. }) => {}
+            _ => panic!("Must return error"),
+        }
+
+        Ok(())
+    }
+
     /*
 
         //*** */
@@ -526,65 +898,96 @@ pub mod tests {
             let request: QueryRequest<Empty> = from_slice(bin_request).unwrap();
             match &request {
                 QueryRequest::Wasm(msg) => match msg {
-                    WasmQuery::Smart { contract_addr, .. } => {
+                    WasmQuery::Smart { contract_addr, msg, .. } => {
                         println!("{}", contract_addr);
                         match contract_addr.as_str() {
-                            FACTORY_ADDRESS => {
-                                QuerierResult::Ok(to_binary(&FactoryQueryResponse::GetConfig {
-                                    pair_contract: ContractInstantiationInfo {
-                                        code_hash: "".to_string(),
-                                        id: 1,
-                                    },
-                                    amm_settings: shadeswap_shared::amm_pair::AMMSettings {
-                                        lp_fee: Fee::new(28, 10000),
-                                        shade_dao_fee: Fee::new(2, 10000),
-                                        shade_dao_address: ContractLink {
-                                            address: HumanAddr(String::from("DAO")),
+                            FACTORY_ADDRESS => match from_binary(msg).unwrap() {
+                                FactoryQueryMsg::GetConfig => {
+                                    QuerierResult::Ok(to_binary(&FactoryQueryResponse::GetConfig {
+                                        pair_contract: ContractInstantiationInfo {
                                             code_hash: "".to_string(),
+                                            id: 1,
                                         },
+                                        amm_settings: shadeswap_shared::amm_pair::AMMSettings {
+                                            lp_fee: Fee::new(28, 10000),
+                                            shade_dao_fee: Fee::new(2, 10000),
+                                            shade_dao_address: ContractLink {
+                                                address: HumanAddr(String::from("DAO")),
+                                                code_hash: "".to_string(),
+                                            },
+                                            shade_dao_fee_mode: shadeswap_shared::amm_pair::ShadeDaoFeeMode::Transfer,
+                                        },
+                                        lp_token_contract: ContractInstantiationInfo {
+                                            code_hash: "".to_string(),
+                                            id: 1,
+                                        },
+                                    }))
+                                }
+                                FactoryQueryMsg::ListAMMPairs { pagination } => {
+                                    let amm_pairs = if pagination.start == 0 {
+                                        mock_amm_pairs()
+                                    } else {
+                                        vec![]
+                                    };
+                                    QuerierResult::Ok(to_binary(&FactoryQueryResponse::ListAMMPairs {
+                                        amm_pairs,
+                                    }))
+                                }
+                                _ => QuerierResult::Ok(Err(StdError::generic_err(
+                                    "Unexpected factory query",
+                                ))),
+                            },
+                            PAIR_CONTRACT_1 => mock_pair_query(
+                                msg,
+                                TokenPair(
+                                    TokenType::CustomToken {
+                                        contract_addr: CUSTOM_TOKEN_1.into(),
+                                        token_code_hash: "hash".into(),
                                     },
-                                    lp_token_contract: ContractInstantiationInfo {
-                                        code_hash: "".to_string(),
-                                        id: 1,
+                                    TokenType::NativeToken {
+                                        denom: "denom".into(),
                                     },
-                                }))
-                            }
-                            PAIR_CONTRACT_1 => QuerierResult::Ok(to_binary(
-                                &AMMPairQueryMsgResponse::GetPairInfo {
-                                    liquidity_token: ContractLink {
-                                        address: HumanAddr::from("asd"),
-                                        code_hash: "".to_string(),
+                                ),
+                            ),
+                            PAIR_CONTRACT_2 => mock_pair_query(
+                                msg,
+                                TokenPair(
+                                    TokenType::NativeToken {
+                                        denom: "denom".into(),
                                     },
-                                    factory: ContractLink {
-                                        address: HumanAddr::from("asd"),
-                                        code_hash: "".to_string(),
+                                    TokenType::CustomToken {
+                                        contract_addr: CUSTOM_TOKEN_2.into(),
+                                        token_code_hash: "hash".into(),
                                     },
-                                    pair: TokenPair(
-                                        TokenType::CustomToken {
-                                            contract_addr: CUSTOM_TOKEN_1.into(),
-                                            token_code_hash: "hash".into(),
-                                        },
-                                        TokenType::NativeToken {
-                                            denom: "denom".into(),
-                                        },
-                                    ),
-                                    amount_0: Uint128(100),
-                                    amount_1: Uint128(101),
-                                    total_liquidity: Uint128(100),
-                                    contract_version: 1,
-                                },
-                            )),
+                                ),
+                            ),
+                            PAIR_CONTRACT_3 => mock_pair_query(
+                                msg,
+                                TokenPair(
+                                    TokenType::CustomToken {
+                                        contract_addr: CUSTOM_TOKEN_1.into(),
+                                        token_code_hash: "hash".into(),
+                                    },
+                                    TokenType::CustomToken {
+                                        contract_addr: CUSTOM_TOKEN_2.into(),
+                                        token_code_hash: "hash".into(),
+                                    },
+                                ),
+                            ),
                             CUSTOM_TOKEN_1 => QuerierResult::Ok(to_binary(&IntBalanceResponse {
                                 balance: Balance {
                                     amount: Uint128(100),
                                 },
                             })),
-                            _ => unimplemented!(),
+                            address => QuerierResult::Ok(Err(StdError::generic_err(format!(
+                                "Unexpected query to {}",
+                                address
+                            )))),
                         }
                     }
-                    _ => unimplemented!(),
+                    _ => QuerierResult::Ok(Err(StdError::generic_err("Unexpected wasm query"))),
                 },
-                _ => unimplemented!(),
+                _ => QuerierResult::Ok(Err(StdError::generic_err("Unexpected query"))),
             }
         }
 
@@ -635,6 +1038,73 @@ pub mod tests {
             Ok(res.amount)
         }
     }
+    fn mock_amm_pairs() -> Vec<AMMPair<HumanAddr>> {
+        let custom_token_1 = TokenType::CustomToken {
+            contract_addr: CUSTOM_TOKEN_1.into(),
+            token_code_hash: "hash".into(),
+        };
+        let custom_token_2 = TokenType::CustomToken {
+            contract_addr: CUSTOM_TOKEN_2.into(),
+            token_code_hash: "hash".into(),
+        };
+        let native_token = TokenType::NativeToken {
+            denom: "denom".into(),
+        };
+        vec![
+            AMMPair {
+                pair: TokenPair(custom_token_1.clone(), native_token.clone()),
+                address: PAIR_CONTRACT_1.into(),
+            },
+            AMMPair {
+                pair: TokenPair(native_token, custom_token_2.clone()),
+                address: PAIR_CONTRACT_2.into(),
+            },
+            AMMPair {
+                pair: TokenPair(custom_token_1, custom_token_2),
+                address: PAIR_CONTRACT_3.into(),
+            },
+        ]
+    }
+
+    // Every pair returns the offer minus a flat fee of 10 on a swap simulation
+    fn mock_pair_query(msg: &Binary, pair: TokenPair<HumanAddr>) -> QuerierResult {
+        match from_binary(msg).unwrap() {
+            AMMPairQueryMsg::GetPairInfo => QuerierResult::Ok(to_binary(
+                &AMMPairQueryMsgResponse::GetPairInfo {
+                    liquidity_token: ContractLink {
+                        address: HumanAddr::from("asd"),
+                        code_hash: "".to_string(),
+                    },
+                    factory: ContractLink {
+                        address: HumanAddr::from("asd"),
+                        code_hash: "".to_string(),
+                    },
+                    pair,
+                    amount_0: Uint128(100),
+                    amount_1: Uint128(101),
+                    total_liquidity: Uint128(100),
+                    locked_liquidity: Uint128(0),
+                    pool_type: PoolType::ConstantProduct,
+                    oracle: None,
+                    contract_version: 1,
+                },
+            )),
+            AMMPairQueryMsg::SwapSimulation { offer } => QuerierResult::Ok(to_binary(
+                &AMMPairQueryMsgResponse::SwapSimulation {
+                    total_fee_amount: Uint128(10),
+                    lp_fee_amount: Uint128(8),
+                    shade_dao_fee_amount: Uint128(2),
+                    result: SwapResult {
+                        return_amount: (offer.amount - Uint128(10)).unwrap(),
+                        spread_amount: Uint128(0),
+                    },
+                    price: Uint128(1),
+                },
+            )),
+            _ => QuerierResult::Ok(Err(StdError::generic_err("Unexpected pair query"))),
+        }
+    }
+
     #[derive(Serialize, Deserialize)]
     struct IntBalanceResponse {
         pub balance: Balance,
@@ -1,11 +1,16 @@
-use shadeswap_shared::msg::staking::{{InitMsg, QueryMsg,QueryResponse,  HandleMsg}};
+use shadeswap_shared::msg::staking::{{InitMsg, QueryMsg,QueryResponse,  HandleMsg, InvokeMsg, LockTier, RewardPeriod}};
 use shadeswap_shared::msg::amm_pair::HandleMsg as AmmPairHandleMsg;
+use shadeswap_shared::msg::amm_pair::{{QueryMsg as AmmPairQueryMsg, QueryMsgResponse as AmmPairQueryMsgResponse}};
+use shadeswap_shared::token_amount::TokenAmount;
+use shadeswap_shared::token_type::TokenType;
 
-use crate::state::{{Config, ClaimRewardsInfo, store_config, load_claim_reward_timestamp,  store_claim_reward_timestamp,
+use crate::state::{{Config, ClaimRewardsInfo, RewardState, RewardStream, REWARD_PRECISION, MAX_REWARD_STREAMS,
+    BASE_LOCK_MULTIPLIER, MAX_LOCK_MULTIPLIER, load_lock_tiers, store_lock_tiers,
+    store_config, load_reward_state, store_reward_state,
     get_total_staking_amount, load_stakers, load_config, is_address_already_staker, store_claim_reward_info,
     store_staker, load_staker_info, store_staker_info, remove_staker, StakingInfo, load_claim_reward_info}};   
 use std::time::{SystemTime, UNIX_EPOCH};
-use shadeswap_shared::admin::{{store_admin, apply_admin_guard}};
+use shadeswap_shared::admin::{{store_admin, apply_admin_guard, set_admin_guard}};
 use shadeswap_shared::{ 
     fadroma::{
         scrt::{
@@ -21,6 +26,8 @@ use shadeswap_shared::{
     }
 };
 
+pub const BLOCK_SIZE: usize = 256;
+
 pub fn init<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
@@ -29,12 +36,25 @@ pub fn init<S: Storage, A: Api, Q: Querier>(
 
     let config = Config {
         contract_owner: env.message.sender.clone(),
-        daily_reward_amount: msg.staking_amount,
-        reward_token: msg.reward_token.clone()
+        pair_contract: msg.contract.clone(),
+        lp_token: None,
     };
     store_config(deps, &config)?;
     store_admin(deps, &env.message.sender.clone())?;
-    let mut messages = vec![];
+    store_reward_state(deps, &RewardState {
+        total_staked: Uint128(0u128),
+        total_weight: Uint128(0u128),
+        last_updated: Uint128(env.block.time as u128),
+        streams: vec![RewardStream {
+            token: msg.reward_token.clone(),
+            daily_reward_amount: msg.staking_amount,
+            start: Uint128(env.block.time as u128),
+            end: None,
+            remaining: Uint128(0u128),
+            reward_per_share: Uint256::from(0u128),
+        }],
+    })?;
+    let mut messages = register_receive_msgs(&env, &msg.reward_token)?;
     messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
         contract_addr: msg.contract.address.clone(),
         callback_code_hash: msg.contract.code_hash.clone(),
@@ -61,53 +81,359 @@ pub fn handle<S: Storage, A: Api, Q: Querier>(
     msg: HandleMsg,
 ) -> StdResult<HandleResponse> {
     match msg {
-        HandleMsg::Stake {         
-            amount,
-            from,
-        } => {
-            return stake(deps,env, amount,from)
-        },
         HandleMsg::ClaimRewards { } => {
             claim_rewards(deps, env)
         }
-        HandleMsg::Unstake {address} => unstake(deps,env, address),
+        HandleMsg::Unstake {amount} => unstake(deps,env, amount),
+        HandleMsg::SetLPToken {lp_token} => set_lp_token(deps, env, lp_token),
+        HandleMsg::SetStakingAdmin {admin} => set_staking_admin(deps, env, admin),
+        HandleMsg::AddRewardStream { token, daily_reward_amount, start, end } => {
+            add_reward_stream(deps, env, token, daily_reward_amount, start, end)
+        }
+        HandleMsg::Receive { from, amount, msg } => receiver_callback(deps, env, from, amount, msg),
+        HandleMsg::SetLockTiers { tiers } => set_lock_tiers(deps, env, tiers),
+        HandleMsg::Lock { duration } => lock(deps, env, duration),
+        HandleMsg::ExpireLock { staker } => expire_lock(deps, env, staker),
     }    
 }
 
-// This should be callback from Snip20 Receiver
-// needs to check for the amount
+fn receiver_callback<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    from: HumanAddr,
+    amount: Uint128,
+    msg: Option<Binary>,
+) -> StdResult<HandleResponse> {
+    let msg = msg.ok_or_else(|| {
+        StdError::generic_err("Receiver callback \"msg\" parameter cannot be empty.")
+    })?;
+    match from_binary(&msg)? {
+        InvokeMsg::FundRewardPeriod { duration } => fund_reward_period(deps, env, from, amount, duration),
+        InvokeMsg::Stake {} => {
+            let config = load_config(deps)?;
+            match config.lp_token {
+                Some(lp_token) if lp_token.address == env.message.sender => stake(deps, env, amount, from),
+                _ => Err(StdError::generic_err("Only the LP token of the pair can be staked.")),
+            }
+        }
+    }
+}
+
+/// Sent by the pair once both it and the LP token know about the staking contract.
+fn set_lp_token<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    lp_token: ContractLink<HumanAddr>
+) -> StdResult<HandleResponse>{
+    apply_admin_guard(env.message.sender.clone(), &deps.storage)?;
+    let mut config = load_config(deps)?;
+    if config.lp_token.is_some() {
+        return Err(StdError::unauthorized());
+    }
+    config.lp_token = Some(lp_token.clone());
+    store_config(deps, &config)?;
+
+    Ok(HandleResponse {
+        messages: vec![snip20::register_receive_msg(
+            env.contract_code_hash,
+            None,
+            BLOCK_SIZE,
+            lp_token.code_hash,
+            lp_token.address.clone(),
+        )?],
+        log: vec![log("lp_token", lp_token.address)],
+        data: None,
+    })
+}
+
+/// Lets the contract receive `token`, streams can only be funded with registered tokens.
+fn register_receive_msgs(env: &Env, token: &TokenType<HumanAddr>) -> StdResult<Vec<CosmosMsg>> {
+    match token {
+        TokenType::CustomToken { contract_addr, token_code_hash } => Ok(vec![snip20::register_receive_msg(
+            env.contract_code_hash.clone(),
+            None,
+            BLOCK_SIZE,
+            token_code_hash.clone(),
+            contract_addr.clone(),
+        )?]),
+        TokenType::NativeToken { .. } => Ok(vec![]),
+    }
+}
+
+fn set_staking_admin<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    admin: ContractLink<HumanAddr>
+) -> StdResult<HandleResponse>{
+    let response = set_admin_guard(deps, env, admin.address.clone())?;
+    let mut config = load_config(deps)?;
+    config.pair_contract = admin;
+    store_config(deps, &config)?;
+    Ok(response)
+}
+
+/// Lets the admin of the pair through, the staking admin itself is the pair.
+fn apply_pair_admin_guard<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    caller: HumanAddr
+) -> StdResult<()>{
+    let config = load_config(deps)?;
+    let result: AmmPairQueryMsgResponse = deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        callback_code_hash: config.pair_contract.code_hash,
+        contract_addr: config.pair_contract.address,
+        msg: to_binary(&AmmPairQueryMsg::GetAdmin)?,
+    }))?;
+    match result {
+        AmmPairQueryMsgResponse::GetAdminAddress { address } if address == caller => Ok(()),
+        _ => Err(StdError::unauthorized()),
+    }
+}
+
+pub fn add_reward_stream<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    token: TokenType<HumanAddr>,
+    daily_reward_amount: Uint128,
+    start: Option<u64>,
+    end: Option<u64>
+) -> StdResult<HandleResponse>{
+    apply_pair_admin_guard(deps, env.message.sender.clone())?;
+    // streams are only funded through the SNIP20 receiver interface
+    if let TokenType::NativeToken { .. } = token {
+        return Err(StdError::generic_err("Reward streams have to pay out a SNIP20 token."));
+    }
+    let current_timestamp = Uint128(env.block.time as u128);
+    let start = start.map(|start| Uint128(start as u128)).unwrap_or(current_timestamp);
+    let end = end.map(|end| Uint128(end as u128));
+    if let Some(end) = end {
+        if end <= start || end <= current_timestamp {
+            return Err(StdError::generic_err("The reward stream has to end in the future, after its start."));
+        }
+    }
+    // the existing streams are accrued up to now before the new one counts
+    let mut state = update_reward_state(deps, current_timestamp)?;
+    if state.streams.len() >= MAX_REWARD_STREAMS {
+        return Err(StdError::generic_err(format!(
+            "A staking contract can't have more than {} reward streams.", MAX_REWARD_STREAMS
+        )));
+    }
+    state.streams.push(RewardStream {
+        token: token.clone(),
+        daily_reward_amount,
+        start,
+        end,
+        remaining: Uint128(0u128),
+        reward_per_share: Uint256::from(0u128),
+    });
+    store_reward_state(deps, &state)?;
+
+    Ok(HandleResponse {
+        messages: register_receive_msgs(&env, &token)?,
+        log: vec![
+                log("action", "add_reward_stream"),
+                log("reward_token", token),
+                log("daily_reward_amount", daily_reward_amount),
+                log("start", start),
+                log("end", format!("{:?}", end)),
+        ],
+        data: None,
+    })
+}
+
+/// Pays `amount` of the stream funded by the sending token, and whatever is left of its
+/// budget, out over `duration` seconds from now or from the stream start if that is later.
+pub fn fund_reward_period<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    from: HumanAddr,
+    amount: Uint128,
+    duration: u64
+) -> StdResult<HandleResponse>{
+    apply_pair_admin_guard(deps, from)?;
+    if duration == 0 {
+        return Err(StdError::generic_err("A reward period can't be empty."));
+    }
+    let current_timestamp = Uint128(env.block.time as u128);
+    // the current period is paid out up to now before it is replaced
+    let mut state = update_reward_state(deps, current_timestamp)?;
+    let stream = state.streams.iter_mut().rev().find(|stream| match &stream.token {
+        TokenType::CustomToken { contract_addr, .. } => *contract_addr == env.message.sender,
+        TokenType::NativeToken { .. } => false,
+    }).ok_or_else(|| StdError::generic_err("The sent token doesn't belong to any reward stream."))?;
+    let budget = stream.remaining + amount;
+    let start = stream.start.max(current_timestamp);
+    let end = start + Uint128(duration as u128);
+    stream.daily_reward_amount = budget.multiply_ratio(24u128 * 60u128 * 60u128, duration as u128);
+    stream.start = start;
+    stream.end = Some(end);
+    stream.remaining = budget;
+    let token = stream.token.clone();
+    let daily_reward_amount = stream.daily_reward_amount;
+    store_reward_state(deps, &state)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+                log("action", "fund_reward_period"),
+                log("reward_token", token),
+                log("budget", budget),
+                log("daily_reward_amount", daily_reward_amount),
+                log("end", end),
+        ],
+        data: None,
+    })
+}
+
+pub fn set_lock_tiers<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    tiers: Option<Vec<LockTier>>
+) -> StdResult<HandleResponse>{
+    apply_pair_admin_guard(deps, env.message.sender.clone())?;
+    if let Some(tiers) = &tiers {
+        for (i, tier) in tiers.iter().enumerate() {
+            if tier.duration == 0 || tier.multiplier < BASE_LOCK_MULTIPLIER || tier.multiplier > MAX_LOCK_MULTIPLIER {
+                return Err(StdError::generic_err(format!(
+                    "Lock tiers need a duration and a multiplier between {} and {}.",
+                    BASE_LOCK_MULTIPLIER, MAX_LOCK_MULTIPLIER
+                )));
+            }
+            if i > 0 && (tier.duration <= tiers[i - 1].duration || tier.multiplier < tiers[i - 1].multiplier) {
+                return Err(StdError::generic_err("Lock tiers have to be sorted by duration and multiplier."));
+            }
+        }
+    }
+    store_lock_tiers(&mut deps.storage, &tiers)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("action", "set_lock_tiers")],
+        data: None,
+    })
+}
+
+/// Multiplier of the longest tier `duration` covers.
+fn lock_multiplier(tiers: &Option<Vec<LockTier>>, duration: u64) -> StdResult<u32> {
+    tiers.iter().flatten()
+        .filter(|tier| tier.duration <= duration)
+        .map(|tier| tier.multiplier)
+        .max()
+        .ok_or_else(|| StdError::generic_err("The lock is shorter than the shortest lock tier."))
+}
+
+pub fn lock<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    duration: u64
+) -> StdResult<HandleResponse>{
+    let staker = env.message.sender.clone();
+    if is_address_already_staker(deps, staker.clone())? != true {
+        return Err(StdError::unauthorized())
+    }
+    let multiplier = lock_multiplier(&load_lock_tiers(&deps.storage)?, duration)?;
+    let current_timestamp = Uint128(env.block.time as u128);
+    let lock_end = current_timestamp + Uint128(duration as u128);
+    let mut state = update_reward_state(deps, current_timestamp)?;
+    let mut stake_info = settle_staker_rewards(deps, staker.clone(), &state)?;
+    // drops an expired lock, a running one can only be extended
+    reweigh_staker(&mut state, &mut stake_info, current_timestamp)?;
+    if let Some(current_end) = stake_info.lock_end {
+        if lock_end <= current_end {
+            return Err(StdError::generic_err(format!("The stake is already locked until {}.", current_end)));
+        }
+    }
+    // extending never lowers the boost of the running lock
+    stake_info.multiplier = stake_info.multiplier.max(multiplier);
+    stake_info.lock_end = Some(lock_end);
+    reweigh_staker(&mut state, &mut stake_info, current_timestamp)?;
+    store_staker_info(deps, &stake_info)?;
+    store_reward_state(deps, &state)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+                log("action", "lock"),
+                log("staker", staker.as_str()),
+                log("multiplier", stake_info.multiplier),
+                log("lock_end", lock_end),
+        ],
+        data: None,
+    })
+}
+
+pub fn expire_lock<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    staker: HumanAddr
+) -> StdResult<HandleResponse>{
+    let current_timestamp = Uint128(env.block.time as u128);
+    let is_expired = is_address_already_staker(deps, staker.clone())? &&
+        load_staker_info(deps, staker.clone())?.lock_end.map_or(false, |lock_end| lock_end <= current_timestamp);
+    if !is_expired {
+        return Err(StdError::generic_err("The staker has no expired lock."));
+    }
+    let mut state = update_reward_state(deps, current_timestamp)?;
+    let mut stake_info = settle_staker_rewards(deps, staker.clone(), &state)?;
+    reweigh_staker(&mut state, &mut stake_info, current_timestamp)?;
+    store_staker_info(deps, &stake_info)?;
+    store_reward_state(deps, &state)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+                log("action", "expire_lock"),
+                log("staker", staker.as_str()),
+        ],
+        data: None,
+    })
+}
+
+/// Stakes the `amount` of LP tokens `from` sent to the contract.
 pub fn stake<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
     amount: Uint128,
     from: HumanAddr
 ) -> StdResult<HandleResponse>{
-    apply_admin_guard(env.message.sender.clone(), &deps.storage)?;
-    claim_rewards_for_all_stakers(deps, Uint128(env.block.time as u128))?;
+    if amount.is_zero() {
+        return Err(StdError::generic_err("Can't stake zero LP tokens."));
+    }
+    let current_timestamp = Uint128(env.block.time as u128);
+    let mut state = update_reward_state(deps, current_timestamp)?;
     let caller = from.clone();
     // check if caller exist
     let is_staker = is_address_already_staker(&deps, caller.clone())?;   
     if is_staker == true {
-        let mut stake_info = load_staker_info(deps, caller.clone())?;
+        let mut stake_info = settle_staker_rewards(deps, caller.clone(), &state)?;
         stake_info.amount += amount;
-        stake_info.last_time_updated = Uint128(env.block.time as u128);        
+        stake_info.last_time_updated = current_timestamp;
+        reweigh_staker(&mut state, &mut stake_info, current_timestamp)?;
         store_staker_info(deps, &stake_info)?;
     }
     else{
         store_staker(deps, caller.clone())?;
-        store_staker_info(deps, &StakingInfo{
+        let mut stake_info = StakingInfo{
             staker: caller.clone(),
             amount: amount,
-            last_time_updated: Uint128(env.block.time as u128),
-        })?;
+            weight: Uint128(0u128),
+            multiplier: BASE_LOCK_MULTIPLIER,
+            lock_end: None,
+            last_time_updated: current_timestamp,
+            reward_per_share_paid: state.streams.iter().map(|stream| stream.reward_per_share).collect(),
+        };
+        reweigh_staker(&mut state, &mut stake_info, current_timestamp)?;
+        store_staker_info(deps, &stake_info)?;
+        // store zero for claim rewards, unless rewards of an earlier stake are still unclaimed
+        if load_claim_reward_info(deps, caller.clone()).is_err() {
+            store_claim_reward_info(deps, &ClaimRewardsInfo{
+                staker: caller.clone(),
+                amounts: vec![Uint128(0u128); state.streams.len()],
+                last_time_claimed: current_timestamp,
+            })?;
+        }
     }
-
-    // store zero for claim rewards
-    store_claim_reward_info(deps, &ClaimRewardsInfo{
-        staker: caller.clone(),
-        amount: Uint128(0u128),
-        last_time_claimed: Uint128(env.block.time as u128),
-    })?;
+    state.total_staked += amount;
+    store_reward_state(deps, &state)?;
 
     // return response
     Ok(HandleResponse {
@@ -127,77 +453,151 @@ pub fn claim_rewards<S: Storage, A: Api, Q: Querier>(
 ) -> StdResult<HandleResponse>{
 
     let receiver = env.message.sender.clone();
-    let is_user_staker = is_address_already_staker(deps, receiver.clone())?;
-    if is_user_staker != true {
+    // stakers who unstaked everything can still claim what they earned
+    if load_claim_reward_info(deps, receiver.clone()).is_err() {
         return Err(StdError::unauthorized())
     }
-    let current_timestamp =  Uint128((env.block.time * 1000) as u128); 
-    let mut messages = Vec::new();
-    // calculate for all also for user
-    claim_rewards_for_all_stakers(deps, current_timestamp)?;
+    let current_timestamp = Uint128(env.block.time as u128);
+    let mut state = update_reward_state(deps, current_timestamp)?;
+    let mut stake_info = settle_staker_rewards(deps, receiver.clone(), &state)?;
+    reweigh_staker(&mut state, &mut stake_info, current_timestamp)?;
+    store_staker_info(deps, &stake_info)?;
+    store_reward_state(deps, &state)?;
     let mut claim_info = load_claim_reward_info(deps, receiver.clone())?;
-    let claim_amount = claim_info.amount;
-    claim_info.amount = Uint128(0u128);
+    let claim_amounts = claim_info.amounts;
+    claim_info.amounts = vec![Uint128(0u128); claim_amounts.len()];
     claim_info.last_time_claimed =  current_timestamp;
     store_claim_reward_info(deps, &claim_info)?;   
-    let config = load_config(deps)?;
-    // send the message
-    messages.push(config.reward_token.create_send_msg(
-        env.contract.address.clone(),
-        receiver.clone(),
-        claim_amount,
-    )?);    
+    // send the messages
+    let messages = reward_send_msgs(&state, &claim_amounts, env.contract.address.clone(), receiver.clone())?;
    
     Ok(HandleResponse {
         messages: messages,
         log: vec![
                 log("action", "claim_rewards"),
                 log("caller", receiver.as_str().clone()),
-                log("reward_amount",claim_amount),
+                log("reward_amount", format_amounts(&claim_amounts)),
         ],
         data: None,
     })
 }
 
-// Total Available Rewards = Daily_Rewards / 24*60*60*1000 * (current_date_time - last_calculated_date_time).miliseconds()
-// User Incremental Rewards = Total Available Rewards * Staked Percentage
-// User Total Rewards = User Owed Rewards + (User Incremental Rewards)
-pub fn claim_rewards_for_all_stakers<S:Storage, A:Api, Q: Querier>(
+// Total Available Rewards = Daily_Rewards / 24*60*60 * (current_date_time - last_calculated_date_time).seconds()
+// Reward Per Share += Total Available Rewards / Total Staked, for every stream while it runs
+// User Total Rewards = User Owed Rewards + Staked * (Reward Per Share - User Reward Per Share Paid)
+pub fn update_reward_state<S:Storage, A:Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     current_timestamp: Uint128
+) -> StdResult<RewardState> {
+    let mut state = load_reward_state(deps)?;
+    accrue_rewards(&mut state, current_timestamp)?;
+    store_reward_state(deps, &state)?;
+    Ok(state)
+}
+
+/// Accrues the rewards of every stream up to `current_timestamp`. Streams never pay out more
+/// than their remaining budget, nothing is paid out while nothing is staked.
+pub fn accrue_rewards(
+    state: &mut RewardState,
+    current_timestamp: Uint128
 ) -> StdResult<()> {
-    let stakers = load_stakers(deps)?;
-    let last_timestamp = load_claim_reward_timestamp(deps)?;    
-    for staker in stakers.into_iter() {
-        let mut claim_info = load_claim_reward_info(deps, staker.clone())?;
-        let staking_reward = calculate_staking_reward(deps, staker.clone(),last_timestamp, current_timestamp)?;
-        claim_info.amount += staking_reward;
-        claim_info.last_time_claimed = current_timestamp;
-        store_claim_reward_info(deps, &claim_info)?;
+    let seconds = Uint128(24u128 * 60u128 *60u128); 
+    for stream in state.streams.iter_mut() {
+        let from = state.last_updated.max(stream.start);
+        let to = stream.end.map_or(current_timestamp, |end| end.min(current_timestamp));
+        if state.total_weight.is_zero() || to <= from {
+            continue;
+        }
+        let time_dif = (to - from)?;
+        let total_available_reward = stream.daily_reward_amount.multiply_ratio(time_dif, seconds)
+            .min(stream.remaining);
+        stream.remaining = (stream.remaining - total_available_reward)?;
+        let increase = ((Uint256::from(total_available_reward) * Uint256::from(REWARD_PRECISION))?
+            / Uint256::from(state.total_weight))?;
+        stream.reward_per_share = (stream.reward_per_share + increase)?;
+    }
+    if current_timestamp > state.last_updated {
+        state.last_updated = current_timestamp;
     }
-    store_claim_reward_timestamp(deps, current_timestamp )?;
     Ok(())
 }
 
-pub fn calculate_staking_reward<S:Storage, A:Api, Q: Querier>(
-    deps: &Extern<S, A, Q>,
+/// Rewards of `stake_info` accrued since its last checkpoint, one per stream.
+fn pending_rewards(stake_info: &StakingInfo, reward_per_share: &[Uint256]) -> StdResult<Vec<Uint128>> {
+    let mut result = Vec::with_capacity(reward_per_share.len());
+    for (i, reward_per_share) in reward_per_share.iter().enumerate() {
+        let paid = stake_info.reward_per_share_paid.get(i).copied().unwrap_or(Uint256::from(0u128));
+        let unpaid = (*reward_per_share - paid)?;
+        let reward = ((Uint256::from(stake_info.weight) * unpaid)?
+            / Uint256::from(REWARD_PRECISION))?;
+        result.push(Uint128(reward.clamp_u128()?));
+    }
+    Ok(result)
+}
+
+/// Adds `b` to `a` per stream, `a` grows with streams added since it was stored.
+fn add_amounts(a: &mut Vec<Uint128>, b: &[Uint128]) {
+    if a.len() < b.len() {
+        a.resize(b.len(), Uint128(0u128));
+    }
+    for (a, b) in a.iter_mut().zip(b) {
+        *a += *b;
+    }
+}
+
+/// Moves the pending rewards of the staker to its claimable amounts, the checkpointed
+/// staking info is returned for the caller to update and store.
+fn settle_staker_rewards<S:Storage, A:Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
     staker: HumanAddr,
-    last_timestamp: Uint128,
+    state: &RewardState
+) -> StdResult<StakingInfo> {
+    let reward_per_share: Vec<Uint256> = state.streams.iter().map(|stream| stream.reward_per_share).collect();
+    let mut stake_info = load_staker_info(deps, staker.clone())?;
+    let mut claim_info = load_claim_reward_info(deps, staker)?;
+    add_amounts(&mut claim_info.amounts, &pending_rewards(&stake_info, &reward_per_share)?);
+    claim_info.last_time_claimed = state.last_updated;
+    store_claim_reward_info(deps, &claim_info)?;
+    stake_info.reward_per_share_paid = reward_per_share;
+    Ok(stake_info)
+}
+
+/// Sets the weight of the staker from its amount and multiplier, an expired lock loses
+/// its boost. Has to follow `settle_staker_rewards`, the old weight earned until now.
+fn reweigh_staker(
+    state: &mut RewardState,
+    stake_info: &mut StakingInfo,
     current_timestamp: Uint128
-) -> StdResult<Uint128>{
-    let cons = Uint128(100u128);
-    let percentage = get_staking_percentage(deps,staker, cons)?;
-    let config = load_config(deps)?;
-    let seconds = Uint128(24u128 * 60u128 *60u128); 
-    let time_dif = (current_timestamp - last_timestamp)?;           
-    if time_dif != Uint128(0u128) {        
-        let total_available_reward = config.daily_reward_amount.multiply_ratio(time_dif, seconds);
-        let result = total_available_reward.multiply_ratio(percentage, cons);
-        Ok(result)
-    }else{
-        Ok(Uint128(0u128))
+) -> StdResult<()> {
+    if stake_info.lock_end.map_or(false, |lock_end| lock_end <= current_timestamp) {
+        stake_info.lock_end = None;
+        stake_info.multiplier = BASE_LOCK_MULTIPLIER;
     }
-   
+    let weight = stake_info.amount.multiply_ratio(stake_info.multiplier, BASE_LOCK_MULTIPLIER);
+    state.total_weight = (state.total_weight - stake_info.weight)? + weight;
+    stake_info.weight = weight;
+    Ok(())
+}
+
+fn format_amounts(amounts: &[Uint128]) -> String {
+    amounts.iter().map(|amount| amount.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+/// Sends every non zero claimable amount to the staker.
+fn reward_send_msgs(
+    state: &RewardState,
+    amounts: &[Uint128],
+    contract: HumanAddr,
+    staker: HumanAddr
+) -> StdResult<Vec<CosmosMsg>> {
+    let mut messages = vec![];
+    for (stream, amount) in state.streams.iter().zip(amounts) {
+        if amount.is_zero() {
+            continue;
+        }
+        messages.push(stream.token.create_send_msg(contract.clone(), staker.clone(), *amount)?);
+    }
+    Ok(messages)
 }
 
 pub fn get_staking_percentage<S:Storage, A:Api, Q: Querier>(
@@ -205,9 +605,9 @@ pub fn get_staking_percentage<S:Storage, A:Api, Q: Querier>(
     staker: HumanAddr,
     cons: Uint128
 ) -> StdResult<Uint128> {
-    let total_staking = Uint256::from(get_total_staking_amount(deps)?);
+    let total_staking = Uint256::from(load_reward_state(deps)?.total_weight);
     let stake_info = load_staker_info(&deps, staker)?;
-    let stake_amount = Uint256::from(stake_info.amount);   
+    let stake_amount = Uint256::from(stake_info.weight);   
     let percentage =((stake_amount * Uint256::from(cons))? / total_staking)?;    
     Ok(Uint128(percentage.clamp_u128()?))
 }
@@ -216,8 +616,52 @@ pub fn query<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>, msg: QueryM
     match msg {
         QueryMsg::GetStakers{ } => {get_all_stakers(deps)},
         QueryMsg::GetClaimReward{time,staker} =>{get_claim_reward_for_user(deps, staker, time)},
+        QueryMsg::GetStakedAmount { staker } => get_staked_amount(deps, staker),
         QueryMsg::GetContractOwner {} => {get_staking_contract_owner(deps)},
+        QueryMsg::GetRewardPeriods { time } => get_reward_periods(deps, time),
+        QueryMsg::GetLockTiers {} => {
+            let tiers = load_lock_tiers(&deps.storage)?;
+            to_binary(&QueryResponse::LockTiers { tiers })
+        }
+        QueryMsg::GetStakerLock { staker } => get_staker_lock(deps, staker),
+    }
+}
+
+pub fn get_staker_lock<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    staker: HumanAddr,
+) -> StdResult<Binary> {
+    if !is_address_already_staker(deps, staker.clone())? {
+        return to_binary(&QueryResponse::StakerLock {
+            amount: Uint128(0u128),
+            weight: Uint128(0u128),
+            multiplier: BASE_LOCK_MULTIPLIER,
+            lock_end: None,
+        });
     }
+    let stake_info = load_staker_info(deps, staker)?;
+    to_binary(&QueryResponse::StakerLock {
+        amount: stake_info.amount,
+        weight: stake_info.weight,
+        multiplier: stake_info.multiplier,
+        lock_end: stake_info.lock_end.map(|lock_end| lock_end.u128() as u64),
+    })
+}
+
+pub fn get_reward_periods<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    time: u128
+) -> StdResult<Binary> {
+    let mut state = load_reward_state(deps)?;
+    accrue_rewards(&mut state, Uint128(time))?;
+    let periods = state.streams.into_iter().map(|stream| RewardPeriod {
+        token: stream.token,
+        daily_reward_amount: stream.daily_reward_amount,
+        remaining_budget: stream.remaining,
+        start: stream.start.u128() as u64,
+        end: stream.end.map(|end| end.u128() as u64),
+    }).collect();
+    to_binary(&QueryResponse::RewardPeriods { periods })
 }
 
 pub fn get_staking_contract_owner<S: Storage, A: Api, Q: Querier>(
@@ -227,18 +671,35 @@ pub fn get_staking_contract_owner<S: Storage, A: Api, Q: Querier>(
     to_binary(&QueryResponse::ContractOwner { address: config.contract_owner})
 }
 
+pub fn get_staked_amount<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    staker: HumanAddr,
+) -> StdResult<Binary> {
+    let amount = if is_address_already_staker(deps, staker.clone())? {
+        load_staker_info(deps, staker)?.amount
+    } else {
+        Uint128(0u128)
+    };
+    to_binary(&QueryResponse::StakedAmount { amount })
+}
+
 pub fn get_claim_reward_for_user<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>, 
     staker: HumanAddr,
     time: u128
 )-> StdResult<Binary> {
-    let unpaid_claim = load_claim_reward_info(deps, staker.clone())?;
-    let last_claim_timestamp = load_claim_reward_timestamp(deps)?;   
-    let current_timestamp = Uint128(time); //  get_current_timestamp()?; 
-    let current_claim = calculate_staking_reward(deps,
-        staker.clone(), last_claim_timestamp, current_timestamp)?;
-    let total_claim = unpaid_claim.amount + current_claim;
-    to_binary(&QueryResponse::ClaimReward{amount: total_claim})
+    let mut total_claim = load_claim_reward_info(deps, staker.clone())?.amounts;
+    let stake_info = load_staker_info(deps, staker)?;
+    let mut state = load_reward_state(deps)?;
+    // time is a block time in seconds
+    accrue_rewards(&mut state, Uint128(time))?;
+    let reward_per_share: Vec<Uint256> = state.streams.iter().map(|stream| stream.reward_per_share).collect();
+    add_amounts(&mut total_claim, &pending_rewards(&stake_info, &reward_per_share)?);
+    let rewards = state.streams.into_iter().zip(total_claim).map(|(stream, amount)| TokenAmount {
+        token: stream.token,
+        amount,
+    }).collect();
+    to_binary(&QueryResponse::ClaimReward{ rewards })
 }
 
 pub fn get_all_stakers<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>) -> StdResult<Binary>{
@@ -256,53 +717,60 @@ pub fn get_current_timestamp()-> StdResult<Uint128> {
 }
 
 
+/// Sends `amount` of the staked LP tokens back to the staker. Its rewards are kept for
+/// `ClaimRewards`, so a short reward balance never holds the LP tokens back.
 pub fn unstake<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
-    address: HumanAddr,
+    amount: Uint128,
 ) -> StdResult<HandleResponse>{
-    apply_admin_guard(env.message.sender.clone(), &deps.storage)?;
-    let caller = address;
-    let current_timestamp = Uint128((env.block.time * 1000) as u128);
+    let caller = env.message.sender.clone();
+    let current_timestamp = Uint128(env.block.time as u128);
     let is_user_staker = is_address_already_staker(deps, caller.clone())?;
-    let config = load_config(deps)?;
     if is_user_staker != true {
         return Err(StdError::unauthorized())
     }
-    // claim rewards
-    claim_rewards_for_all_stakers(deps, current_timestamp)?;
-    // remove staker
-    remove_staker(deps, caller.clone())?;
-    let mut messages = Vec::new();
+    let mut staker_info = load_staker_info(deps, caller.clone())?;
+    if let Some(lock_end) = staker_info.lock_end {
+        if lock_end > current_timestamp {
+            return Err(StdError::generic_err(format!("The stake is locked until {}.", lock_end)));
+        }
+    }
+    if amount.is_zero() || amount > staker_info.amount {
+        return Err(StdError::generic_err(format!(
+            "Can only unstake between 0 and {} LP tokens.", staker_info.amount
+        )));
+    }
+    // the rewards earned so far stay claimable
+    let mut state = update_reward_state(deps, current_timestamp)?;
+    staker_info = settle_staker_rewards(deps, caller.clone(), &state)?;
+    state.total_staked = (state.total_staked - amount)?;
     // update stake_info
-    let mut staker_info = load_staker_info(deps, caller.clone())?;        
-    staker_info.amount = Uint128(0);
+    staker_info.amount = (staker_info.amount - amount)?;
     staker_info.last_time_updated = current_timestamp;
+    if staker_info.amount.is_zero() {
+        remove_staker(deps, caller.clone())?;
+    }
+    reweigh_staker(&mut state, &mut staker_info, current_timestamp)?;
     store_staker_info(deps, &staker_info)?;
+    store_reward_state(deps, &state)?;
 
-    // send reward if any and 
-    let mut claim_reward = load_claim_reward_info(deps, caller.clone())?;
-    // send all remaing reward token
-    messages.push(config.reward_token.create_send_msg(
-        env.contract.address.clone(),
-        caller.clone(),
-        claim_reward.amount,
-    )?); 
-
-    // update claim  reward for staker
-    claim_reward.amount = Uint128(0);
-    claim_reward.last_time_claimed = current_timestamp;
-    store_claim_reward_info(deps, &ClaimRewardsInfo{
-        staker: caller.clone(),
-        amount: Uint128(0),
-        last_time_claimed: Uint128(env.block.time as u128)
-    })?;
+    let lp_token = load_config(deps)?.lp_token
+        .ok_or_else(|| StdError::generic_err("The LP token of the pair isn't known yet."))?;
   
     Ok(HandleResponse {
-        messages: messages,
+        messages: vec![snip20::transfer_msg(
+            caller.clone(),
+            amount,
+            None,
+            BLOCK_SIZE,
+            lp_token.code_hash,
+            lp_token.address,
+        )?],
         log: vec![
                 log("action", "unstake"),
                 log("staker", caller.as_str()),
+                log("amount", amount),
         ],
         data: None,
     })
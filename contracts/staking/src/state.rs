@@ -6,8 +6,9 @@ use shadeswap_shared::{
             Api, CanonicalAddr, Extern, HumanAddr, Uint128,
             Querier, StdResult, Storage, StdError
         },
-        scrt_storage::{load, save, ns_save, ns_load},
+        scrt_storage::{load, save, ns_save, ns_load, ns_remove},
         scrt_vk::ViewingKey,
+        scrt_uint256::Uint256,
     },
     token_pair::TokenPair
 };
@@ -16,32 +17,82 @@ use serde::{Deserialize, Serialize};
 use shadeswap_shared::token_type::TokenType;
 use serde::de::DeserializeOwned;
 use shadeswap_shared::msg::amm_pair::{{ HandleMsg,TradeHistory}};
+use shadeswap_shared::msg::staking::LockTier;
 use std::fmt::{{Formatter, Display}};
 
 pub static STAKING_CONFIG: &[u8] = b"STAKING_CONFIG";
-pub static LIST_STAKERS: &[u8] = b"LIST_STAKERS";
+pub static STAKERS: &[u8] = b"STAKERS";
+pub static STAKER_INDEX: &[u8] = b"STAKER_INDEX";
+pub static STAKERS_COUNT: &[u8] = b"STAKERS_COUNT";
 pub static STAKING_INFO: &[u8] = b"STAKING_INFO";
 pub static CLAIM_REWARDS: &[u8] = b"CLAIM_REWARDS";
-pub static LAST_REWARD_TIME_CLAIMED: &[u8] = b"LAST_REWARD_TIME_CLAIMED";
+pub static REWARD_STATE: &[u8] = b"REWARD_STATE";
+pub static LOCK_TIERS: &[u8] = b"LOCK_TIERS";
+
+/// Scale of `RewardStream::reward_per_share`.
+pub const REWARD_PRECISION: u128 = 1_000_000_000_000;
+/// Every stake, claim and unstake visits all reward streams.
+pub const MAX_REWARD_STREAMS: usize = 10;
+/// Multiplier of unlocked stakes, multipliers are in basis points.
+pub const BASE_LOCK_MULTIPLIER: u32 = 10_000;
+pub const MAX_LOCK_MULTIPLIER: u32 = 50_000;
 
 #[derive(Serialize, Deserialize,  PartialEq, Debug)]
 pub struct Config {
     pub contract_owner: HumanAddr,
-    pub daily_reward_amount: Uint128,
-    pub reward_token: TokenType<HumanAddr>   
+    /// The pair whose LP is staked, its admin manages the reward streams.
+    pub pair_contract: ContractLink<HumanAddr>,
+    /// Only this token can be staked, `None` until the pair sends it.
+    pub lp_token: Option<ContractLink<HumanAddr>>,
 }
 
 #[derive(Serialize, Deserialize,  PartialEq, Debug)]
 pub struct StakingInfo{
     pub staker: HumanAddr,
     pub amount: Uint128,
-    pub last_time_updated: Uint128
+    /// `amount` boosted by `multiplier`, the share of the rewards the staker earns.
+    pub weight: Uint128,
+    pub multiplier: u32,
+    /// Block time in seconds before which the stake can't be unstaked.
+    pub lock_end: Option<Uint128>,
+    pub last_time_updated: Uint128,
+    /// `RewardStream::reward_per_share` of every stream up to which the rewards of the
+    /// staker are paid out to its `ClaimRewardsInfo`, missing streams are at zero.
+    pub reward_per_share_paid: Vec<Uint256>,
+}
+
+#[derive(Serialize, Deserialize,  PartialEq, Debug, Clone)]
+pub struct RewardStream{
+    pub token: TokenType<HumanAddr>,
+    pub daily_reward_amount: Uint128,
+    /// Block times in seconds, no rewards are paid outside of them.
+    pub start: Uint128,
+    pub end: Option<Uint128>,
+    /// Funded reward tokens not paid out yet, the stream only pays while it has some left.
+    pub remaining: Uint128,
+    /// Rewards earned by a single staked token since the stream was added, scaled by
+    /// `REWARD_PRECISION`. 256 bits, as 18 decimal rewards over a tiny stake overflow 128.
+    pub reward_per_share: Uint256,
+}
+
+/// Rewards are spread over the stakers through an accumulator per stream, so no operation
+/// has to visit every staker.
+#[derive(Serialize, Deserialize,  PartialEq, Debug)]
+pub struct RewardState{
+    pub total_staked: Uint128,
+    /// Sum of the `StakingInfo::weight` of all stakers, rewards are spread over it.
+    pub total_weight: Uint128,
+    /// Block time in seconds up to which the streams are accrued.
+    pub last_updated: Uint128,
+    /// Streams are only appended, their index identifies them.
+    pub streams: Vec<RewardStream>,
 }
 
 #[derive(Serialize, Deserialize,  PartialEq, Debug)]
 pub struct ClaimRewardsInfo{
     pub staker: HumanAddr,
-    pub amount: Uint128,
+    /// Claimable amount of every reward stream.
+    pub amounts: Vec<Uint128>,
     pub last_time_claimed: Uint128
 }
 
@@ -61,56 +112,85 @@ pub fn load_config<S: Storage, A: Api, Q: Querier>(
     Ok(result)
 }
 
+/// Lists every staker, only used by queries.
 pub fn load_stakers<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>
 ) -> StdResult<Vec<HumanAddr>> {
-   let stakers = load(&deps.storage, LIST_STAKERS)?.unwrap_or(Vec::new());    
-   Ok(stakers)
+    let count = load_stakers_count(&deps.storage)?;
+    let mut stakers = Vec::with_capacity(count as usize);
+    for index in 0..count {
+        let staker: HumanAddr = ns_load(&deps.storage, STAKERS, &index.to_be_bytes())?
+            .ok_or_else(|| StdError::generic_err("Staker doesn't exist in storage."))?;
+        stakers.push(staker);
+    }
+    Ok(stakers)
 }
 
-pub fn load_claim_reward_timestamp<S: Storage, A: Api, Q: Querier>(
+fn load_stakers_count(storage: &impl Storage) -> StdResult<u64> {
+    Ok(load(storage, STAKERS_COUNT)?.unwrap_or(0))
+}
+
+pub fn load_reward_state<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>
-) -> StdResult<Uint128> {
-   let timestamp = load(&deps.storage, LAST_REWARD_TIME_CLAIMED)?.unwrap_or(Uint128(0u128));    
-   Ok(timestamp)
+) -> StdResult<RewardState> {
+    let state = load(&deps.storage, REWARD_STATE)?.unwrap_or(RewardState {
+        total_staked: Uint128(0u128),
+        total_weight: Uint128(0u128),
+        last_updated: Uint128(0u128),
+        streams: vec![],
+    });
+    Ok(state)
 }
 
-pub fn store_claim_reward_timestamp<S: Storage, A: Api, Q: Querier>(
+pub fn store_reward_state<S: Storage, A: Api, Q: Querier>(
     deps:   &mut Extern<S, A, Q>,
-    timestamp: Uint128
+    state: &RewardState
 ) -> StdResult<()> {
-    save(&mut deps.storage, LAST_REWARD_TIME_CLAIMED, &timestamp)
+    save(&mut deps.storage, REWARD_STATE, state)
+}
+
+pub fn load_lock_tiers(storage: &impl Storage) -> StdResult<Option<Vec<LockTier>>> {
+    let tiers = load(storage, LOCK_TIERS)?.unwrap_or(None);
+    Ok(tiers)
+}
+
+pub fn store_lock_tiers(storage: &mut impl Storage, tiers: &Option<Vec<LockTier>>) -> StdResult<()> {
+    save(storage, LOCK_TIERS, tiers)
 }
 
 pub fn store_staker<S: Storage, A: Api, Q: Querier>(
     deps:   &mut Extern<S, A, Q>,
     staker: HumanAddr
 ) -> StdResult<()> {
-    let mut unwrap_data = load_stakers(deps)?;
-    unwrap_data.push(staker); 
-    save(&mut deps.storage, LIST_STAKERS, &unwrap_data)
+    let count = load_stakers_count(&deps.storage)?;
+    ns_save(&mut deps.storage, STAKERS, &count.to_be_bytes(), &staker)?;
+    ns_save(&mut deps.storage, STAKER_INDEX, staker.as_str().as_bytes(), &count)?;
+    save(&mut deps.storage, STAKERS_COUNT, &(count + 1))
 }
 
 pub fn get_total_staking_amount<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>
 ) -> StdResult<Uint128> {
-    let stakers = load_stakers(&deps)?;
-    let mut amount = Uint128(0u128);
-    for staker in stakers.into_iter(){
-        let stake_info = load_staker_info(deps, staker)?;
-        amount += stake_info.amount;
-    }
-    Ok(amount)
+    Ok(load_reward_state(deps)?.total_staked)
 }
 
-    
+/// Moves the last staker into the slot of the removed one.
 pub fn remove_staker<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>, 
     staker: HumanAddr
 ) -> StdResult<()> {
-    let mut addresses = load_stakers(deps)?;
-    addresses.retain(|x| x != &staker);
-    save(&mut deps.storage, LIST_STAKERS, &addresses)
+    let index: u64 = ns_load(&deps.storage, STAKER_INDEX, staker.as_str().as_bytes())?
+        .ok_or_else(|| StdError::generic_err("Staker doesn't exist in storage."))?;
+    let last = load_stakers_count(&deps.storage)? - 1;
+    if index != last {
+        let moved: HumanAddr = ns_load(&deps.storage, STAKERS, &last.to_be_bytes())?
+            .ok_or_else(|| StdError::generic_err("Staker doesn't exist in storage."))?;
+        ns_save(&mut deps.storage, STAKERS, &index.to_be_bytes(), &moved)?;
+        ns_save(&mut deps.storage, STAKER_INDEX, moved.as_str().as_bytes(), &index)?;
+    }
+    ns_remove(&mut deps.storage, STAKERS, &last.to_be_bytes());
+    ns_remove(&mut deps.storage, STAKER_INDEX, staker.as_str().as_bytes());
+    save(&mut deps.storage, STAKERS_COUNT, &last)
 }
 
 
@@ -118,12 +198,8 @@ pub fn is_address_already_staker<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
     address: HumanAddr
 ) -> StdResult<bool>{
-    let addrs = load_stakers(&deps)?;
-    if addrs.contains(&address) {
-       return Ok(true)
-    } else {
-       return Ok(false)
-    }      
+    let index: Option<u64> = ns_load(&deps.storage, STAKER_INDEX, address.as_str().as_bytes())?;
+    Ok(index.is_some())
 }
 
 pub fn load_staker_info<S: Storage, A: Api, Q: Querier>(
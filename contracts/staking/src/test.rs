@@ -35,12 +35,13 @@ use shadeswap_shared::fadroma::BalanceResponse;
 #[cfg(test)]
 pub mod tests {
     use super::*;
-    use shadeswap_shared::msg::staking::{{InitMsg,QueryMsg,QueryResponse,  HandleMsg}};
-    use crate::state::{{Config , store_config, load_stakers, get_total_staking_amount, load_claim_reward_timestamp,
+    use shadeswap_shared::msg::staking::{{InitMsg,QueryMsg,QueryResponse,  HandleMsg, InvokeMsg, LockTier}};
+    use crate::state::{{Config , store_config, load_stakers, get_total_staking_amount, load_reward_state,
         load_config, is_address_already_staker, load_claim_reward_info,
         load_staker_info}};    
-    use crate::contract::{{init, get_current_timestamp,claim_rewards_for_all_stakers, query, handle, get_staking_percentage}};
+    use crate::contract::{{init, query, handle, get_staking_percentage}};
     use shadeswap_shared::msg::factory::{QueryResponse as FactoryQueryResponse,QueryMsg as FactoryQueryMsg };
+    use shadeswap_shared::msg::amm_pair::QueryMsgResponse as AmmPairQueryMsgResponse;
    
     use shadeswap_shared::token_type::TokenType;
     use serde::Deserialize;
@@ -51,14 +52,18 @@ pub mod tests {
     pub const LP_TOKEN: &str = "LP_TOKEN";
     pub const REWARD_TOKEN: &str = "REWARD_TOKEN";
     pub const STAKING_CONTRACT_ADDRESS: &str = "STAKING_CONTRACT_ADDRESS";
+    pub const PAIR_CONTRACT: &str = "PAIR_CONTRACT";
+    pub const PAIR_ADMIN: &str = "PAIR_ADMIN";
     
     #[test]
     fn assert_init_config() -> StdResult<()> {   
         let mut deps = mock_deps();  
         let env = mock_env(CONTRACT_ADDRESS,1571797523, 1524,CONTRACT_ADDRESS, &[]);
         let config: Config = make_init_config(&mut deps, env, Uint128(100u128))?;        
-        assert_eq!(config.daily_reward_amount, Uint128(100u128));
-        assert_eq!(config.reward_token, TokenType::CustomToken{
+        assert_eq!(config.pair_contract.address, HumanAddr::from(PAIR_CONTRACT));
+        let stream = load_reward_state(&deps)?.streams[0].clone();
+        assert_eq!(stream.daily_reward_amount, Uint128(100u128));
+        assert_eq!(stream.token, TokenType::CustomToken{
             contract_addr: HumanAddr::from(CONTRACT_ADDRESS),
             token_code_hash: CONTRACT_ADDRESS.to_string(),
         });
@@ -72,28 +77,12 @@ pub mod tests {
         let env = mock_env(CONTRACT_ADDRESS,1571797523, 1524,CONTRACT_ADDRESS, &[]);
         let staker = env.message.sender.clone();     
         let config: Config = make_init_config(&mut deps, env.clone(), Uint128(100u128))?;     
-        let result = handle(
-            &mut deps,
-            env.clone(),
-            HandleMsg::Stake{
-                amount: Uint128(100u128),
-                from: staker.clone()
-            },
-        )
-        .unwrap();
+        let result = stake_lp(&mut deps, &env, staker.clone(), Uint128(100u128)).unwrap();
         let is_user_staker = is_address_already_staker(&deps, staker.clone())?;
         let stake_info = load_staker_info(&deps, staker.clone())?;
         assert_eq!(is_user_staker, true);
         assert_eq!(stake_info.amount, Uint128(100u128));
-        let result = handle(
-            &mut deps,
-            env.clone(),
-            HandleMsg::Stake{
-                amount: Uint128(100u128),
-                from: staker.clone()
-            },
-        )
-        .unwrap();
+        let result = stake_lp(&mut deps, &env, staker.clone(), Uint128(100u128)).unwrap();
         let total_amount = get_total_staking_amount(&mut deps)?;
         assert_eq!(total_amount, Uint128(200u128));
         Ok(())
@@ -105,26 +94,44 @@ pub mod tests {
         let env = mock_env(CONTRACT_ADDRESS, 1571797523, 1524,CONTRACT_ADDRESS, &[]);
         let staker = env.message.sender.clone();     
         let config: Config = make_init_config(&mut deps, env.clone(), Uint128(100u128))?;     
-        let result = handle(
-            &mut deps,
-            env.clone(),
-            HandleMsg::Stake{
-                amount: Uint128(100u128),
-                from: staker.clone()
-            },
-        )
-        .unwrap();            
+        let result = stake_lp(&mut deps, &env, staker.clone(), Uint128(100u128)).unwrap();            
         let stake_info = load_staker_info(&deps, staker.clone())?;     
         assert_eq!(stake_info.amount, Uint128(100u128));
         let result = handle(
             &mut deps,
             env.clone(),
-            HandleMsg::Unstake {address: staker.clone()},
+            HandleMsg::Unstake {amount: Uint128(40u128)},
         )
         .unwrap();
+        // the unstaked LP tokens go back to the staker
+        assert_eq!(result.messages, vec![snip20::transfer_msg(
+            staker.clone(), Uint128(40u128), None, 256, "".to_string(), HumanAddr::from(LP_TOKEN),
+        )?]);
+        let stake_info = load_staker_info(&deps, staker.clone())?;    
+        assert_eq!(stake_info.amount, Uint128(60u128));
+        assert_eq!(is_address_already_staker(&deps, staker.clone())?, true);
+        assert!(handle(&mut deps, env.clone(), HandleMsg::Unstake {amount: Uint128(61u128)}).is_err());
+        handle(&mut deps, env.clone(), HandleMsg::Unstake {amount: Uint128(60u128)})?;
         let stake_info = load_staker_info(&deps, staker.clone())?;    
-        let claim_reward_inf = load_claim_reward_info(&deps, staker.clone()) ?;
         assert_eq!(stake_info.amount, Uint128(0u128));
+        assert_eq!(is_address_already_staker(&deps, staker.clone())?, false);
+        assert_eq!(get_total_staking_amount(&deps)?, Uint128(0u128));
+        Ok(())
+    }
+
+    #[test]
+    fn assert_stake_only_accepts_lp_token() -> StdResult<()>{
+        let mut deps = mock_deps();  
+        let env = mock_env(CONTRACT_ADDRESS, 1571797523, 1524, CONTRACT_ADDRESS, &[]);
+        make_init_config(&mut deps, env.clone(), Uint128(100u128))?;
+        let env_token = mock_env(REWARD_TOKEN, 1571797523, 1524, CONTRACT_ADDRESS, &[]);
+        let result = handle(&mut deps, env_token, HandleMsg::Receive {
+            from: HumanAddr::from("STAKERA"),
+            amount: Uint128(100u128),
+            msg: Some(to_binary(&InvokeMsg::Stake{})?),
+        });
+        assert!(result.is_err());
+        assert_eq!(get_total_staking_amount(&deps)?, Uint128(0u128));
         Ok(())
     }
 
@@ -134,24 +141,8 @@ pub mod tests {
         let env_a = mock_env(CONTRACT_ADDRESS, 1571797523, 1524,STAKING_CONTRACT_ADDRESS, &[]);
         let env_b = mock_env(STAKING_CONTRACT_ADDRESS, 1571797533, 1570, STAKING_CONTRACT_ADDRESS, &[]);      
         let config: Config = make_init_config(&mut deps, env_a.clone(), Uint128(100u128))?;     
-        let result = handle(
-            &mut deps,
-            env_a.clone(),         
-            HandleMsg::Stake{
-                amount: Uint128(100u128),
-                from: env_a.message.sender.clone()
-            },
-        )
-        .unwrap();     
-        let result = handle(
-            &mut deps,
-            env_b.clone(),        
-            HandleMsg::Stake{
-                amount: Uint128(100u128),
-                from: env_b.message.sender.clone()
-            },
-        )
-        .unwrap();         
+        let result = stake_lp(&mut deps, &env_a, env_a.message.sender.clone(), Uint128(100u128)).unwrap();     
+        let result = stake_lp(&mut deps, &env_b, env_b.message.sender.clone(), Uint128(100u128)).unwrap();         
       
         let test = query(&deps, QueryMsg::GetStakers{})?;
         match from_binary(&test)? {
@@ -160,12 +151,13 @@ pub mod tests {
             } => {
                 assert_eq!(stakers.len(), 2);
             },
-            QueryResponse::ClaimReward{amount} => {
-                assert_eq!(amount, Uint128(0));
+            QueryResponse::ClaimReward{rewards} => {
+                assert_eq!(rewards.len(), 1);
             },
             QueryResponse::ContractOwner{address} =>{
                 assert_eq!(env_b.message.sender.clone(),address)
             }
+            _ => panic!("QueryResponse::Stakers"),
         };    
         Ok(())
     }
@@ -176,37 +168,255 @@ pub mod tests {
         let staker_a = HumanAddr("STAKERA".to_string());
         let staker_b = HumanAddr("STAKERB".to_string());  
         let mut deps = mock_deps();  
-        let current_timestamp = get_current_timestamp()?;
-        let env_a = mock_env(CONTRACT_ADDRESS, current_timestamp.u128() as u64, 1524, CONTRACT_ADDRESS,  &[]);
+        let timestamp = 1571797523u64;
+        let env_a = mock_env(CONTRACT_ADDRESS, timestamp, 1524, CONTRACT_ADDRESS,  &[]);
         let config: Config = make_init_config(&mut deps, env_a.clone(), Uint128(1000000000000u128))?;                       
-        let result = handle(
-            &mut deps,
-            env_a.clone(),          
-            HandleMsg::Stake{
-                amount: Uint128(150u128),
-                from: staker_a.clone()
-            },
-            
-        )
-        .unwrap();
+        fund_rewards(&mut deps, CONTRACT_ADDRESS, timestamp, Uint128(10000000000000u128), 864000)?;
+        let result = stake_lp(&mut deps, &env_a, staker_a.clone(), Uint128(150u128)).unwrap();
         let is_user_staker = is_address_already_staker(&deps, staker_a.clone())?;        
         assert_eq!(is_user_staker, true);
-        let env_b = mock_env(STAKING_CONTRACT_ADDRESS, (current_timestamp + Uint128(100u128)).u128() as u64, 1524, CONTRACT_ADDRESS, &[]);
-        let result = handle(
-            &mut deps,
-            env_b.clone(),        
-            HandleMsg::Stake{
-                amount: Uint128(50u128),
-                from: staker_b.clone()
+        // staker a earns everything until staker b joins
+        let env_b = mock_env(CONTRACT_ADDRESS, timestamp + 100, 1524, CONTRACT_ADDRESS, &[]);
+        let result = stake_lp(&mut deps, &env_b, staker_b.clone(), Uint128(50u128)).unwrap();            
+        assert_eq!(load_claim_reward_info(&deps, staker_a.clone())?.amounts, vec![Uint128(0)]);
+        let reward_state = load_reward_state(&deps)?;
+        assert_eq!(reward_state.total_staked, Uint128(200u128));
+        assert_eq!(reward_state.streams[0].reward_per_share, Uint256::from(7716049380000000000u128));
+
+        let current_time = timestamp as u128 + 1000;
+        match from_binary(&query(&deps, QueryMsg::GetClaimReward{ time: current_time, staker: staker_b.clone() })?)? {
+            QueryResponse::ClaimReward { rewards } => assert_eq!(rewards[0].amount, Uint128(2604166666u128)),
+            _ => panic!("QueryResponse::ClaimReward"),
+        }
+        let env_claim = mock_env(staker_a.clone(), current_time as u64, 1534, CONTRACT_ADDRESS, &[]);
+        let result = handle(&mut deps, env_claim, HandleMsg::ClaimRewards{})?;
+        assert_eq!(result.log[2].value, "8969907406".to_string());
+        assert_eq!(load_claim_reward_info(&deps,staker_a.clone())?.amounts, vec![Uint128(0)]);
+        // the rewards of staker b stay untouched until it is settled
+        assert_eq!(load_claim_reward_info(&deps,staker_b.clone())?.amounts, vec![Uint128(0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn assert_reward_per_share_of_tiny_stake_does_not_overflow() -> StdResult<()>{
+        let staker_a = HumanAddr("STAKERA".to_string());
+        let mut deps = mock_deps();  
+        let timestamp = 1571797523u64;
+        let env = mock_env(CONTRACT_ADDRESS, timestamp, 1524, CONTRACT_ADDRESS, &[]);
+        make_init_config(&mut deps, env.clone(), Uint128(0u128))?;
+        // a billion tokens with 18 decimals a day for a single staked unit
+        let daily_reward = Uint128(1_000_000_000_000_000_000_000_000_000u128);
+        fund_rewards(&mut deps, CONTRACT_ADDRESS, timestamp, daily_reward, 86400)?;
+        stake_lp(&mut deps, &env, staker_a.clone(), Uint128(1u128))?;
+
+        let env_claim = mock_env(staker_a.clone(), timestamp + 86400, 1534, CONTRACT_ADDRESS, &[]);
+        let result = handle(&mut deps, env_claim, HandleMsg::ClaimRewards{})?;
+        assert_eq!(result.log[2].value, daily_reward.to_string());
+        assert!(load_reward_state(&deps)?.streams[0].reward_per_share > Uint256::from(u128::MAX));
+        Ok(())
+    }
+
+    #[test]
+    fn assert_claim_rewards_pays_every_stream() -> StdResult<()>{
+        let staker_a = HumanAddr("STAKERA".to_string());
+        let mut deps = mock_deps();  
+        let timestamp = 1571797523u64;
+        let env = mock_env(CONTRACT_ADDRESS, timestamp, 1524, CONTRACT_ADDRESS, &[]);
+        make_init_config(&mut deps, env.clone(), Uint128(1000000000000u128))?;
+        fund_rewards(&mut deps, CONTRACT_ADDRESS, timestamp, Uint128(10000000000000u128), 864000)?;
+        stake_lp(&mut deps, &env, staker_a.clone(), Uint128(100u128))?;
+        let add_stream = || HandleMsg::AddRewardStream {
+            token: TokenType::CustomToken{
+                contract_addr: HumanAddr::from(REWARD_TOKEN),
+                token_code_hash: REWARD_TOKEN.to_string(),
             },
-        )
-        .unwrap();            
-        let current_time = current_timestamp + Uint128(1000u128);              
-        claim_rewards_for_all_stakers(&mut deps, current_time)?;
-        let claim_reward_info_a = load_claim_reward_info(&deps,staker_a.clone())?;
-        assert_eq!(claim_reward_info_a.amount, Uint128(8));      
-        let claim_reward_info_b = load_claim_reward_info(&deps,staker_b.clone())?;
-        assert_eq!(claim_reward_info_b.amount, Uint128(2));       
+            daily_reward_amount: Uint128(86400u128),
+            start: Some(timestamp + 100),
+            end: Some(timestamp + 200),
+        };
+        let env_other = mock_env(staker_a.clone(), timestamp, 1524, CONTRACT_ADDRESS, &[]);
+        match handle(&mut deps, env_other, add_stream()) {
+            Err(StdError::Unauthorized { .. }) => {},
+            _ => panic!("only the pair admin can add reward streams"),
+        }
+        let env_admin = mock_env(PAIR_ADMIN, timestamp, 1524, CONTRACT_ADDRESS, &[]);
+        handle(&mut deps, env_admin, add_stream())?;
+        assert_eq!(load_reward_state(&deps)?.streams.len(), 2);
+        fund_rewards(&mut deps, REWARD_TOKEN, timestamp, Uint128(100u128), 100)?;
+
+        // the second stream only pays between its start and its end
+        match from_binary(&query(&deps, QueryMsg::GetClaimReward{ time: timestamp as u128 + 150, staker: staker_a.clone() })?)? {
+            QueryResponse::ClaimReward { rewards } => {
+                assert_eq!(rewards.len(), 2);
+                assert_eq!(rewards[0].amount, Uint128(1736111111u128));
+                assert_eq!(rewards[1].amount, Uint128(50u128));
+            },
+            _ => panic!("QueryResponse::ClaimReward"),
+        }
+        let env_claim = mock_env(staker_a.clone(), timestamp + 1000, 1534, CONTRACT_ADDRESS, &[]);
+        let result = handle(&mut deps, env_claim, HandleMsg::ClaimRewards{})?;
+        assert_eq!(result.messages.len(), 2);
+        assert_eq!(result.log[2].value, "11574074074, 100".to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn assert_native_token_reward_stream_is_rejected() -> StdResult<()>{
+        let mut deps = mock_deps();  
+        let timestamp = 1571797523u64;
+        let env = mock_env(CONTRACT_ADDRESS, timestamp, 1524, CONTRACT_ADDRESS, &[]);
+        make_init_config(&mut deps, env.clone(), Uint128(1000000000000u128))?;
+        let env_admin = mock_env(PAIR_ADMIN, timestamp, 1524, CONTRACT_ADDRESS, &[]);
+        let result = handle(&mut deps, env_admin, HandleMsg::AddRewardStream {
+            token: TokenType::NativeToken{ denom: "uscrt".to_string() },
+            daily_reward_amount: Uint128(86400u128),
+            start: None,
+            end: None,
+        });
+        match result {
+            Err(StdError::GenericErr { msg, .. }) => {
+                assert_eq!(msg, "Reward streams have to pay out a SNIP20 token.".to_string())
+            },
+            _ => panic!("a native token stream can't be funded and must be rejected"),
+        }
+        // no slot of the streams is used up
+        assert_eq!(load_reward_state(&deps)?.streams.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn assert_funded_reward_period_stops_at_its_end() -> StdResult<()>{
+        let staker_a = HumanAddr("STAKERA".to_string());
+        let mut deps = mock_deps();  
+        let timestamp = 1571797523u64;
+        let env = mock_env(CONTRACT_ADDRESS, timestamp, 1524, CONTRACT_ADDRESS, &[]);
+        make_init_config(&mut deps, env.clone(), Uint128(1000000000000u128))?;
+        stake_lp(&mut deps, &env, staker_a.clone(), Uint128(100u128))?;
+        // nothing is paid out before the stream is funded
+        match from_binary(&query(&deps, QueryMsg::GetClaimReward{ time: timestamp as u128 + 100, staker: staker_a.clone() })?)? {
+            QueryResponse::ClaimReward { rewards } => assert_eq!(rewards[0].amount, Uint128(0u128)),
+            _ => panic!("QueryResponse::ClaimReward"),
+        }
+        // the reward token of the init stream is CONTRACT_ADDRESS
+        let fund = |from: &str| HandleMsg::Receive {
+            from: HumanAddr::from(from),
+            amount: Uint128(1000u128),
+            msg: Some(to_binary(&InvokeMsg::FundRewardPeriod{ duration: 1000 }).unwrap()),
+        };
+        let env_fund = mock_env(CONTRACT_ADDRESS, timestamp + 100, 1524, CONTRACT_ADDRESS, &[]);
+        match handle(&mut deps, env_fund.clone(), fund("STAKERA")) {
+            Err(StdError::Unauthorized { .. }) => {},
+            _ => panic!("only the pair admin can fund reward periods"),
+        }
+        handle(&mut deps, env_fund, fund(PAIR_ADMIN))?;
+
+        match from_binary(&query(&deps, QueryMsg::GetRewardPeriods{ time: timestamp as u128 + 600 })?)? {
+            QueryResponse::RewardPeriods { periods } => {
+                assert_eq!(periods[0].daily_reward_amount, Uint128(86400u128));
+                assert_eq!(periods[0].remaining_budget, Uint128(500u128));
+                assert_eq!(periods[0].end, Some(timestamp + 1100));
+            },
+            _ => panic!("QueryResponse::RewardPeriods"),
+        }
+        // nothing is paid out after the period ends
+        let env_claim = mock_env(staker_a.clone(), timestamp + 2000, 1534, CONTRACT_ADDRESS, &[]);
+        let result = handle(&mut deps, env_claim, HandleMsg::ClaimRewards{})?;
+        assert_eq!(result.log[2].value, "1000".to_string());
+        assert_eq!(load_reward_state(&deps)?.streams[0].remaining, Uint128(0u128));
+        Ok(())
+    }
+
+    #[test]
+    fn assert_locked_stake_earns_boost_until_unstake() -> StdResult<()>{
+        let staker_a = HumanAddr("STAKERA".to_string());
+        let staker_b = HumanAddr("STAKERB".to_string());
+        let mut deps = mock_deps();  
+        let timestamp = 1571797523u64;
+        let env = mock_env(CONTRACT_ADDRESS, timestamp, 1524, CONTRACT_ADDRESS, &[]);
+        make_init_config(&mut deps, env.clone(), Uint128(86400u128))?;
+        fund_rewards(&mut deps, CONTRACT_ADDRESS, timestamp, Uint128(864000u128), 864000)?;
+        stake_lp(&mut deps, &env, staker_a.clone(), Uint128(100u128))?;
+        stake_lp(&mut deps, &env, staker_b.clone(), Uint128(100u128))?;
+        let env_admin = mock_env(PAIR_ADMIN, timestamp, 1524, CONTRACT_ADDRESS, &[]);
+        handle(&mut deps, env_admin, HandleMsg::SetLockTiers{ tiers: Some(vec![
+            LockTier{ duration: 1000, multiplier: 20000 },
+            LockTier{ duration: 5000, multiplier: 30000 },
+        ])})?;
+
+        let env_a = mock_env(staker_a.clone(), timestamp, 1524, CONTRACT_ADDRESS, &[]);
+        handle(&mut deps, env_a, HandleMsg::Lock{ duration: 1000 })?;
+        match from_binary(&query(&deps, QueryMsg::GetStakerLock{ staker: staker_a.clone() })?)? {
+            QueryResponse::StakerLock { amount, weight, multiplier, lock_end } => {
+                assert_eq!(amount, Uint128(100u128));
+                assert_eq!(weight, Uint128(200u128));
+                assert_eq!(multiplier, 20000);
+                assert_eq!(lock_end, Some(timestamp + 1000));
+            },
+            _ => panic!("QueryResponse::StakerLock"),
+        }
+        assert_eq!(get_staking_percentage(&deps, staker_a.clone(), Uint128(300u128))?, Uint128(200u128));
+
+        // a running lock can't be shortened or unstaked
+        let env_a = mock_env(staker_a.clone(), timestamp + 300, 1524, CONTRACT_ADDRESS, &[]);
+        assert!(handle(&mut deps, env_a.clone(), HandleMsg::Lock{ duration: 100 }).is_err());
+        assert!(handle(&mut deps, env_a.clone(), HandleMsg::Unstake{ amount: Uint128(100u128) }).is_err());
+        let result = handle(&mut deps, env_a, HandleMsg::ClaimRewards{})?;
+        assert_eq!(result.log[2].value, "200".to_string());
+
+        let env_unstake = mock_env(staker_a.clone(), timestamp + 1000, 1524, CONTRACT_ADDRESS, &[]);
+        handle(&mut deps, env_unstake, HandleMsg::Unstake{ amount: Uint128(100u128) })?;
+        assert_eq!(load_reward_state(&deps)?.total_weight, Uint128(100u128));
+        Ok(())
+    }
+
+    #[test]
+    fn assert_unstake_keeps_other_stakers_listed() -> StdResult<()>{
+        let mut deps = mock_deps();  
+        let env = mock_env(CONTRACT_ADDRESS, 1571797523, 1524, CONTRACT_ADDRESS, &[]);
+        make_init_config(&mut deps, env.clone(), Uint128(100u128))?;
+        let stakers: Vec<HumanAddr> = vec!["STAKERA", "STAKERB", "STAKERC"].into_iter().map(HumanAddr::from).collect();
+        for staker in stakers.iter() {
+            stake_lp(&mut deps, &env, staker.clone(), Uint128(100u128))?;
+        }
+        let env_unstake = mock_env(stakers[0].clone(), 1571797523, 1524, CONTRACT_ADDRESS, &[]);
+        handle(&mut deps, env_unstake, HandleMsg::Unstake{ amount: Uint128(100u128) })?;
+        assert_eq!(load_stakers(&deps)?, vec![stakers[2].clone(), stakers[1].clone()]);
+        assert_eq!(is_address_already_staker(&deps, stakers[0].clone())?, false);
+        assert_eq!(get_total_staking_amount(&deps)?, Uint128(200u128));
+        Ok(())
+    }
+
+    #[test]
+    fn assert_unstake_returns_lp_without_paying_rewards() -> StdResult<()>{
+        let staker_a = HumanAddr("STAKERA".to_string());
+        let mut deps = mock_deps();  
+        let timestamp = 1571797523u64;
+        let env = mock_env(CONTRACT_ADDRESS, timestamp, 1524, CONTRACT_ADDRESS, &[]);
+        make_init_config(&mut deps, env.clone(), Uint128(86400u128))?;
+        fund_rewards(&mut deps, CONTRACT_ADDRESS, timestamp, Uint128(864000u128), 864000)?;
+        stake_lp(&mut deps, &env, staker_a.clone(), Uint128(100u128))?;
+
+        // unstaking never depends on the reward balance of the contract, only the LP tokens are sent
+        let env_unstake = mock_env(staker_a.clone(), timestamp + 1000, 1524, CONTRACT_ADDRESS, &[]);
+        let result = handle(&mut deps, env_unstake, HandleMsg::Unstake{ amount: Uint128(100u128) })?;
+        assert_eq!(result.messages, vec![snip20::transfer_msg(
+            staker_a.clone(), Uint128(100u128), None, 256, "".to_string(), HumanAddr::from(LP_TOKEN),
+        )?]);
+        assert_eq!(load_claim_reward_info(&deps, staker_a.clone())?.amounts, vec![Uint128(1000u128)]);
+
+        // staking again keeps the unclaimed rewards
+        let env_restake = mock_env(CONTRACT_ADDRESS, timestamp + 1500, 1524, CONTRACT_ADDRESS, &[]);
+        stake_lp(&mut deps, &env_restake, staker_a.clone(), Uint128(100u128))?;
+        let env_unstake = mock_env(staker_a.clone(), timestamp + 1500, 1524, CONTRACT_ADDRESS, &[]);
+        handle(&mut deps, env_unstake, HandleMsg::Unstake{ amount: Uint128(100u128) })?;
+        assert_eq!(load_claim_reward_info(&deps, staker_a.clone())?.amounts, vec![Uint128(1000u128)]);
+
+        // the former staker claims them later
+        let env_claim = mock_env(staker_a.clone(), timestamp + 2000, 1524, CONTRACT_ADDRESS, &[]);
+        let result = handle(&mut deps, env_claim, HandleMsg::ClaimRewards{})?;
+        assert_eq!(result.log[2].value, "1000".to_string());
+        assert_eq!(result.messages.len(), 1);
+        assert_eq!(load_claim_reward_info(&deps, staker_a.clone())?.amounts, vec![Uint128(0u128)]);
         Ok(())
     }
 
@@ -219,27 +429,11 @@ pub mod tests {
         let config: Config = make_init_config(&mut deps, env_a.clone(), Uint128(100u128))?;   
         let staker_a = HumanAddr("STAKERA".to_string());
         let staker_b = HumanAddr("STAKERB".to_string());       
-        let result = handle(
-            &mut deps,
-            env_a.clone(),        
-            HandleMsg::Stake{
-                amount: Uint128(150u128),
-                from: staker_a.clone()
-            },
-        )
-        .unwrap();
+        let result = stake_lp(&mut deps, &env_a, staker_a.clone(), Uint128(150u128)).unwrap();
         let is_user_staker = is_address_already_staker(&deps, staker_a.clone())?;
         let stake_info = load_staker_info(&deps, staker_a.clone())?;
         assert_eq!(is_user_staker, true);
-        let result = handle(
-            &mut deps,
-            env_b.clone(),      
-            HandleMsg::Stake{
-                amount: Uint128(50u128),
-                from: staker_b.clone()
-            },
-        )
-        .unwrap();
+        let result = stake_lp(&mut deps, &env_b, staker_b.clone(), Uint128(50u128)).unwrap();
         let staking_percentage_a = get_staking_percentage(&mut deps, staker_a.clone(), Uint128(100u128))?;
         println!("{}", Uint256::from(staking_percentage_a));
         assert_eq!(staking_percentage_a, Uint128(75u128));
@@ -261,15 +455,51 @@ pub mod tests {
                 token_code_hash: CONTRACT_ADDRESS.to_string(),
             },           
             contract: ContractLink {
-                address: HumanAddr::from(CONTRACT_ADDRESS),
+                address: HumanAddr::from(PAIR_CONTRACT),
                 code_hash: "".to_string().clone(),
             }           
         };         
         assert!(init(deps, env.clone(), msg).is_ok());
+        // the init sender stands in for the pair
+        handle(deps, env, HandleMsg::SetLPToken {
+            lp_token: ContractLink {
+                address: HumanAddr::from(LP_TOKEN),
+                code_hash: "".to_string(),
+            }
+        })?;
         let config = load_config(deps)?;
         Ok(config)
     }
 
+    fn stake_lp<S: Storage, A: Api, Q: Querier>(
+        deps: &mut Extern<S, A, Q>,
+        env: &Env,
+        staker: HumanAddr,
+        amount: Uint128) -> StdResult<HandleResponse> {
+        // LP tokens are staked by sending them to the contract
+        let env = mock_env(LP_TOKEN, env.block.time, env.block.height, CONTRACT_ADDRESS, &[]);
+        handle(deps, env, HandleMsg::Receive {
+            from: staker,
+            amount,
+            msg: Some(to_binary(&InvokeMsg::Stake{})?),
+        })
+    }
+
+    fn fund_rewards<S: Storage, A: Api, Q: Querier>(
+        deps: &mut Extern<S, A, Q>,
+        token: &str,
+        time: u64,
+        amount: Uint128,
+        duration: u64) -> StdResult<HandleResponse> {
+        // reward periods are funded by the pair admin sending the reward token
+        let env = mock_env(token, time, 1524, CONTRACT_ADDRESS, &[]);
+        handle(deps, env, HandleMsg::Receive {
+            from: HumanAddr::from(PAIR_ADMIN),
+            amount,
+            msg: Some(to_binary(&InvokeMsg::FundRewardPeriod{ duration })?),
+        })
+    }
+
     pub fn mock_env<U: Into<HumanAddr>>(sender: U, time: u64, height: u64, contract_address: &str, sent: &[Coin]) -> Env {
         Env {
             block: BlockInfo {
@@ -326,6 +556,11 @@ pub mod tests {
                                         }
                                     }))
                                 },
+                                PAIR_CONTRACT => {
+                                    QuerierResult::Ok(to_binary(&AmmPairQueryMsgResponse::GetAdminAddress {
+                                        address: HumanAddr::from(PAIR_ADMIN),
+                                    }))
+                                },
                                 REWARD_TOKEN => {
                                     QuerierResult::Ok(to_binary(&IntBalanceResponse {
                                         balance: Balance {
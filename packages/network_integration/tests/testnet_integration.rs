@@ -10,7 +10,7 @@ use secretcli::{
 };
 use serde_json::Result;
 use shadeswap_shared::{
-    amm_pair::{AMMPair, AMMSettings, Fee},
+    amm_pair::{AMMPair, AMMSettings, Fee, ShadeDaoFeeMode},
     fadroma::{
         scrt::{
             from_binary, log, secret_toolkit::snip20, to_binary, Api, BankMsg, Binary, Coin,
@@ -251,6 +251,7 @@ fn run_testnet() -> Result<()> {
                 address: HumanAddr(String::from(shade_dao.to_string())),
                 code_hash: s_sSHD.code_hash.clone(),
             },
+            shade_dao_fee_mode: ShadeDaoFeeMode::Transfer,
         },
         lp_token_contract: ContractInstantiationInfo {
             code_hash: s_lp.code_hash.clone(),
@@ -301,7 +302,9 @@ fn run_testnet() -> Result<()> {
                         contract_addr: s_sREWARDSNIP20.address.clone().into(),
                         token_code_hash: s_sREWARDSNIP20.code_hash.to_string(),
                     },
-                })
+                }),
+                pool_type: None,
+                oracle: None,
             },
             &factory_contract,
             ACCOUNT_KEY,
@@ -332,6 +335,8 @@ fn run_testnet() -> Result<()> {
                 pair: test_native_pair.clone(),
                 entropy: to_binary(&"".to_string()).unwrap(),
                 staking_contract: None,
+                pool_type: None,
+                oracle: None,
                 // staking_contract: Some(StakingContractInit {
                 //     contract_info: ContractInstantiationInfo{
                 //         code_hash: staking_contract.code_hash.to_string(),
@@ -578,6 +583,7 @@ fn run_testnet() -> Result<()> {
                             expected_return: Some(Uint128(10)),
                             paths: vec![ammPair.address.clone()],
                             recipient: Some(HumanAddr::from(account.to_string())),
+                            deadline: None,
                         })
                         .unwrap(),
                     ),
@@ -629,6 +635,7 @@ fn run_testnet() -> Result<()> {
                             expected_return: Some(Uint128(10)),
                             paths: vec![ammPair.address.clone()],
                             recipient: Some(HumanAddr::from(account.to_string())),
+                            deadline: None,
                         })
                         .unwrap(),
                     ),
@@ -685,6 +692,7 @@ fn run_testnet() -> Result<()> {
                     expected_return: None,
                     path: vec![amm_pair_2.address.clone()],
                     recipient: None,
+                    deadline: None,
                 },
                 &router_contract,
                 ACCOUNT_KEY,
@@ -716,6 +724,7 @@ fn run_testnet() -> Result<()> {
                     expected_return: None,
                     path: vec![amm_pair_2.address.clone(), ammPair.address.clone()],
                     recipient: None,
+                    deadline: None,
                 },
                 &router_contract,
                 ACCOUNT_KEY,
@@ -749,6 +758,7 @@ fn run_testnet() -> Result<()> {
                             expected_return: Some(Uint128(10)),
                             paths: vec![ammPair.address.clone(), amm_pair_2.address.clone()],
                             recipient: Some(HumanAddr::from(account.to_string())),
+                            deadline: None,
                         })
                         .unwrap(),
                     ),
@@ -843,7 +853,8 @@ fn run_test_deploy() -> Result<()> {
             shade_dao_address: ContractLink {
                 address: HumanAddr(String::from(shade_dao.to_string())),
                 code_hash: "asd".to_string(),
-            }
+            },
+            shade_dao_fee_mode: ShadeDaoFeeMode::Transfer,
         },
         lp_token_contract: ContractInstantiationInfo {
             code_hash: s_lp.code_hash.clone(),
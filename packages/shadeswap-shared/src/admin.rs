@@ -15,8 +15,22 @@ use crate::fadroma::{
 use composable_snip20::msg::{
     InitConfig as Snip20ComposableConfig, InitMsg as Snip20ComposableMsg,
 };
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
 pub static ADMIN: &[u8] =b"contract_pair_admin";
+pub static STATUS: &[u8] =b"contract_status";
+
+/// Circuit breaker of a contract. Withdrawals are never paused so LPs can always exit.
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ContractStatus {
+    Normal,
+    /// Swaps are rejected, liquidity can still be added and removed.
+    SwapsPaused,
+    /// Only withdrawals are accepted.
+    AllPaused,
+}
 
 pub fn apply_admin_guard(
     caller: HumanAddr,
@@ -59,4 +73,57 @@ pub fn set_admin_guard<S: Storage, A: Api, Q: Querier>(
         data: None,
     })
 
-}
\ No newline at end of file
+}
+
+pub fn store_status(storage: &mut impl Storage, status: &ContractStatus) -> StdResult<()> {
+    save(storage, STATUS, status)
+}
+
+pub fn load_status(storage: &impl Storage) -> StdResult<ContractStatus> {
+    let status = load(storage, STATUS)?.unwrap_or(ContractStatus::Normal);
+    Ok(status)
+}
+
+/// Fails unless swaps are allowed by the status of the contract.
+pub fn apply_swap_guard(storage: &impl Storage) -> StdResult<()> {
+    match load_status(storage)? {
+        ContractStatus::Normal => Ok(()),
+        _ => Err(StdError::generic_err("Swaps are paused.")),
+    }
+}
+
+/// Fails if only withdrawals are allowed by the status of the contract.
+pub fn apply_deposit_guard(storage: &impl Storage) -> StdResult<()> {
+    match load_status(storage)? {
+        ContractStatus::AllPaused => Err(StdError::generic_err("Deposits are paused.")),
+        _ => Ok(()),
+    }
+}
+
+/// Stores the status without checking the caller, for contracts which also accept it from
+/// another contract. Use `set_status_guard` otherwise.
+pub fn set_status<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    status: ContractStatus
+) -> StdResult<HandleResponse>{
+    store_status(&mut deps.storage, &status)?;
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+                log("action", "set_status"),
+                log("caller", env.message.sender),
+                log("status", format!("{:?}", status)),
+        ],
+        data: None,
+    })
+}
+
+pub fn set_status_guard<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    status: ContractStatus
+) -> StdResult<HandleResponse>{
+    apply_admin_guard(env.message.sender.clone(), &deps.storage)?;
+    set_status(deps, env, status)
+}
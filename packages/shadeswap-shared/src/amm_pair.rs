@@ -1,9 +1,10 @@
 use fadroma::{
-    scrt::{HumanAddr, StdResult, Api, CanonicalAddr},
+    scrt::{HumanAddr, StdError, StdResult, Api, CanonicalAddr, Uint128},
     scrt_addr::{Canonize, Humanize},
     scrt_link::ContractLink,
 };
 use crate::token_pair::TokenPair;
+use crate::admin::ContractStatus;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
@@ -38,15 +39,24 @@ impl Humanize<AMMPair<HumanAddr>> for AMMPair<CanonicalAddr> {
 pub struct AMMSettings<A> {
     pub lp_fee: Fee,
     pub shade_dao_fee: Fee,
-    pub shade_dao_address: ContractLink<A>
+    pub shade_dao_address: ContractLink<A>,
+    pub shade_dao_fee_mode: ShadeDaoFeeMode,
 }
 
 impl AMMSettings<HumanAddr> {
+    pub fn validate(&self) -> StdResult<()> {
+        if self.shade_dao_address.address.as_str().is_empty() {
+            return Err(StdError::generic_err("shade_dao_address can't be empty."));
+        }
+        validate_fees(self.lp_fee, self.shade_dao_fee)
+    }
+
     pub fn canonize(&self, api: &impl Api) -> StdResult<AMMSettings<CanonicalAddr>> {
         Ok(AMMSettings {
             lp_fee: self.lp_fee,
             shade_dao_fee: self.shade_dao_fee,
-            shade_dao_address: self.shade_dao_address.canonize(api)?
+            shade_dao_address: self.shade_dao_address.canonize(api)?,
+            shade_dao_fee_mode: self.shade_dao_fee_mode,
         })
     }
 }
@@ -56,19 +66,215 @@ impl AMMSettings<CanonicalAddr> {
         Ok(AMMSettings {
             lp_fee: self.lp_fee,
             shade_dao_fee: self.shade_dao_fee,
-            shade_dao_address: self.shade_dao_address.humanize(api)?
+            shade_dao_address: self.shade_dao_address.humanize(api)?,
+            shade_dao_fee_mode: self.shade_dao_fee_mode,
         })
     }
 }
 
+/// How pairs collect the DAO share of the swap fees.
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ShadeDaoFeeMode {
+    /// Sent to `shade_dao_address` in the offered token with every swap.
+    Transfer,
+    /// Left in the pool and collected by minting LP tokens to `shade_dao_address` when
+    /// liquidity changes, based on the growth of `sqrt(x * y)` since the last change.
+    MintLiquidity,
+}
+
+impl Default for ShadeDaoFeeMode {
+    fn default() -> Self {
+        ShadeDaoFeeMode::Transfer
+    }
+}
+
+/// Oracle an oracle pegged pair centers its curve on.
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Debug, Clone)]
+pub struct OracleSettings<A> {
+    /// Answers `msg::oracle::QueryMsg::GetPrice` with the price of token 0 in token 1.
+    pub contract: ContractLink<A>,
+    /// Largest deviation of the executed price of a swap from the oracle price, in basis points.
+    pub max_deviation: u16,
+    /// Seconds after its last update the oracle price is still traded against.
+    pub max_age: u64,
+}
+
+impl Canonize<OracleSettings<CanonicalAddr>> for OracleSettings<HumanAddr> {
+    fn canonize(&self, api: &impl Api) -> StdResult<OracleSettings<CanonicalAddr>> {
+        Ok(OracleSettings {
+            contract: self.contract.canonize(api)?,
+            max_deviation: self.max_deviation,
+            max_age: self.max_age,
+        })
+    }
+}
+
+impl Humanize<OracleSettings<HumanAddr>> for OracleSettings<CanonicalAddr> {
+    fn humanize(&self, api: &impl Api) -> StdResult<OracleSettings<HumanAddr>> {
+        Ok(OracleSettings {
+            contract: self.contract.humanize(api)?,
+            max_deviation: self.max_deviation,
+            max_age: self.max_age,
+        })
+    }
+}
+
+/// Largest fee discount, the whole fee.
+pub const MAX_FEE_DISCOUNT: u16 = 10_000;
+
+/// Swap fee discounts of a pair, traders get the highest discount of the tiers they reach.
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Debug, Clone)]
+pub struct FeeDiscounts<A> {
+    pub source: FeeDiscountSource<A>,
+    pub tiers: Vec<FeeDiscountTier>,
+    /// Router allowed to name the `trader` of the swaps it sends, every other swap gets
+    /// the discount of its sender.
+    pub router: Option<A>,
+}
+
+/// What the tiers of `FeeDiscounts` are compared against.
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum FeeDiscountSource<A> {
+    /// Balance of the trader in this SNIP20 token, read with the viewing key the trader
+    /// gave the pair through `SetDiscountViewingKey`.
+    TokenBalance { token: ContractLink<A> },
+    /// LP tokens the trader has staked in the staking contract of the pair.
+    Stake,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Debug, Clone)]
+pub struct FeeDiscountTier {
+    pub min_amount: Uint128,
+    /// Part of the LP and DAO fees waived, in basis points.
+    pub discount: u16,
+}
+
+impl<A> FeeDiscounts<A> {
+    /// Discount in basis points for a trader holding or staking `amount`.
+    pub fn discount_for(&self, amount: Uint128) -> u16 {
+        self.tiers
+            .iter()
+            .filter(|tier| tier.min_amount <= amount)
+            .map(|tier| tier.discount)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// State handed from a pair which is upgraded to its replacement.
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Debug, Clone)]
+pub struct PairMigration<A> {
+    /// The replaced pair, it sends its reserves once called back with `key`.
+    pub old_pair: ContractLink<A>,
+    pub key: String,
+    /// LP token of the replaced pair, the replacement becomes its minter.
+    pub lp_token: ContractLink<A>,
+    pub staking_contract: Option<ContractLink<A>>,
+    pub custom_fee: Option<CustomFee>,
+    /// A paused pair stays paused once replaced.
+    pub status: ContractStatus,
+    /// Traders register their discount viewing keys again with the replacement.
+    pub fee_discounts: Option<FeeDiscounts<A>>,
+    /// Size of the pool the DAO share of the fees is measured from.
+    pub root_k_last: Uint128,
+    pub locked_liquidity: Uint128,
+    pub price_accumulator: Option<PriceObservation>,
+    /// Observations of the TWAP still kept by the replaced pair, oldest first.
+    pub price_observations: Vec<PriceObservation>,
+}
+
+/// Cumulative prices of the pair at a given block time. The cumulative values wrap
+/// around on overflow, only the difference between two observations is meaningful.
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Debug, Clone)]
+pub struct PriceObservation {
+    pub block_time: u64,
+    pub price_0_cumulative: Uint128,
+    pub price_1_cumulative: Uint128,
+}
+
+/// Fees of a single pair which replace the ones from the factory `AMMSettings`.
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Clone, Copy, Debug)]
+pub struct CustomFee {
+    pub lp_fee: Fee,
+    pub shade_dao_fee: Fee,
+}
+
+impl CustomFee {
+    pub fn validate(&self) -> StdResult<()> {
+        validate_fees(self.lp_fee, self.shade_dao_fee)
+    }
+}
+
+/// Invariant used by a pair to price swaps and liquidity.
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum PoolType {
+    /// `x * y = k`
+    ConstantProduct,
+    /// Curve style invariant for pegged assets, `amp` is the amplification coefficient.
+    StableSwap { amp: u64 },
+    /// Liquidity is provided in price ranges between two ticks, `price = 1.0001^tick`.
+    /// The price of the pool starts at `initial_tick`.
+    ConcentratedLiquidity { tick_spacing: u16, initial_tick: i32 },
+    /// Weighted product `x^weight_0 * y^weight_1 = k`, the weights are percentages
+    /// of the pool value held in each token and add up to 100.
+    Weighted { weight_0: u8, weight_1: u8 },
+}
+
+impl Default for PoolType {
+    fn default() -> Self {
+        PoolType::ConstantProduct
+    }
+}
+
+/// Denominator of a fee in hundredths of a basis point.
+pub const FEE_PRECISION: u32 = 1_000_000;
+
+/// Ceiling of the LP and DAO fees charged together on a swap, 10%.
+pub const MAX_TOTAL_FEE: Fee = Fee { nom: 1, denom: 10 };
+
+/// Part `nom / denom` of the offered amount charged on a swap. Fractions of a basis point
+/// need a larger denominator, e.g. 0.025% is `Fee::new(25, 100_000)`.
 #[derive(Serialize, Deserialize, JsonSchema, PartialEq, Clone, Copy, Debug)]
 pub struct Fee {
-    pub nom: u8,
-    pub denom: u16,
+    pub nom: u32,
+    pub denom: u32,
 }
 
 impl Fee {
-    pub fn new(nom: u8, denom: u16) -> Self {
+    pub fn new(nom: u32, denom: u32) -> Self {
         Self { nom, denom }
     }
+
+    /// Fee in hundredths of a basis point, 0.3% is `Fee::from_bps_hundredths(3_000)`.
+    pub fn from_bps_hundredths(hundredths: u32) -> Self {
+        Self::new(hundredths, FEE_PRECISION)
+    }
+
+    pub fn validate(&self) -> StdResult<()> {
+        if self.denom == 0 {
+            return Err(StdError::generic_err("Fee denom can't be zero."));
+        }
+        if self.nom >= self.denom {
+            return Err(StdError::generic_err("Fee has to be less than 100%."));
+        }
+        Ok(())
+    }
+}
+
+/// The LP and DAO fees are both charged on the offer, together they can't exceed `MAX_TOTAL_FEE`.
+pub fn validate_fees(lp_fee: Fee, shade_dao_fee: Fee) -> StdResult<()> {
+    lp_fee.validate()?;
+    shade_dao_fee.validate()?;
+    let denom = lp_fee.denom as u128 * shade_dao_fee.denom as u128;
+    let nom = lp_fee.nom as u128 * shade_dao_fee.denom as u128 + shade_dao_fee.nom as u128 * lp_fee.denom as u128;
+    if nom * MAX_TOTAL_FEE.denom as u128 > MAX_TOTAL_FEE.nom as u128 * denom {
+        return Err(StdError::generic_err(format!(
+            "Combined fees can't be more than {}/{}.",
+            MAX_TOTAL_FEE.nom, MAX_TOTAL_FEE.denom
+        )));
+    }
+    Ok(())
 }
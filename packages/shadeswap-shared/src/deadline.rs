@@ -0,0 +1,36 @@
+use fadroma::scrt::{Env, StdError, StdResult};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Point after which a trade must no longer be executed.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Deadline {
+    /// Block time in seconds.
+    Time(u64),
+    /// Block height.
+    Height(u64),
+}
+
+impl Deadline {
+    pub fn is_expired(&self, env: &Env) -> bool {
+        match self {
+            Deadline::Time(time) => env.block.time > *time,
+            Deadline::Height(height) => env.block.height > *height,
+        }
+    }
+}
+
+/// Fails if a deadline was given and the current block is past it.
+pub fn assert_deadline(env: &Env, deadline: &Option<Deadline>) -> StdResult<()> {
+    if let Some(deadline) = deadline {
+        if deadline.is_expired(env) {
+            return Err(StdError::generic_err(format!(
+                "Trade deadline {:?} has passed.",
+                deadline
+            )));
+        }
+    }
+
+    Ok(())
+}
@@ -5,10 +5,12 @@ pub use token_type::*;
 pub use token_amount::*;
 pub use msg::*;
 pub use token_pair_amount::*;
+pub use deadline::*;
 pub mod token_pair;
 pub mod token_type;
 pub mod token_amount;
 pub mod token_pair_amount;
+pub mod deadline;
 pub mod amm_pair;
 pub mod admin;
 pub mod stake_contract;
@@ -6,6 +6,8 @@ use fadroma::{
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use crate::TokenType;
+use crate::deadline::Deadline;
+use crate::admin::ContractStatus;
 
 pub use crate::snip20_impl::msg as snip20;
 use crate::token_amount::TokenAmount;
@@ -44,7 +46,15 @@ pub mod router {
         SwapTokensForExact {
             paths: Vec<HumanAddr>,
             expected_return: Option<Uint128>,
-            recipient: Option<HumanAddr>
+            recipient: Option<HumanAddr>,
+            deadline: Option<Deadline>
+        },
+        SwapTokensForBestRoute {
+            target_token: TokenType<HumanAddr>,
+            max_hops: Option<u8>,
+            expected_return: Option<Uint128>,
+            recipient: Option<HumanAddr>,
+            deadline: Option<Deadline>
         },
     }
 
@@ -70,7 +80,18 @@ pub mod router {
             offer: TokenAmount<HumanAddr>,
             expected_return: Option<Uint128>,
             path: Vec<HumanAddr>,
-            recipient: Option<HumanAddr>
+            recipient: Option<HumanAddr>,
+            deadline: Option<Deadline>
+        },
+        /// Looks up every route to `target_token` through the factory's pairs
+        /// and swaps along the one with the best return.
+        SwapTokensForBestRoute {
+            offer: TokenAmount<HumanAddr>,
+            target_token: TokenType<HumanAddr>,
+            max_hops: Option<u8>,
+            expected_return: Option<Uint128>,
+            recipient: Option<HumanAddr>,
+            deadline: Option<Deadline>
         },
         SwapCallBack {
             last_token_out: TokenAmount<HumanAddr>,
@@ -79,18 +100,45 @@ pub mod router {
         RegisterSNIP20Token{
             token: HumanAddr,
             token_code_hash: String
+        },
+        /// Admin only, any status but `Normal` stops new swaps through the router.
+        SetRouterStatus {
+            status: ContractStatus
         }
     }
 
     #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
     #[serde(rename_all = "snake_case")]
     pub enum QueryMsg {
+        SimulateSwap {
+            offer: TokenAmount<HumanAddr>,
+            path: Vec<HumanAddr>,
+        },
+    }
+
+    /// Result of a single leg of a simulated swap.
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+    pub struct SwapHop {
+        pub pair: HumanAddr,
+        pub offer: TokenAmount<HumanAddr>,
+        pub return_amount: Uint128,
+        pub lp_fee_amount: Uint128,
+        pub shade_dao_fee_amount: Uint128,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+    #[serde(rename_all = "snake_case")]
+    pub enum QueryMsgResponse {
+        SimulateSwap {
+            hops: Vec<SwapHop>,
+            return_amount: Uint128,
+        },
     }
 }
 
 pub mod amm_pair {
     use super::*;
-    use crate::{amm_pair::AMMSettings, fadroma::HumanAddr, Pagination, TokenPair, stake_contract::StakingContractInit};
+    use crate::{amm_pair::{AMMSettings, CustomFee, FeeDiscounts, OracleSettings, PairMigration, PoolType}, fadroma::HumanAddr, Pagination, TokenPair, stake_contract::StakingContractInit};
     use schemars::JsonSchema;
     use serde::{Deserialize, Serialize};
 
@@ -128,7 +176,13 @@ pub mod amm_pair {
         pub callback: Option<Callback<HumanAddr>>,
         pub entropy: Binary,
         pub admin: Option<HumanAddr>,
-        pub staking_contract: Option<StakingContractInit>
+        pub staking_contract: Option<StakingContractInit>,
+        /// Defaults to `PoolType::ConstantProduct`.
+        pub pool_type: Option<PoolType>,
+        /// Centers a constant product curve on the price of this oracle.
+        pub oracle: Option<OracleSettings<HumanAddr>>,
+        /// Set when the pair replaces `migration.old_pair` instead of starting empty.
+        pub migration: Option<PairMigration<HumanAddr>>,
     }
     #[derive(Serialize, Deserialize, JsonSchema)]
     #[serde(rename_all = "snake_case")]
@@ -137,13 +191,49 @@ pub mod amm_pair {
             deposit: TokenPairAmount<HumanAddr>,
             slippage: Option<Decimal>,
         },
+        /// Provide liquidity with only one of the pair tokens. The optimal part of it
+        /// is swapped through the pool before the LP tokens are minted.
+        ZapIn {
+            /// The native token amount sent along with the message.
+            offer: TokenAmount<HumanAddr>,
+            min_lp_tokens: Option<Uint128>,
+        },
+        /// Concentrated liquidity pools only. Adds liquidity to the position of the sender
+        /// between the two ticks, the part of the deposit which isn't needed is refunded.
+        AddRangeLiquidity {
+            deposit: TokenPairAmount<HumanAddr>,
+            lower_tick: i32,
+            upper_tick: i32,
+            min_liquidity: Option<Uint128>,
+        },
+        /// Sends the tokens of `liquidity` together with the fees earned by the position.
+        RemoveRangeLiquidity {
+            lower_tick: i32,
+            upper_tick: i32,
+            liquidity: Uint128,
+        },
+        CollectRangeFees {
+            lower_tick: i32,
+            upper_tick: i32,
+        },
         SwapTokens {
             /// The token type to swap from.
             offer: TokenAmount<HumanAddr>,
             expected_return: Option<Uint128>,
             to: Option<HumanAddr>,
             router_link: Option<ContractLink<HumanAddr>>,
-            callback_signature: Option<Binary>
+            callback_signature: Option<Binary>,
+            deadline: Option<Deadline>,
+            /// Set by the router to the address which started the swap, whose fee discount applies.
+            trader: Option<HumanAddr>,
+        },
+        SwapTokensForExactOutput {
+            /// The native token amount sent along with the message.
+            offer: TokenAmount<HumanAddr>,
+            desired_output: Uint128,
+            max_input: Uint128,
+            to: Option<HumanAddr>,
+            deadline: Option<Deadline>,
         },
         // SNIP20 receiver interface
         Receive {
@@ -153,16 +243,33 @@ pub mod amm_pair {
         },
         // Sent by the LP token contract so that we can record its address.
         OnLpTokenInitAddr,
-        AddWhiteListAddress {
-            address: HumanAddr,
-        },
-        RemoveWhitelistAddresses {
-            addresses: Vec<HumanAddr>
-        },
+        /// Admin only, `None` removes all discounts.
+        SetFeeDiscounts { discounts: Option<FeeDiscounts<HumanAddr>> },
+        /// Viewing key of the sender for the discount token, so the pair can read their balance.
+        SetDiscountViewingKey { key: String },
         SetAMMPairAdmin {
             admin: HumanAddr
         },
+        /// Sent by the admin or the factory, removing liquidity is allowed in every status.
+        SetAMMPairStatus {
+            status: ContractStatus
+        },
         SetStakingContract { contract: ContractLink<HumanAddr> },
+        /// Sent by the factory, `None` goes back to the factory `AMMSettings` fees.
+        SetCustomFee { fee: Option<CustomFee> },
+        /// Sent by the factory, replaces the oracle of an oracle pegged pair.
+        SetOracle { oracle: OracleSettings<HumanAddr> },
+        /// Sent by the factory. Stops the pair and instantiates its replacement from `new_code`,
+        /// `callback` is passed on to the replacement.
+        MigrateTo {
+            new_code: ContractInstantiationInfo,
+            prng_seed: Binary,
+            entropy: Binary,
+            callback: Callback<HumanAddr>,
+        },
+        /// Sent by the replacement once it is instantiated, the reserves and the LP token
+        /// are handed over to it.
+        CompleteMigration { key: String },
     }
     #[derive(Serialize, Deserialize, JsonSchema)]
     #[serde(rename_all = "snake_case")]
@@ -171,10 +278,26 @@ pub mod amm_pair {
             expected_return: Option<Uint128>,
             to: Option<HumanAddr>,
             router_link: Option<ContractLink<HumanAddr>>,
-            callback_signature: Option<Binary>
+            callback_signature: Option<Binary>,
+            deadline: Option<Deadline>,
+            /// Set by the router to the address which started the swap, whose fee discount applies.
+            trader: Option<HumanAddr>,
+        },
+        SwapTokensForExactOutput {
+            desired_output: Uint128,
+            max_input: Uint128,
+            to: Option<HumanAddr>,
+            deadline: Option<Deadline>,
+        },
+        ZapIn {
+            min_lp_tokens: Option<Uint128>,
         },
         RemoveLiquidity {
             recipient: HumanAddr,
+            min_amount_0: Option<Uint128>,
+            min_amount_1: Option<Uint128>,
+            /// Withdraw everything as this token, the other side is swapped inside the pair.
+            withdraw_into: Option<TokenType<HumanAddr>>,
         },
     }
     #[derive(Serialize, Deserialize, JsonSchema)]
@@ -182,12 +305,20 @@ pub mod amm_pair {
     pub enum QueryMsg {
         GetPairInfo,
         GetTradeHistory { pagination: Pagination },
-        GetWhiteListAddress,
+        GetFeeDiscounts,
+        /// Discount in basis points `address` currently gets on swap fees.
+        GetFeeDiscount { address: HumanAddr },
         GetTradeCount,
         GetAdmin,
         GetStakingContract,
         GetClaimReward{time: u128, staker: HumanAddr},
-        GetEstimatedPrice { offer: TokenAmount<HumanAddr>}
+        GetEstimatedPrice { offer: TokenAmount<HumanAddr>},
+        SwapSimulation { offer: TokenAmount<HumanAddr> },
+        /// Time-weighted average price over the `window` seconds up to `time`, which
+        /// should be the current block time. Fails for a `time` before the last price update.
+        GetTwap { window: u64, time: u64 },
+        GetConcentratedPool,
+        GetPosition { owner: HumanAddr, lower_tick: i32, upper_tick: i32 },
     }
     #[derive(Serialize, Deserialize, JsonSchema)]
     #[serde(rename_all = "snake_case")]
@@ -199,13 +330,20 @@ pub mod amm_pair {
             amount_0: Uint128,
             amount_1: Uint128,
             total_liquidity: Uint128,
+            /// LP tokens locked forever in the pair on the first deposit.
+            locked_liquidity: Uint128,
+            pool_type: PoolType,
+            oracle: Option<OracleSettings<HumanAddr>>,
             contract_version: u32,
         },
         GetTradeHistory {
             data: Vec<TradeHistory>,
         },
-        GetWhiteListAddress {
-            addresses: Vec<HumanAddr>,
+        GetFeeDiscounts {
+            discounts: Option<FeeDiscounts<HumanAddr>>,
+        },
+        GetFeeDiscount {
+            discount: u16,
         },
         GetTradeCount {
             count: u64,
@@ -214,19 +352,52 @@ pub mod amm_pair {
             address: HumanAddr
         },
         GetClaimReward {
-            amount: Uint128,
+            rewards: Vec<TokenAmount<HumanAddr>>,
         },
         StakingContractInfo{
             staking_contract: ContractLink<HumanAddr>
         },
         EstimatedPrice {
             estimated_price: Uint128
-        }
+        },
+        SwapSimulation {
+            total_fee_amount: Uint128,
+            lp_fee_amount: Uint128,
+            shade_dao_fee_amount: Uint128,
+            result: SwapResult,
+            price: Uint128,
+        },
+        /// Average prices scaled by `TWAP_PRICE_PRECISION`. `price_0_average` is the
+        /// price of token 0 expressed in token 1, `price_1_average` the inverse.
+        GetTwap {
+            price_0_average: Uint128,
+            price_1_average: Uint128,
+            window_start: u64,
+            window_end: u64,
+        },
+        /// `sqrt_price` is the square root of the price of token 0 in token 1, scaled by 10^18.
+        GetConcentratedPool {
+            sqrt_price: Uint128,
+            tick: i32,
+            liquidity: Uint128,
+        },
+        /// Amounts the position would withdraw at the current price, fees include
+        /// everything earned so far.
+        GetPosition {
+            liquidity: Uint128,
+            amount_0: Uint128,
+            amount_1: Uint128,
+            fees_owed_0: Uint128,
+            fees_owed_1: Uint128,
+        },
     }
+
+    /// Scale of the fixed point prices used by the TWAP accumulators.
+    pub const TWAP_PRICE_PRECISION: u128 = 1_000_000_000_000_000_000;
 }
 
 pub mod factory {
-    use crate::{amm_pair::AMMSettings, fadroma::HumanAddr, Pagination, TokenPair};
+    use crate::{admin::ContractStatus, amm_pair::{AMMSettings, CustomFee, OracleSettings, PoolType}, fadroma::HumanAddr, Pagination, TokenPair};
     use fadroma::{Binary, ContractInstantiationInfo};
     use schemars::JsonSchema;
     use serde::{Deserialize, Serialize};
@@ -252,7 +423,9 @@ pub mod factory {
         CreateAMMPair {
             pair: TokenPair<HumanAddr>,
             entropy: Binary,
-            staking_contract: Option<StakingContractInit>
+            staking_contract: Option<StakingContractInit>,
+            pool_type: Option<PoolType>,
+            oracle: Option<OracleSettings<HumanAddr>>,
         },
         AddAMMPairs {
             amm_pairs: Vec<AMMPair<HumanAddr>>,
@@ -263,7 +436,36 @@ pub mod factory {
         },
         SetFactoryAdmin {
             admin: HumanAddr
-        }
+        },
+        /// Any status but `Normal` stops the creation of pairs.
+        SetFactoryStatus {
+            status: ContractStatus
+        },
+        /// Sends `status` to every registered pair.
+        SetPairsStatus {
+            status: ContractStatus
+        },
+        /// Overrides the fees of a single pair, `None` removes the override.
+        SetPairFee {
+            pair: TokenPair<HumanAddr>,
+            fee: Option<CustomFee>,
+        },
+        /// Replaces the oracle of an oracle pegged pair.
+        SetPairOracle {
+            pair: TokenPair<HumanAddr>,
+            oracle: OracleSettings<HumanAddr>,
+        },
+        /// Moves the pairs to `new_code`. Each pair is stopped and hands its reserves and
+        /// LP token over to a replacement, which takes its place in the factory.
+        UpgradePairs {
+            pairs: Vec<TokenPair<HumanAddr>>,
+            new_code: ContractInstantiationInfo,
+        },
+        /// Sent by the replacement of a pair which is upgraded.
+        RegisterUpgradedAMMPair {
+            pair: TokenPair<HumanAddr>,
+            signature: Binary,
+        },
     }
 
     #[derive(Serialize, Deserialize, Debug, JsonSchema, PartialEq)]
@@ -300,6 +502,30 @@ pub mod factory {
     }
 }
 
+pub mod oracle {
+    use super::*;
+    use schemars::JsonSchema;
+    use serde::{Deserialize, Serialize};
+
+    /// Scale of the prices returned by oracles, the same as for the pair TWAP.
+    pub const ORACLE_PRICE_PRECISION: u128 = 1_000_000_000_000_000_000;
+
+    /// Queries an oracle contract has to answer to back an oracle pegged pair.
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+    #[serde(rename_all = "snake_case")]
+    pub enum QueryMsg {
+        /// Price of token 0 of the pair expressed in token 1, scaled by `ORACLE_PRICE_PRECISION`.
+        GetPrice {},
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+    #[serde(rename_all = "snake_case")]
+    pub enum QueryResponse {
+        /// `last_updated` is the block time the price was set at.
+        GetPrice { price: Uint128, last_updated: u64 },
+    }
+}
+
 pub mod staking {
     use super::*;
     use schemars::JsonSchema;
@@ -316,13 +542,61 @@ pub mod staking {
     #[serde(rename_all = "snake_case")]
     pub enum HandleMsg {
         ClaimRewards {}, 
-        Stake {   
+        /// Sends `amount` of the staked LP tokens of the sender back to it. Its rewards stay
+        /// claimable through `ClaimRewards`.
+        Unstake {
+            amount: Uint128
+        },  
+        /// Sent by the pair, LP tokens of `lp_token` can be staked from then on.
+        SetLPToken {
+            lp_token: ContractLink<HumanAddr>
+        },
+        /// Sent by the pair when it is upgraded, the replacement takes over staking.
+        SetStakingAdmin {
+            admin: ContractLink<HumanAddr>
+        },
+        /// Sent by the admin of the pair. Adds a stream of the SNIP20 `token` next to the existing
+        /// ones, it pays nothing until it is funded through `InvokeMsg::FundRewardPeriod`.
+        AddRewardStream {
+            token: TokenType<HumanAddr>,
+            daily_reward_amount: Uint128,
+            start: Option<u64>,
+            end: Option<u64>,
+        },
+        // SNIP20 receiver interface
+        Receive {
             from: HumanAddr,
+            msg: Option<Binary>,
             amount: Uint128,
         },
-        Unstake {
-            address: HumanAddr
-        },  
+        /// Sent by the admin of the pair, `None` removes the boosts of new locks.
+        SetLockTiers { tiers: Option<Vec<LockTier>> },
+        /// Locks the stake of the sender for `duration` seconds from now, boosting its rewards
+        /// by the multiplier of the longest tier it covers. A running lock can only be extended.
+        Lock { duration: u64 },
+        /// Drops the boost of an expired lock, can be sent by anyone.
+        ExpireLock { staker: HumanAddr },
+    }
+
+    /// Locks of at least `duration` seconds earn rewards `multiplier` times as fast.
+    #[derive(Serialize, Deserialize, Clone, Debug, JsonSchema, PartialEq)]
+    pub struct LockTier {
+        pub duration: u64,
+        /// In basis points, 10000 is no boost.
+        pub multiplier: u32,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, JsonSchema, PartialEq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum InvokeMsg {
+        /// Sent by the admin of the pair together with reward tokens of an existing stream.
+        /// The sent amount and what is left of the current period are paid out evenly over
+        /// `duration` seconds, the stream stops afterwards.
+        FundRewardPeriod {
+            duration: u64,
+        },
+        /// Sent with LP tokens of the pair, stakes them for the sender.
+        Stake {},
     }
 
     #[derive(Serialize, Deserialize, Debug, JsonSchema, PartialEq)]
@@ -330,7 +604,21 @@ pub mod staking {
     pub enum QueryMsg {
         GetStakers {},
         GetClaimReward {time: u128, staker: HumanAddr},
-        GetContractOwner {}
+        GetStakedAmount { staker: HumanAddr },
+        GetContractOwner {},
+        /// `time` is the block time in seconds the budgets are computed at.
+        GetRewardPeriods { time: u128 },
+        GetLockTiers {},
+        GetStakerLock { staker: HumanAddr },
+    }
+
+    #[derive(Serialize, Deserialize, Debug, JsonSchema, PartialEq)]
+    pub struct RewardPeriod {
+        pub token: TokenType<HumanAddr>,
+        pub daily_reward_amount: Uint128,
+        pub remaining_budget: Uint128,
+        pub start: u64,
+        pub end: Option<u64>,
     }
 
     #[derive(Serialize, Deserialize, Debug, JsonSchema, PartialEq)]
@@ -339,12 +627,30 @@ pub mod staking {
         Stakers {
             stakers: Vec<HumanAddr>
         },
+        /// One entry per reward stream.
         ClaimReward {
+            rewards: Vec<TokenAmount<HumanAddr>>
+        },
+        StakedAmount {
             amount: Uint128
         },
         ContractOwner {
             address: HumanAddr
+        },
+        /// One entry per reward stream.
+        RewardPeriods {
+            periods: Vec<RewardPeriod>
+        },
+        LockTiers {
+            tiers: Option<Vec<LockTier>>
+        },
+        /// `weight` is the staked amount boosted by `multiplier`, rewards are paid per weight.
+        StakerLock {
+            amount: Uint128,
+            weight: Uint128,
+            multiplier: u32,
+            lock_end: Option<u64>,
         }
     }
 
-}
\ No newline at end of file
+}
\ No newline at end of file
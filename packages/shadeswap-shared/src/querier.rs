@@ -1,6 +1,6 @@
 use fadroma::{
     scrt::{
-        from_binary, from_slice, to_binary, Coin, Empty,
+        from_binary, from_slice, to_binary, Binary, Coin, Empty,
         HumanAddr, Querier, QuerierResult, QueryRequest, StdResult, SystemError, Uint128, WasmQuery,
         secret_toolkit::snip20::{Balance, TokenInfo}
     },
@@ -9,6 +9,7 @@ use fadroma::{
 };
 
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use crate::msg::oracle::{QueryMsg as OracleQueryMsg, QueryResponse as OracleQueryResponse};
 
 // Redefine here, so we can deserialize
 #[derive(Serialize, Deserialize)]
@@ -41,10 +42,15 @@ impl<C: DeserializeOwned> MockQuerier<C> {
     pub fn new(balances: &[(&HumanAddr, &[Coin])], tokens: Vec<MockContractInstance>) -> Self {
         MockQuerier {
             std_mock_querier: StdMockQuerier::new(balances),
-            wasm: InternalWasmQuerier { tokens },
+            wasm: InternalWasmQuerier { tokens, oracles: vec![] },
         }
     }
 
+    /// Answer price queries sent to `oracle.instance` from now on.
+    pub fn add_oracle(&mut self, oracle: MockOracle) {
+        self.wasm.oracles.push(oracle);
+    }
+
     /// Subtract amount from balance displayed after spending it.
     pub fn sub_balance(&mut self, amount: Uint128, address: &HumanAddr) -> StdResult<()> {
         for mut token in &mut self.wasm.tokens {
@@ -83,6 +89,7 @@ impl<C: DeserializeOwned> MockQuerier<C> {
 
 pub struct InternalWasmQuerier {
     pub tokens: Vec<MockContractInstance>,
+    pub oracles: Vec<MockOracle>,
 }
 
 pub struct MockContractInstance {
@@ -91,6 +98,28 @@ pub struct MockContractInstance {
     pub token_supply: Uint128,
 }
 
+/// Oracle contract which always reports `price`, for pairs in oracle pegged mode.
+pub struct MockOracle {
+    pub instance: ContractLink<HumanAddr>,
+    pub price: Uint128,
+    pub last_updated: u64,
+}
+
+impl MockOracle {
+    pub fn query(&self, msg: &Binary) -> QuerierResult {
+        match from_binary(msg) {
+            Ok(OracleQueryMsg::GetPrice {}) => Ok(to_binary(&OracleQueryResponse::GetPrice {
+                price: self.price,
+                last_updated: self.last_updated,
+            })),
+            Err(e) => Err(SystemError::InvalidRequest {
+                error: format!("Parsing oracle query: {}", e),
+                request: msg.clone(),
+            }),
+        }
+    }
+}
+
 impl InternalWasmQuerier {
     fn query(&self, request: &WasmQuery) -> QuerierResult {
         match request {
@@ -99,6 +128,12 @@ impl InternalWasmQuerier {
                 contract_addr,
                 msg,
             } => {
+                for oracle in &self.oracles {
+                    if &oracle.instance.address == contract_addr {
+                        return oracle.query(msg);
+                    }
+                }
+
                 let msg: QueryMsg = from_binary(&msg).unwrap();
 
                 for token in &self.tokens {